@@ -30,6 +30,14 @@ impl App {
         self.get_arg_flag("-s") || self.get_arg_flag("stop")
     }
 
+    pub fn reload(&self) -> bool {
+        self.get_arg_flag("-r") || self.get_arg_flag("reload")
+    }
+
+    pub fn reopen(&self) -> bool {
+        self.get_arg_flag("reopen")
+    }
+
     pub fn test(&self) -> bool {
         self.get_arg_flag("-t") || self.get_arg_flag("test")
     }
@@ -78,6 +86,8 @@ USAGE:
 FLAGS:
     -d, detach          Running in the background
     -h, help            Print help information
+    -r, reload          Reload the configuration of the daemon
+        reopen          Reopen the log files of the daemon
     -s, stop            Stop the daemon
     -t, test            Test the config file for error
     -v, version         Print version number
@@ -0,0 +1,498 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use flate2::{Compression, Crc};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use crate::cidr;
+use crate::config::ServerConfig;
+use crate::log;
+
+// Archives of a directory tree, written while they are sent
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Zip,
+    TarGz
+}
+
+// Without zip64 offsets are 32 bits, this leaves room for headers and incompressible data
+const ZIP_MAX: u64 = 0xF000_0000;
+const ZIP_ENTRIES: usize = 0xFFFF;
+
+// Largest size of a ustar header, 11 octal digits
+const TAR_MAX: u64 = 0o77777777777;
+
+impl Kind {
+
+    pub fn new(value: &str) -> Result<Kind, String> {
+        match value {
+            "zip" => Ok(Kind::Zip),
+            "tar.gz" => Ok(Kind::TarGz),
+            _ => Err(format!("Invalid archive format '{}', expected zip or tar.gz", value))
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Zip => "zip",
+            Kind::TarGz => "tar.gz"
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Kind::Zip => "application/zip",
+            Kind::TarGz => "application/gzip"
+        }
+    }
+
+    pub fn write<W: Write>(self, entries: &[Entry], out: W) -> io::Result<()> {
+        match self {
+            Kind::Zip => zip(entries, out),
+            Kind::TarGz => tar_gz(entries, out)
+        }
+    }
+
+}
+
+// A file or directory of the archive
+#[derive(Debug)]
+pub struct Entry {
+    path: PathBuf,
+    // Relative to the archived directory, directories end with '/'
+    name: String,
+    dir: bool,
+    size: u64,
+    modified: Option<SystemTime>
+}
+
+#[derive(Debug)]
+pub enum Error {
+    TooLarge,
+    Io(io::Error)
+}
+
+// Everything below `dir` the client may see: no hidden names, nothing `access` denies them.
+// `url` is the path of `dir` in requests, ending with '/'
+pub fn collect(
+    dir: &Path,
+    url: &str,
+    config: &ServerConfig,
+    ip: Option<IpAddr>,
+    kind: Kind,
+    max: u64
+) -> Result<Vec<Entry>, Error> {
+
+    let max = match kind {
+        Kind::Zip => max.min(ZIP_MAX),
+        Kind::TarGz => max
+    };
+    let mut entries = vec![];
+    let mut total = 0;
+    // Directories still to read, with their name in the archive
+    let mut pending = vec![(dir.to_path_buf(), String::new())];
+
+    while let Some((path, prefix)) = pending.pop() {
+
+        let mut names = vec![];
+        for entry in fs::read_dir(&path).map_err(Error::Io)? {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue
+            };
+            match entry.file_name().to_str() {
+                Some(name) if !name.starts_with('.') => names.push(name.to_string()),
+                _ => continue
+            }
+        }
+        names.sort();
+
+        for name in names.into_iter().rev() {
+            if let Some(ip) = ip {
+                if !cidr::allowed(config.access(&format!("{}{}{}", url, prefix, name)), &ip) {
+                    continue;
+                }
+            }
+            let path = path.join(&name);
+            let meta = match fs::metadata(&path) {
+                Ok(meta) => meta,
+                Err(_) => continue
+            };
+            if meta.is_dir() {
+                // Links to directories could loop
+                if fs::symlink_metadata(&path).map_or(true, |meta| meta.file_type().is_symlink()) {
+                    continue;
+                }
+                let name = format!("{}{}/", prefix, name);
+                pending.push((path.clone(), name.clone()));
+                entries.push(Entry { path, name, dir: true, size: 0, modified: meta.modified().ok() });
+            }else if meta.is_file() {
+                total += meta.len();
+                if total > max || meta.len() > TAR_MAX {
+                    return Err(Error::TooLarge);
+                }
+                let name = format!("{}{}", prefix, name);
+                entries.push(Entry { path, name, dir: false, size: meta.len(), modified: meta.modified().ok() });
+            }
+        }
+
+        if kind == Kind::Zip && entries.len() >= ZIP_ENTRIES {
+            return Err(Error::TooLarge);
+        }
+
+    }
+
+    // Parents before their children, in name order
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+
+}
+
+// Files that went away are left out, headers are only written once a file is open
+fn open(entry: &Entry) -> Option<File> {
+    match File::open(&entry.path) {
+        Ok(file) => Some(file),
+        Err(err) => {
+            log::warn(format_args!("Archiving \"{}\" failed ({})", entry.path.display(), err));
+            None
+        }
+    }
+}
+
+// Copy at most `size` bytes, a growing file can't outgrow its header
+fn copy<W: Write>(file: File, size: u64, out: &mut W, crc: &mut Crc) -> io::Result<u64> {
+    let mut file = file.take(size);
+    let mut buf = vec![0; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(copied);
+        }
+        crc.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+}
+
+// Bytes written so far, zip records their offsets
+struct Counter<W: Write> {
+    inner: W,
+    count: u64
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// MS-DOS time and date, local time from 1980 on
+fn dos_time(time: Option<SystemTime>) -> (u16, u16) {
+    let time: DateTime<Local> = DateTime::from(time.unwrap_or(UNIX_EPOCH));
+    if time.year() < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    (
+        (time.hour() << 11 | time.minute() << 5 | (time.second() / 2)) as u16,
+        (((time.year() - 1980).min(127) as u32) << 9 | time.month() << 5 | time.day()) as u16
+    )
+}
+
+// Sizes and CRC follow each file in a data descriptor, so nothing is read twice
+fn zip<W: Write>(entries: &[Entry], out: W) -> io::Result<()> {
+
+    let mut out = Counter { inner: out, count: 0 };
+    let mut central = vec![];
+    let mut count: u16 = 0;
+
+    for entry in entries {
+
+        let file = if entry.dir {
+            None
+        }else {
+            match open(entry) {
+                Some(file) => Some(file),
+                None => continue
+            }
+        };
+
+        let offset = out.count as u32;
+        let (time, date) = dos_time(entry.modified);
+        let method: u16 = if entry.dir { 0 } else { 8 };
+        // UTF-8 names, files have a data descriptor
+        let flags: u16 = if entry.dir { 1 << 11 } else { 1 << 3 | 1 << 11 };
+
+        let mut local = vec![];
+        local.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        local.extend_from_slice(&20u16.to_le_bytes());
+        local.extend_from_slice(&flags.to_le_bytes());
+        local.extend_from_slice(&method.to_le_bytes());
+        local.extend_from_slice(&time.to_le_bytes());
+        local.extend_from_slice(&date.to_le_bytes());
+        // CRC and sizes are in the descriptor
+        local.extend_from_slice(&[0; 12]);
+        local.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes());
+        local.extend_from_slice(entry.name.as_bytes());
+        out.write_all(&local)?;
+
+        let mut crc = Crc::new();
+        let (mut compressed, mut size) = (0, 0);
+        if let Some(file) = file {
+            let start = out.count;
+            let mut encoder = DeflateEncoder::new(&mut out, Compression::default());
+            size = copy(file, entry.size, &mut encoder, &mut crc)?;
+            encoder.finish()?;
+            compressed = out.count - start;
+            let mut descriptor = vec![];
+            descriptor.extend_from_slice(&0x08074b50u32.to_le_bytes());
+            descriptor.extend_from_slice(&crc.sum().to_le_bytes());
+            descriptor.extend_from_slice(&(compressed as u32).to_le_bytes());
+            descriptor.extend_from_slice(&(size as u32).to_le_bytes());
+            out.write_all(&descriptor)?;
+        }
+
+        // Made by Unix, so the permissions are kept
+        let mode: u32 = if entry.dir { 0o40755 } else { 0o100644 };
+        let attributes = mode << 16 | if entry.dir { 0x10 } else { 0 };
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&(3u16 << 8 | 20).to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&flags.to_le_bytes());
+        central.extend_from_slice(&method.to_le_bytes());
+        central.extend_from_slice(&time.to_le_bytes());
+        central.extend_from_slice(&date.to_le_bytes());
+        central.extend_from_slice(&crc.sum().to_le_bytes());
+        central.extend_from_slice(&(compressed as u32).to_le_bytes());
+        central.extend_from_slice(&(size as u32).to_le_bytes());
+        central.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        // Extra field, comment, disk, internal attributes
+        central.extend_from_slice(&[0; 8]);
+        central.extend_from_slice(&attributes.to_le_bytes());
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(entry.name.as_bytes());
+        count += 1;
+
+    }
+
+    let start = out.count as u32;
+    out.write_all(&central)?;
+
+    let mut end = vec![];
+    end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    end.extend_from_slice(&[0; 4]);
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    end.extend_from_slice(&start.to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    out.write_all(&end)?;
+    out.flush()
+
+}
+
+// ustar header, numbers are octal text
+fn tar_header(name: &[u8], kind: u8, size: u64, mtime: u64, mode: u32) -> [u8; 512] {
+
+    let mut header = [0; 512];
+    let mut field = |at: usize, value: &[u8]| header[at..at + value.len()].copy_from_slice(value);
+
+    field(0, &name[..name.len().min(100)]);
+    field(100, format!("{:07o}\0", mode).as_bytes());
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, format!("{:011o}\0", mtime.min(TAR_MAX)).as_bytes());
+    // Counted as spaces
+    field(148, b"        ");
+    field(156, &[kind]);
+    field(257, b"ustar\0");
+    field(263, b"00");
+
+    let sum: u32 = header.iter().map(|b| *b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+
+}
+
+// Up to the next 512 byte block
+fn tar_pad<W: Write>(out: &mut W, size: u64) -> io::Result<()> {
+    let rest = (512 - size % 512) % 512;
+    out.write_all(&vec![0; rest as usize])
+}
+
+// Names over 100 bytes go in a pax header ahead of the entry
+fn pax_path(name: &str) -> Vec<u8> {
+    // The length counts its own digits
+    let rest = format!(" path={}\n", name).len();
+    let mut len = rest + 1;
+    while (rest + len.to_string().len()) != len {
+        len = rest + len.to_string().len();
+    }
+    format!("{} path={}\n", len, name).into_bytes()
+}
+
+fn tar_gz<W: Write>(entries: &[Entry], out: W) -> io::Result<()> {
+
+    let mut out = GzEncoder::new(out, Compression::default());
+
+    for entry in entries {
+
+        let file = if entry.dir {
+            None
+        }else {
+            match open(entry) {
+                Some(file) => Some(file),
+                None => continue
+            }
+        };
+        let mtime = entry.modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs());
+
+        if entry.name.len() > 100 {
+            let pax = pax_path(&entry.name);
+            out.write_all(&tar_header(b"././@PaxHeader", b'x', pax.len() as u64, mtime, 0o644))?;
+            out.write_all(&pax)?;
+            tar_pad(&mut out, pax.len() as u64)?;
+        }
+
+        match file {
+            Some(file) => {
+                out.write_all(&tar_header(entry.name.as_bytes(), b'0', entry.size, mtime, 0o644))?;
+                let copied = copy(file, entry.size, &mut out, &mut Crc::new())?;
+                // A file that shrank is filled up to the size in its header
+                out.write_all(&vec![0; (entry.size - copied) as usize])?;
+                tar_pad(&mut out, entry.size)?;
+            },
+            None => {
+                out.write_all(&tar_header(entry.name.as_bytes(), b'5', 0, mtime, 0o755))?;
+            }
+        }
+
+    }
+
+    // End of archive
+    out.write_all(&[0; 1024])?;
+    out.finish()?.flush()
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use flate2::read::{DeflateDecoder, GzDecoder};
+    use crate::archive::{self, Error, Kind};
+    use crate::config::ServerConfig;
+
+    fn tree(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("see-archive-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub/empty")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join("a.txt"), "hello hello hello").unwrap();
+        fs::write(root.join("sub/b.txt"), "b").unwrap();
+        fs::write(root.join(".env"), "secret").unwrap();
+        fs::write(root.join(".git/config"), "secret").unwrap();
+        fs::write(root.join(format!("sub/{}.txt", "x".repeat(120))), "long").unwrap();
+        root
+    }
+
+    fn u16_at(data: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([data[at], data[at + 1]]) as usize
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> usize {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+    }
+
+    #[test]
+    fn test_collect() {
+        let root = tree("collect");
+        let config = ServerConfig::default();
+        let entries = archive::collect(&root, "/", &config, None, Kind::Zip, 1024).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names[..4], ["a.txt", "sub/", "sub/b.txt", "sub/empty/"]);
+        assert_eq!(names.len(), 5);
+
+        match archive::collect(&root, "/", &config, None, Kind::TarGz, 10) {
+            Err(Error::TooLarge) => {},
+            other => panic!("{:?}", other)
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_zip() {
+        let root = tree("zip");
+        let entries = archive::collect(&root, "/", &ServerConfig::default(), None, Kind::Zip, 1024).unwrap();
+        let mut data = vec![];
+        Kind::Zip.write(&entries, &mut data).unwrap();
+
+        // End of central directory
+        let end = data.len() - 22;
+        assert_eq!(u32_at(&data, end), 0x06054b50);
+        assert_eq!(u16_at(&data, end + 10), 5);
+        let central = u32_at(&data, end + 16);
+        assert_eq!(u32_at(&data, end + 12), end - central);
+
+        // First central record leads to a.txt
+        assert_eq!(u32_at(&data, central), 0x02014b50);
+        assert_eq!(&data[central + 46..central + 51], b"a.txt");
+        let compressed = u32_at(&data, central + 20);
+        assert_eq!(u32_at(&data, central + 24), 17);
+        let local = u32_at(&data, central + 42);
+        assert_eq!(u32_at(&data, local), 0x04034b50);
+        let start = local + 30 + u16_at(&data, local + 26);
+        let mut text = String::new();
+        DeflateDecoder::new(&data[start..start + compressed]).read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hello hello hello");
+        assert_eq!(u32_at(&data, start + compressed), 0x08074b50);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tar_gz() {
+        let root = tree("tar");
+        let entries = archive::collect(&root, "/", &ServerConfig::default(), None, Kind::TarGz, 1024).unwrap();
+        let mut data = vec![];
+        Kind::TarGz.write(&entries, &mut data).unwrap();
+        let mut tar = vec![];
+        GzDecoder::new(&data[..]).read_to_end(&mut tar).unwrap();
+
+        assert_eq!(&tar[..6], b"a.txt\0");
+        assert_eq!(&tar[124..136], b"00000000021\0");
+        assert_eq!(&tar[257..263], b"ustar\0");
+        let sum: u32 = tar[..512].iter().enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { b' ' as u32 } else { *b as u32 })
+            .sum();
+        assert_eq!(&tar[148..156], format!("{:06o}\0 ", sum).as_bytes());
+        assert_eq!(&tar[512..529], b"hello hello hello");
+
+        // The long name comes in a pax header
+        let name = format!("sub/{}.txt", "x".repeat(120));
+        let record = format!("{} path={}\n", 10 + name.len(), name);
+        assert_eq!(record.len(), 10 + name.len());
+        let text = String::from_utf8_lossy(&tar);
+        assert!(text.contains(&record));
+        assert!(tar.len().is_multiple_of(512));
+        assert!(tar.ends_with(&[0; 1024]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+}
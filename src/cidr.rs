@@ -0,0 +1,192 @@
+use std::fmt;
+use std::net::IpAddr;
+
+// A network such as 10.0.0.0/8 or a single address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8
+}
+
+impl Cidr {
+
+    pub fn new(value: &str) -> Result<Cidr, String> {
+
+        let (addr, prefix) = match value.find('/') {
+            Some(i) => (&value[..i], Some(&value[i + 1..])),
+            None => (value, None)
+        };
+
+        let addr: IpAddr = addr.parse()
+            .map_err(|_| format!("Invalid address '{}'", value))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(n) if n <= max => n,
+                _ => return Err(format!("Invalid prefix length in '{}'", value))
+            },
+            None => max
+        };
+
+        Ok(Cidr { addr, prefix })
+
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+
+        // Clients of a dual stack socket show up as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => *ip
+            },
+            _ => *ip
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                matches(u32::from(net) as u128, u32::from(ip) as u128, 32, self.prefix)
+            },
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                matches(u128::from(net), u128::from(ip), 128, self.prefix)
+            },
+            _ => false
+        }
+
+    }
+
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+fn matches(net: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    let shift = u32::from(bits - prefix);
+    net.checked_shr(shift).unwrap_or(0) == ip.checked_shr(shift).unwrap_or(0)
+}
+
+// One line of an allow / deny list, `None` stands for all addresses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    Allow(Option<Cidr>),
+    Deny(Option<Cidr>)
+}
+
+impl Rule {
+
+    // `allow` or `deny` followed by an address, a network or `all`
+    pub fn new(kind: &str, value: &str) -> Result<Rule, String> {
+        let net = match value {
+            "all" => None,
+            _ => Some(Cidr::new(value)?)
+        };
+        match kind {
+            "allow" => Ok(Rule::Allow(net)),
+            "deny" => Ok(Rule::Deny(net)),
+            _ => Err(format!("Invalid access rule '{}', expected allow or deny", kind))
+        }
+    }
+
+}
+
+// The first matching rule decides, allowed when none does
+pub fn allowed(rules: &[Rule], ip: &IpAddr) -> bool {
+    for rule in rules {
+        match rule {
+            Rule::Allow(net) if net.is_none_or(|net| net.contains(ip)) => return true,
+            Rule::Deny(net) if net.is_none_or(|net| net.contains(ip)) => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+// Client behind trusted proxies: the last address of X-Forwarded-For
+// that is not one of them, proxies append the address they received from
+pub fn forwarded(peer: IpAddr, header: &str, trusted: &[Cidr]) -> IpAddr {
+    let mut client = peer;
+    for hop in header.rsplit(',') {
+        if !trusted.iter().any(|net| net.contains(&client)) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break
+        }
+    }
+    client
+}
+
+// Default allowlist, this machine only
+pub fn loopback() -> Vec<Cidr> {
+    vec![
+        Cidr::new("127.0.0.0/8").unwrap(),
+        Cidr::new("::1").unwrap()
+    ]
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::IpAddr;
+    use crate::cidr::{self, Cidr, Rule};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let net = Cidr::new("10.1.0.0/16").unwrap();
+        assert!(net.contains(&ip("10.1.200.3")));
+        assert!(!net.contains(&ip("10.2.0.1")));
+        assert!(net.contains(&ip("::ffff:10.1.0.1")));
+        assert!(!net.contains(&ip("::1")));
+
+        assert!(Cidr::new("0.0.0.0/0").unwrap().contains(&ip("8.8.8.8")));
+        assert!(Cidr::new("192.168.1.5").unwrap().contains(&ip("192.168.1.5")));
+        assert!(!Cidr::new("192.168.1.5").unwrap().contains(&ip("192.168.1.6")));
+
+        let net = Cidr::new("fd00::/8").unwrap();
+        assert!(net.contains(&ip("fd12::1")));
+        assert!(!net.contains(&ip("fe80::1")));
+        assert!(Cidr::new("::/0").unwrap().contains(&ip("fe80::1")));
+
+        assert!(Cidr::new("10.0.0.0/33").is_err());
+        assert!(Cidr::new("localhost").is_err());
+        assert_eq!(Cidr::new("10.0.0.1").unwrap().to_string(), "10.0.0.1/32");
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules = [
+            Rule::new("deny", "192.168.1.1").unwrap(),
+            Rule::new("allow", "192.168.1.0/24").unwrap(),
+            Rule::new("allow", "2001:db8::/32").unwrap(),
+            Rule::new("deny", "all").unwrap()
+        ];
+        assert!(!cidr::allowed(&rules, &ip("192.168.1.1")));
+        assert!(cidr::allowed(&rules, &ip("192.168.1.2")));
+        assert!(cidr::allowed(&rules, &ip("2001:db8::1")));
+        assert!(!cidr::allowed(&rules, &ip("10.0.0.1")));
+        assert!(cidr::allowed(&[], &ip("10.0.0.1")));
+        assert!(Rule::new("permit", "all").is_err());
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let trusted = [Cidr::new("10.0.0.0/8").unwrap()];
+        // Straight from the client, the header is not believed
+        assert_eq!(cidr::forwarded(ip("1.2.3.4"), "5.6.7.8", &trusted), ip("1.2.3.4"));
+        assert_eq!(cidr::forwarded(ip("10.0.0.1"), "5.6.7.8", &trusted), ip("5.6.7.8"));
+        // A spoofed first entry stays hidden behind the real client
+        assert_eq!(cidr::forwarded(ip("10.0.0.1"), "9.9.9.9, 5.6.7.8, 10.0.0.2", &trusted), ip("5.6.7.8"));
+        assert_eq!(cidr::forwarded(ip("10.0.0.1"), "garbage", &trusted), ip("10.0.0.1"));
+    }
+
+}
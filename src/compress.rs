@@ -5,6 +5,8 @@ use flate2::Compression;
 use flate2::write::GzEncoder;
 use flate2::write::DeflateEncoder;
 use std::io::prelude::*;
+use std::fs::File;
+use std::io;
 
 
 pub fn gzip(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
@@ -31,3 +33,13 @@ pub fn deflate(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
 }
 
 
+// Gzip the file at `from` into a new file at `to`
+pub fn gzip_file(from: &str, to: &str) -> Result<(), std::io::Error> {
+
+    let mut input = File::open(from)?;
+    let mut e = GzEncoder::new(File::create(to)?, Compression::default());
+
+    io::copy(&mut input, &mut e)?;
+    e.finish()?.sync_all()
+
+}
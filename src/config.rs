@@ -3,14 +3,33 @@
 extern crate yaml_rust;
 extern crate base64;
 
+use std::env;
 use std::fs;
 use std::sync::Arc;
 use std::result::Result;
+use std::time::Duration;
 use std::collections::HashMap;
-use crate::log::Log;
+use crate::log::{self, Log, Format, Level, Overflow, Target};
 use base64::encode;
 use crate::fill_path;
-use yaml_rust::{YamlLoader};
+use crate::archive;
+use crate::webdav;
+use crate::cidr::{self, Cidr, Rule};
+use crate::limit::{Key, Rate};
+use crate::listing::{Order, Sort};
+use crate::template::Template;
+use yaml_rust::{Yaml, YamlLoader};
+
+// Process-wide options
+#[derive(Debug)]
+pub struct GlobalConfig {
+    pub worker_threads: usize,
+    pub worker_queue: usize,
+    // How long requests in flight may take to finish when stopping
+    pub shutdown_timeout: Option<Duration>,
+    pub error_log: Target,
+    pub log_level: Level
+}
 
 // Configuration of each service
 #[derive(Debug, Default)]
@@ -21,19 +40,54 @@ pub struct ServerConfig {
     pub compress: Option<Compress>,
     pub directory: Option<DirectoryOption>,
     pub index: Option<String>,
+    // .md files are rendered, ?raw sends them as they are
+    pub markdown: bool,
+    pub upload: Option<Upload>,
+    pub manage: Option<Manage>,
+    pub webdav: Option<Webdav>,
     pub headers: Vec<Header>,
     pub rewrite: Option<HashMap<String, Rewrite>>,
     pub extensions: Option<Vec<String>>,
     pub methods: Vec<String>,
     pub auth: Option<String>,
+    // Path serving the Prometheus metrics
+    pub metrics: Option<String>,
+    // Path answered with 200 without touching the disk
+    pub health: Option<String>,
+    pub status: Option<Status>,
+    // Checked in order, `locations` override them for their paths
+    pub access: Vec<Rule>,
+    pub locations: Vec<Location>,
+    // Proxies whose X-Forwarded-For is believed
+    pub trusted_proxies: Vec<Cidr>,
+    pub limit: Limit,
+    // Connections start with a PROXY protocol header, for the whole port
+    pub proxy_protocol: bool,
     pub error: Error,
-    pub log: Recording
+    pub log: Recording,
+    pub timeout: Timeout
 }
 
 #[derive(Debug, Default)]
 pub struct DirectoryOption {
     pub time: bool,
-    pub size: bool
+    pub size: bool,
+    // Unless the query asks otherwise
+    pub sort: Sort,
+    pub order: Order,
+    // Replaces the built-in HTML page
+    pub template: Option<Template>,
+    // Download of the whole tree with ?download=
+    pub archive: Option<Archive>,
+    // Entries of an HTML page, all on one without it
+    pub page_size: Option<usize>
+}
+
+#[derive(Debug)]
+pub struct Archive {
+    pub formats: Vec<archive::Kind>,
+    // Bytes of all files together, before compression
+    pub max_size: u64
 }
 
 // Header -> key: value
@@ -43,6 +97,104 @@ pub struct Header {
     pub value: String
 }
 
+#[derive(Debug, Default)]
+pub struct Limit {
+    pub rate: Option<Rate>,
+    // Open connections per client address, checked on accept
+    pub connections: Option<usize>
+}
+
+// PUT and POST write files below `paths`
+#[derive(Debug)]
+pub struct Upload {
+    // Without a trailing '/', "" for the whole root
+    pub paths: Vec<String>,
+    // Bytes of one request body
+    pub max_size: usize,
+    pub overwrite: bool,
+    // Lowercase, any file when empty
+    pub extensions: Vec<String>
+}
+
+impl Upload {
+
+    pub fn allows(&self, path: &str) -> bool {
+        below(&self.paths, path)
+    }
+
+    // "tar.gz" matches "build.tar.gz"
+    pub fn accepts(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.extensions.is_empty() || self.extensions.iter().any(|ext| name.ends_with(&format!(".{}", ext)))
+    }
+
+}
+
+// DELETE and MOVE below `paths`, only with `auth`
+#[derive(Debug)]
+pub struct Manage {
+    pub paths: Vec<String>,
+    // DELETE takes directories that aren't empty
+    pub recursive: bool
+}
+
+impl Manage {
+
+    pub fn allows(&self, path: &str) -> bool {
+        below(&self.paths, path)
+    }
+
+}
+
+// WebDAV below `paths`, only with `auth`
+#[derive(Debug)]
+pub struct Webdav {
+    pub paths: Vec<String>,
+    // Bytes of a PUT body
+    pub max_size: usize
+}
+
+impl Webdav {
+
+    pub fn allows(&self, path: &str) -> bool {
+        below(&self.paths, path)
+    }
+
+}
+
+// Whether `path` is one of `prefixes` or below it
+fn below(prefixes: &[String], path: &str) -> bool {
+    prefixes.iter().any(|prefix| {
+        path.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+// `name.path`, one or a list, the whole root when left out
+fn prefixes(yaml: &Yaml, name: &str) -> Result<Vec<String>, String> {
+    let paths = match &yaml["path"] {
+        Yaml::String(path) => vec![path.as_str()],
+        Yaml::Array(paths) => paths.iter().filter_map(|path| path.as_str()).collect(),
+        Yaml::BadValue => vec!["/"],
+        _ => return Err(format!("Invalid '{}.path'", name))
+    };
+    // Without a trailing '/', "" for the root
+    Ok(paths.iter().map(|path| format!("/{}", path.trim_matches('/')).trim_end_matches('/').to_string()).collect())
+}
+
+// Rules for a path and everything below it
+#[derive(Debug)]
+pub struct Location {
+    pub path: String,
+    pub access: Vec<Rule>
+}
+
+// Status page, only shown to `allow`
+#[derive(Debug)]
+pub struct Status {
+    pub path: String,
+    pub allow: Vec<Cidr>
+}
+
 #[derive(Debug)]
 pub struct Rewrite {
     pub url: String,
@@ -83,6 +235,30 @@ pub struct Error {
     pub _500: Option<String>
 }
 
+// Connection timeouts, `None` waits forever
+#[derive(Debug)]
+pub struct Timeout {
+    // Receiving the whole request header
+    pub header: Option<Duration>,
+    // Between two reads of the request body
+    pub body: Option<Duration>,
+    // Between two writes of the response
+    pub send: Option<Duration>,
+    // From accepting the connection to the end of the response
+    pub request: Option<Duration>
+}
+
+impl Default for Timeout {
+    fn default() -> Self {
+        Timeout {
+            header: Some(DEFAULT_TIMEOUT),
+            body: Some(DEFAULT_TIMEOUT),
+            send: Some(DEFAULT_TIMEOUT),
+            request: None
+        }
+    }
+}
+
 // Log path
 #[derive(Debug, Default)]
 pub struct Recording {
@@ -91,42 +267,176 @@ pub struct Recording {
 }
 
 pub const DEFAULT_METHODS: [&str; 2] = ["GET", "HEAD"];
+pub const DEFAULT_WORKER_THREADS: usize = 16;
+pub const DEFAULT_WORKER_QUEUE: usize = 1024;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+// MB
+pub const DEFAULT_ARCHIVE_MAX: u64 = 1024;
+pub const DEFAULT_UPLOAD_MAX: usize = 64;
 
-impl ServerConfig {
+impl Default for GlobalConfig {
+    fn default() -> Self {
+        GlobalConfig {
+            worker_threads: DEFAULT_WORKER_THREADS,
+            worker_queue: DEFAULT_WORKER_QUEUE,
+            shutdown_timeout: Some(DEFAULT_SHUTDOWN_TIMEOUT),
+            error_log: Target::Stderr,
+            log_level: Level::Warn
+        }
+    }
+}
 
-    // The same port service is a group
-    pub fn new(path: &str) -> Result<Vec<Arc<Vec<ServerConfig>>>, String>  {
+// Read the top-level list of the configuration file
+fn load(path: &str) -> Result<Vec<Yaml>, String> {
+
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(format!("{} {}", path, err));
+        }
+    };
+
+    let docs = match YamlLoader::load_from_str(&content) {
+        Ok(yaml) => {
+            if yaml.is_empty() {
+                return Err(String::from("Server should be a list"));
+            }
+            yaml
+        },
+        Err(err) => {
+            return Err(err.to_string());
+        }
+    };
+
+    match docs[0].as_vec() {
+        Some(list) => Ok(list.clone()),
+        None => Err(String::from("Server should be a list"))
+    }
+
+}
+
+// Seconds, fractions allowed
+fn duration(yaml: &Yaml, name: &str, default: Option<Duration>) -> Result<Option<Duration>, String> {
+
+    let secs = match yaml {
+        Yaml::Integer(n) => *n as f64,
+        Yaml::Real(_) => yaml.as_f64().unwrap_or(-1.0),
+        Yaml::BadValue => return Ok(default),
+        _ => -1.0
+    };
+
+    if secs <= 0.0 {
+        return Err(format!("\"{}\" must be a positive number of seconds", name));
+    }
+
+    Ok(Some(Duration::from_secs_f64(secs)))
+
+}
+
+// A list of `- allow: <address>` and `- deny: <address>`
+fn rules(yaml: &Yaml, name: &str) -> Result<Vec<Rule>, String> {
+
+    let list = match yaml {
+        Yaml::Array(list) => list,
+        Yaml::BadValue => return Ok(vec![]),
+        _ => return Err(format!("\"{}\" must be a list of allow and deny rules", name))
+    };
 
-        let content = match fs::read_to_string(&path) {
-            Ok(content) => content,
-            Err(err) => {
-                return Err(format!("{} {}", path, err));
+    let mut rules = vec![];
+    for item in list {
+        let rule = item.as_hash()
+            .filter(|hash| hash.len() == 1)
+            .and_then(|hash| hash.iter().next())
+            .and_then(|(kind, value)| Some((kind.as_str()?, value.as_str()?)));
+        match rule {
+            Some((kind, value)) => rules.push(Rule::new(kind, value)?),
+            None => return Err(format!("\"{}\" must be a list of allow and deny rules", name))
+        }
+    }
+    Ok(rules)
+
+}
+
+impl GlobalConfig {
+
+    // Options from the `- global:` item, if any
+    pub fn new(path: &str) -> Result<GlobalConfig, String> {
+
+        let mut config = GlobalConfig::default();
+
+        for x in load(path)?.iter() {
+
+            let global = &x["global"];
+            if global.is_badvalue() {
+                continue;
             }
-        };
 
-        let docs = match YamlLoader::load_from_str(&content) {
-            Ok(yaml) => {
-                if yaml.len() == 0 {
-                    return Err(String::from("Server should be a list"));
+            if let Some(n) = global["worker_threads"].as_i64() {
+                if n < 1 {
+                    return Err(String::from("\"worker_threads\" must be greater than 0"));
                 }
-                yaml
-            },
-            Err(err) => {
-                return Err(err.to_string());
+                config.worker_threads = n as usize;
             }
-        };
 
-        let mut configs: Vec<Vec<ServerConfig>> = vec![];
+            if let Some(n) = global["worker_queue"].as_i64() {
+                if n < 1 {
+                    return Err(String::from("\"worker_queue\" must be greater than 0"));
+                }
+                config.worker_queue = n as usize;
+            }
 
-        let servers = match docs[0].as_vec() {
-            Some(servers) => servers,
-            None => {
-                return Err(String::from("Server should be a list"));
+            config.shutdown_timeout = duration(
+                &global["shutdown_timeout"],
+                "shutdown_timeout",
+                config.shutdown_timeout
+            )?;
+
+            // Relative paths start from the working directory
+            if let Some(target) = global["error_log"].as_str() {
+                let current = env::current_dir().map_err(|err| err.to_string())?;
+                config.error_log = Target::new(target, &current.to_string_lossy())?;
+            }
+
+            if let Some(level) = global["log_level"].as_str() {
+                config.log_level = Level::new(level)?;
+            }
+
+        }
+
+        Ok(config)
+
+    }
+
+}
+
+impl ServerConfig {
+
+    // Rules of the deepest location containing `path`
+    pub fn access(&self, path: &str) -> &[Rule] {
+        for location in &self.locations {
+            if let Some(rest) = path.strip_prefix(&location.path) {
+                if rest.is_empty() || rest.starts_with('/') {
+                    return &location.access;
+                }
             }
-        };
+        }
+        &self.access
+    }
+
+    // The same port service is a group
+    pub fn new(path: &str) -> Result<Vec<Arc<Vec<ServerConfig>>>, String>  {
+
+        let servers = load(path)?;
+
+        let mut configs: Vec<Vec<ServerConfig>> = vec![];
 
         for x in servers.iter() {
 
+            if !x["global"].is_badvalue() {
+                continue;
+            }
+
             let server = &x["server"];
 
             let hosts = match server["host"].as_vec() {
@@ -218,9 +528,65 @@ impl ServerConfig {
                                 Some(size) => size,
                                 None => false
                             };
+                            let sort = match server["directory"]["sort"].as_str() {
+                                Some(sort) => Sort::new(sort)?,
+                                None => Sort::default()
+                            };
+                            let order = match server["directory"]["order"].as_str() {
+                                Some(order) => Order::new(order)?,
+                                None => Order::default()
+                            };
+                            let template = match server["directory"]["template"].as_str() {
+                                Some(file) => {
+                                    let file = fill_path(&root, file);
+                                    let source = fs::read_to_string(&file)
+                                        .map_err(|err| format!("Reading the template {} failed ({})", file, err))?;
+                                    Some(Template::new(&source).map_err(|err| format!("{}: {}", file, err))?)
+                                },
+                                None => None
+                            };
+                            let page_size = match &server["directory"]["page_size"] {
+                                Yaml::Integer(n) if *n > 0 => Some(*n as usize),
+                                Yaml::BadValue => None,
+                                _ => return Err(String::from("\"directory.page_size\" must be a positive number"))
+                            };
+                            let archive = &server["directory"]["archive"];
+                            let archive = match archive {
+                                Yaml::Boolean(false) | Yaml::BadValue => None,
+                                _ => {
+                                    let formats = match &archive["formats"] {
+                                        Yaml::Array(formats) => {
+                                            let mut kinds = vec![];
+                                            for format in formats {
+                                                match format.as_str() {
+                                                    Some(format) => kinds.push(archive::Kind::new(format)?),
+                                                    None => return Err(String::from("Invalid 'directory.archive.formats'"))
+                                                }
+                                            }
+                                            kinds
+                                        },
+                                        Yaml::BadValue => vec![archive::Kind::Zip, archive::Kind::TarGz],
+                                        _ => return Err(String::from("Invalid 'directory.archive.formats'"))
+                                    };
+                                    let max_size = match &archive["max_size"] {
+                                        Yaml::Integer(n) if *n > 0 => *n as u64,
+                                        Yaml::BadValue => DEFAULT_ARCHIVE_MAX,
+                                        _ => return Err(String::from("\"directory.archive.max_size\" must be a positive number of MB"))
+                                    };
+                                    Some(Archive {
+                                        formats,
+                                        max_size: max_size * 1024 * 1024
+                                    })
+                                }
+                            };
                             Some(DirectoryOption {
                                 time,
-                                size
+                                size,
+                                sort,
+                                order,
+                                template,
+                                archive,
+                                page_size
                             })
                         },
                         None => None
@@ -311,6 +677,82 @@ impl ServerConfig {
                 }
             }
 
+            let upload = match &server["upload"] {
+                Yaml::BadValue | Yaml::Boolean(false) => None,
+                yaml => {
+                    if server["auth"].as_hash().is_none() {
+                        return Err(String::from("'upload' needs 'auth'"));
+                    }
+                    let paths = prefixes(yaml, "upload")?;
+                    let max_size = match &yaml["max_size"] {
+                        Yaml::Integer(n) if *n > 0 => *n as usize,
+                        Yaml::BadValue => DEFAULT_UPLOAD_MAX,
+                        _ => return Err(String::from("\"upload.max_size\" must be a positive number of MB"))
+                    };
+                    let extensions = match &yaml["extensions"] {
+                        Yaml::Array(exts) => exts.iter()
+                            .filter_map(|ext| ext.as_str())
+                            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                            .collect(),
+                        Yaml::BadValue => vec![],
+                        _ => return Err(String::from("Invalid 'upload.extensions'"))
+                    };
+                    // Nothing to upload with otherwise
+                    for method in ["PUT", "POST"] {
+                        if !methods.iter().any(|m| m == method) {
+                            methods.push(method.to_string());
+                        }
+                    }
+                    Some(Upload {
+                        paths,
+                        max_size: max_size * 1024 * 1024,
+                        overwrite: yaml["overwrite"].as_bool().unwrap_or(false),
+                        extensions
+                    })
+                }
+            };
+
+            let manage = match &server["manage"] {
+                Yaml::BadValue | Yaml::Boolean(false) => None,
+                yaml => {
+                    if server["auth"].as_hash().is_none() {
+                        return Err(String::from("'manage' needs 'auth'"));
+                    }
+                    for method in ["DELETE", "MOVE"] {
+                        if !methods.iter().any(|m| m == method) {
+                            methods.push(method.to_string());
+                        }
+                    }
+                    Some(Manage {
+                        paths: prefixes(yaml, "manage")?,
+                        recursive: yaml["recursive"].as_bool().unwrap_or(false)
+                    })
+                }
+            };
+
+            let webdav = match &server["webdav"] {
+                Yaml::BadValue | Yaml::Boolean(false) => None,
+                yaml => {
+                    if server["auth"].as_hash().is_none() {
+                        return Err(String::from("'webdav' needs 'auth'"));
+                    }
+                    let max_size = match &yaml["max_size"] {
+                        Yaml::Integer(n) if *n > 0 => *n as usize,
+                        Yaml::BadValue => DEFAULT_UPLOAD_MAX,
+                        _ => return Err(String::from("\"webdav.max_size\" must be a positive number of MB"))
+                    };
+                    for method in webdav::METHODS {
+                        if !methods.iter().any(|m| m == method) {
+                            methods.push(method.to_string());
+                        }
+                    }
+                    Some(Webdav {
+                        paths: prefixes(yaml, "webdav")?,
+                        max_size: max_size * 1024 * 1024
+                    })
+                }
+            };
+
             let _404 = match server["error"][404].as_str() {
                 Some(d) => Some(fill_path(&root, d)),
                 None => None
@@ -321,13 +763,69 @@ impl ServerConfig {
                 None => None
             };
 
+            let format = match server["log"]["format"].as_str() {
+                Some(d) => Format::new(d)?,
+                None => Format::default()
+            };
+
+            let buffer = match server["log"]["buffer"].as_i64() {
+                Some(n) if n < 1 => {
+                    return Err(String::from("\"log.buffer\" must be greater than 0"));
+                },
+                Some(n) => n as usize,
+                None => log::DEFAULT_BUFFER
+            };
+
+            let flush = duration(&server["log"]["flush"], "log.flush", Some(log::DEFAULT_FLUSH))?
+                .unwrap_or(log::DEFAULT_FLUSH);
+
+            let overflow = match server["log"]["overflow"].as_str() {
+                Some("block") | None => Overflow::Block,
+                Some("drop") => Overflow::Drop,
+                Some(d) => {
+                    return Err(format!("Wrong log overflow \"{}\"", d));
+                }
+            };
+
+            let rotate = &server["log"]["rotate"];
+            let size = match rotate["size"] {
+                Yaml::Integer(n) if n > 0 => Some(n as u64 * 1024 * 1024),
+                Yaml::Real(_) if rotate["size"].as_f64().unwrap_or(0.0) > 0.0 => {
+                    Some((rotate["size"].as_f64().unwrap() * 1024.0 * 1024.0) as u64)
+                },
+                Yaml::BadValue => None,
+                _ => {
+                    return Err(String::from("\"log.rotate.size\" must be a positive number of megabytes"));
+                }
+            };
+            let keep = match rotate["keep"].as_i64() {
+                Some(n) if n < 1 => {
+                    return Err(String::from("\"log.rotate.keep\" must be greater than 0"));
+                },
+                Some(n) => n as usize,
+                None => log::DEFAULT_KEEP
+            };
+
+            let options = log::Options {
+                format,
+                buffer,
+                flush,
+                overflow,
+                rotate: log::Rotate {
+                    size,
+                    daily: rotate["daily"].as_bool().unwrap_or(false),
+                    keep,
+                    compress: rotate["compress"].as_bool().unwrap_or(false)
+                }
+            };
+
             let success = match server["log"]["success"].as_str() {
-                Some(d) => Some(Log::new(fill_path(&root, d))),
+                Some(d) => Some(Log::new(Target::new(d, &root)?, options.clone())),
                 None => None
             };
 
             let error = match server["log"]["error"].as_str() {
-                Some(d) => Some(Log::new(fill_path(&root, d))),
+                Some(d) => Some(Log::new(Target::new(d, &root)?, options)),
                 None => None
             };
 
@@ -352,12 +850,131 @@ impl ServerConfig {
                 None => None
             };
 
+            let metrics = match server["metrics"].as_str() {
+                Some(d) if d.starts_with('/') => Some(d.to_string()),
+                Some(d) => return Err(format!("Invalid metrics path '{}', must start with '/'", d)),
+                None => None
+            };
+
+            let health = match server["health"].as_str() {
+                Some(d) if d.starts_with('/') => Some(d.to_string()),
+                Some(d) => return Err(format!("Invalid health path '{}', must start with '/'", d)),
+                None => None
+            };
+
+            let status = match &server["status"] {
+                Yaml::String(path) => Some(Status {
+                    path: path.to_string(),
+                    allow: cidr::loopback()
+                }),
+                Yaml::Hash(_) => {
+                    let path = match server["status"]["path"].as_str() {
+                        Some(d) => d.to_string(),
+                        None => return Err(String::from("Missing 'path' in status"))
+                    };
+                    let allow = match server["status"]["allow"].as_vec() {
+                        Some(list) => {
+                            let mut allow = vec![];
+                            for item in list {
+                                match item.as_str() {
+                                    Some(d) => allow.push(Cidr::new(d)?),
+                                    None => return Err(String::from("Invalid 'allow' in status"))
+                                }
+                            }
+                            allow
+                        },
+                        None => cidr::loopback()
+                    };
+                    Some(Status { path, allow })
+                },
+                Yaml::BadValue => None,
+                _ => return Err(String::from("Invalid status"))
+            };
+            if let Some(status) = &status {
+                if !status.path.starts_with('/') {
+                    return Err(format!("Invalid status path '{}', must start with '/'", status.path));
+                }
+            }
+
+            let access = rules(&server["access"], "access")?;
+
+            let mut locations = vec![];
+            if let Some(hash) = server["location"].as_hash() {
+                for (path, location) in hash.iter() {
+                    let path = match path.as_str() {
+                        Some(d) if d.starts_with('/') => d.trim_end_matches('/').to_string(),
+                        _ => return Err(String::from("Invalid location, must be a path starting with '/'"))
+                    };
+                    let access = rules(&location["access"], &format!("location {} access", path))?;
+                    locations.push(Location { path, access });
+                }
+            }
+            // Longest path first
+            locations.sort_by_key(|location| std::cmp::Reverse(location.path.len()));
+
+            let trusted_proxies = match &server["trusted_proxies"] {
+                Yaml::Array(list) => {
+                    let mut trusted = vec![];
+                    for item in list {
+                        match item.as_str() {
+                            Some(d) => trusted.push(Cidr::new(d)?),
+                            None => return Err(String::from("Invalid 'trusted_proxies'"))
+                        }
+                    }
+                    trusted
+                },
+                Yaml::BadValue => vec![],
+                _ => return Err(String::from("Invalid 'trusted_proxies'"))
+            };
+
+            let limit = &server["limit"];
+            let rate = match &limit["rate"] {
+                Yaml::BadValue => None,
+                yaml => {
+                    let rate = match yaml {
+                        Yaml::Integer(n) => *n as f64,
+                        Yaml::Real(_) => yaml.as_f64().unwrap_or(-1.0),
+                        _ => -1.0
+                    };
+                    if rate <= 0.0 {
+                        return Err(String::from("\"limit.rate\" must be a positive number of requests per second"));
+                    }
+                    let burst = match &limit["burst"] {
+                        Yaml::Integer(n) if *n >= 0 => *n as u32,
+                        Yaml::BadValue => 0,
+                        _ => return Err(String::from("\"limit.burst\" must be a number of requests"))
+                    };
+                    let key = match limit["key"].as_str() {
+                        Some("ip") | None => Key::Ip,
+                        Some(header) => Key::Header(header.to_lowercase())
+                    };
+                    Some(Rate::new(rate, burst, limit["nodelay"].as_bool().unwrap_or(false), key))
+                }
+            };
+            let connections = match &limit["connections"] {
+                Yaml::Integer(n) if *n > 0 => Some(*n as usize),
+                Yaml::BadValue => None,
+                _ => return Err(String::from("\"limit.connections\" must be a positive number"))
+            };
+
+            let default = Timeout::default();
+            let timeout = Timeout {
+                header: duration(&server["timeout"]["header"], "timeout.header", default.header)?,
+                body: duration(&server["timeout"]["body"], "timeout.body", default.body)?,
+                send: duration(&server["timeout"]["send"], "timeout.send", default.send)?,
+                request: duration(&server["timeout"]["request"], "timeout.request", default.request)?
+            };
+
             let config = ServerConfig {
                 hosts,
                 listen,
                 root,
                 compress,
                 directory,
+                markdown: server["markdown"].as_bool().unwrap_or(false),
+                upload,
+                manage,
+                webdav,
                 index,
                 headers,
                 rewrite,
@@ -371,7 +988,19 @@ impl ServerConfig {
                     success,
                     error
                 },
-                auth
+                auth,
+                metrics,
+                health,
+                status,
+                access,
+                locations,
+                trusted_proxies,
+                proxy_protocol: server["proxy_protocol"].as_bool().unwrap_or(false),
+                limit: Limit {
+                    rate,
+                    connections
+                },
+                timeout
             };
 
             let (mut has, mut n) = (false, 0);
@@ -383,6 +1012,9 @@ impl ServerConfig {
                 }
             }
             if has {
+                if configs[n][0].proxy_protocol != config.proxy_protocol {
+                    return Err(format!("Servers on port {} must agree on 'proxy_protocol'", listen));
+                }
                 configs[n].push(config);
             }else {
                 configs.push(vec![config]);
@@ -390,6 +1022,12 @@ impl ServerConfig {
 
         }
 
+        let logs: Vec<&Log> = configs.iter()
+            .flatten()
+            .flat_map(|config| config.log.success.iter().chain(&config.log.error))
+            .collect();
+        log::check(&logs)?;
+
         let mut conf = vec![];
         for config in configs {
             conf.push(Arc::new(config));
@@ -401,4 +1039,14 @@ impl ServerConfig {
 
 }
 
+// Start the log writers once the configuration is the one in use,
+// `ServerConfig::new` only checks them
+pub fn open_logs(configs: &[Arc<Vec<ServerConfig>>]) {
+    for config in configs.iter().flat_map(|group| group.iter()) {
+        for log in config.log.success.iter().chain(&config.log.error) {
+            log.open();
+        }
+    }
+}
+
 
@@ -0,0 +1,708 @@
+
+use std::io;
+use std::io::prelude::*;
+use std::sync::Arc;
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::time::Instant;
+use crate::config::{self, ServerConfig};
+use crate::log;
+use crate::pool::ThreadPool;
+use crate::metrics::Active;
+use crate::limit::Client;
+use crate::response::{StatusCode, Response};
+use crate::signal::{Signal, Signals};
+use crate::{handle_connection, earliest, Prelude};
+#[cfg(target_os = "linux")]
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use crate::{upgrade, status, proxy, client, header_end, screen, HEADER_MAX};
+#[cfg(target_os = "linux")]
+use crate::limit::Verdict;
+
+#[cfg(target_os = "linux")]
+use std::collections::{BTreeSet, HashMap};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+// Accept queue length, the kernel caps it at `net.core.somaxconn`
+#[cfg(target_os = "linux")]
+const BACKLOG: i32 = 4096;
+
+// Token of the signal pipe, listeners and connections count up from 0
+#[cfg(target_os = "linux")]
+const SIGNALS: u64 = u64::MAX;
+
+
+// Readiness notification from the poller
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    pub token: u64,
+    pub readable: bool,
+    pub hangup: bool
+}
+
+// Thin wrapper around epoll
+#[cfg(target_os = "linux")]
+pub struct Poller {
+    fd: RawFd
+}
+
+#[cfg(target_os = "linux")]
+impl Poller {
+
+    pub fn new() -> io::Result<Poller> {
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Poller { fd })
+    }
+
+    // Listeners stay registered, connections are reported only once
+    pub fn add(&self, fd: RawFd, token: u64, once: bool) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_ADD, fd, token, once)
+    }
+
+    // Report a connection once more
+    pub fn rearm(&self, fd: RawFd, token: u64) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_MOD, fd, token, true)
+    }
+
+    fn control(&self, op: i32, fd: RawFd, token: u64, once: bool) -> io::Result<()> {
+        let mut flags = libc::EPOLLIN | libc::EPOLLRDHUP;
+        if once {
+            flags |= libc::EPOLLONESHOT;
+        }
+        let mut event = libc::epoll_event {
+            events: flags as u32,
+            u64: token
+        };
+        let n = unsafe { libc::epoll_ctl(self.fd, op, fd, &mut event) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn delete(&self, fd: RawFd) -> io::Result<()> {
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        let n = unsafe { libc::epoll_ctl(self.fd, libc::EPOLL_CTL_DEL, fd, &mut event) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Block until at least one event arrives, `timeout` in milliseconds (-1 waits forever)
+    pub fn wait(&self, events: &mut Vec<Event>, timeout: i32) -> io::Result<()> {
+        let mut buffer = [libc::epoll_event { events: 0, u64: 0 }; 1024];
+        events.clear();
+        let n = unsafe {
+            libc::epoll_wait(self.fd, buffer.as_mut_ptr(), buffer.len() as i32, timeout)
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(err);
+        }
+        for event in buffer.iter().take(n as usize) {
+            let flags = event.events as i32;
+            events.push(Event {
+                token: event.u64,
+                readable: flags & libc::EPOLLIN != 0,
+                hangup: flags & (libc::EPOLLRDHUP | libc::EPOLLHUP | libc::EPOLLERR) != 0
+            });
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Poller {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+
+// Loads the configuration again, grouped by port like `ServerConfig::new`
+pub type Reload<'a> = &'a dyn Fn() -> Result<Vec<Arc<Vec<ServerConfig>>>, String>;
+
+#[cfg(target_os = "linux")]
+struct Listener {
+    socket: TcpListener,
+    port: i64,
+    configs: Arc<Vec<ServerConfig>>
+}
+
+// A connection waiting for its request to arrive
+#[cfg(target_os = "linux")]
+struct Connection {
+    stream: TcpStream,
+    configs: Arc<Vec<ServerConfig>>,
+    accepted: Instant,
+    // When to give up waiting for the request
+    expire: Option<Instant>,
+    active: Active,
+    // Counted once the client is known, see `proxied`
+    client: Option<Client>,
+    // The peer, or the client the PROXY protocol header names
+    remote: SocketAddr,
+    // The PROXY protocol header is still to come
+    proxy: bool,
+    // Received so far, after the PROXY protocol header
+    buffer: Vec<u8>
+}
+
+// Where a connection stands after reading what has arrived
+#[cfg(target_os = "linux")]
+enum Progress {
+    // More of the header is to come
+    Waiting,
+    // The header is in, or too large to wait for
+    Ready,
+    // Closed by the client, or not speaking the PROXY protocol
+    Gone
+}
+
+// Multiplex all listeners and idle connections on a single epoll instance.
+// A connection only occupies a worker once its request header has arrived
+// and the rate limit lets it go on.
+// Returns when asked to shut down, requests already handed to `pool` keep running.
+#[cfg(target_os = "linux")]
+pub fn run(
+    sockets: Vec<(TcpListener, Arc<Vec<ServerConfig>>)>,
+    pool: Arc<ThreadPool>,
+    signals: &Signals,
+    reload: Reload
+) -> io::Result<()> {
+
+    let poller = Poller::new()?;
+    poller.add(signals.fd(), SIGNALS, false)?;
+
+    // Tokens of listeners and connections share one counter
+    let mut next = 0;
+    let mut listeners: HashMap<u64, Listener> = HashMap::new();
+
+    for (socket, configs) in sockets {
+        listen(&poller, &socket, next)?;
+        listeners.insert(next, Listener {
+            socket,
+            port: configs[0].listen,
+            configs
+        });
+        next += 1;
+    }
+
+    let mut connections: HashMap<u64, Connection> = HashMap::new();
+    // Slowed down by the rate limit, waiting for their turn
+    let mut delayed: HashMap<u64, Connection> = HashMap::new();
+    // Idle connections ordered by expiry, delayed ones by their turn
+    let mut timers: BTreeSet<(Instant, u64)> = BTreeSet::new();
+    let mut events = vec![];
+    let mut running = true;
+
+    while running {
+
+        let timeout = match timers.iter().next() {
+            Some((expire, _)) => {
+                let wait = expire.saturating_duration_since(Instant::now());
+                // Round up so the timer has fired when we wake up
+                (wait.as_millis() as i32).saturating_add(1)
+            },
+            None => -1
+        };
+
+        poller.wait(&mut events, timeout)?;
+
+        for event in events.iter() {
+
+            if event.token == SIGNALS {
+                for signal in signals.read() {
+                    match signal {
+                        Signal::Shutdown => running = false,
+                        Signal::Reload => {
+                            match reload() {
+                                Ok(configs) => {
+                                    let servers = status::servers(&configs);
+                                    match swap(&poller, &mut listeners, &mut next, configs) {
+                                        Ok(()) => {
+                                            status::serving(servers);
+                                            log::info(format_args!("Configuration reloaded"));
+                                        },
+                                        Err(err) => log::error(format_args!(
+                                            "Reload failed, keeping the current configuration ({})", err
+                                        ))
+                                    }
+                                },
+                                Err(err) => {
+                                    log::error(format_args!(
+                                        "Reload failed, keeping the current configuration ({})", err
+                                    ));
+                                }
+                            }
+                        },
+                        Signal::Reopen => log::reopen(),
+                        Signal::Upgrade => {
+                            let sockets: Vec<&TcpListener> = listeners
+                                .values()
+                                .map(|listener| &listener.socket)
+                                .collect();
+                            match upgrade::spawn(&sockets) {
+                                Ok(pid) => {
+                                    println!("Upgraded to process {}", pid);
+                                    running = false;
+                                },
+                                Err(err) => {
+                                    log::error(format_args!("Upgrade failed ({})", err));
+                                }
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if let Some(listener) = listeners.get(&event.token) {
+                loop {
+                    let (stream, addr) = match listener.socket.accept() {
+                        Ok(accepted) => accepted,
+                        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            log::warn(format_args!("Accept failed ({})", err));
+                            break;
+                        }
+                    };
+                    let client = if listener.configs[0].proxy_protocol || proxied(&listener.configs, addr.ip()) {
+                        None
+                    }else {
+                        match Client::connect(listener.port, addr.ip(), listener.configs[0].limit.connections) {
+                            Some(client) => Some(client),
+                            None => {
+                                too_many(stream, addr.ip());
+                                continue;
+                            }
+                        }
+                    };
+                    if stream.set_nonblocking(true).is_err() {
+                        continue;
+                    }
+                    if poller.add(stream.as_raw_fd(), next, true).is_ok() {
+                        let accepted = Instant::now();
+                        let timeout = &listener.configs[0].timeout;
+                        let expire = earliest(
+                            timeout.header.map(|t| accepted + t),
+                            timeout.request.map(|t| accepted + t)
+                        );
+                        if let Some(expire) = expire {
+                            timers.insert((expire, next));
+                        }
+                        connections.insert(next, Connection {
+                            stream,
+                            configs: listener.configs.clone(),
+                            accepted,
+                            expire,
+                            active: Active::new(),
+                            client,
+                            remote: addr,
+                            proxy: listener.configs[0].proxy_protocol,
+                            buffer: vec![]
+                        });
+                        next += 1;
+                    }
+                }
+                continue;
+            }
+
+            let mut conn = match connections.remove(&event.token) {
+                Some(conn) => conn,
+                None => continue
+            };
+
+            let progress = progress(&mut conn);
+            let (ip, rate) = match progress {
+                Progress::Ready => match header_end(&conn.buffer, 0) {
+                    Some(end) => screen(&conn.configs, &conn.buffer[..end], conn.remote),
+                    None => (conn.remote.ip(), None)
+                },
+                _ => (conn.remote.ip(), None)
+            };
+
+            // Behind a proxy the client is known once the PROXY protocol header
+            // or, for a trusted proxy, the request header has arrived
+            let known = match progress {
+                Progress::Ready => true,
+                Progress::Waiting => !conn.proxy && !proxied(&conn.configs, ip),
+                Progress::Gone => false
+            };
+            let mut refused = false;
+            if known && conn.client.is_none() {
+                conn.client = Client::connect(conn.configs[0].listen, ip, conn.configs[0].limit.connections);
+                refused = conn.client.is_none();
+            }
+
+            if let (Progress::Waiting, false) = (&progress, refused) {
+                if poller.rearm(conn.stream.as_raw_fd(), event.token).is_ok() {
+                    connections.insert(event.token, conn);
+                    continue;
+                }
+            }
+            let _ = poller.delete(conn.stream.as_raw_fd());
+            if let Some(expire) = conn.expire {
+                timers.remove(&(expire, event.token));
+            }
+            if refused {
+                too_many(conn.stream, ip);
+                continue;
+            }
+            if let Progress::Ready = progress {
+                match rate {
+                    Some(Verdict::Delay(wait)) => {
+                        timers.insert((Instant::now() + wait, event.token));
+                        delayed.insert(event.token, conn);
+                    },
+                    rate => dispatch(&pool, conn, rate)
+                }
+            }
+
+        }
+
+        let now = Instant::now();
+        while let Some(&(expire, token)) = timers.iter().next() {
+            if expire > now {
+                break;
+            }
+            timers.remove(&(expire, token));
+            // Nothing was received in time
+            if let Some(conn) = connections.remove(&token) {
+                let _ = poller.delete(conn.stream.as_raw_fd());
+                timed_out(conn.stream);
+            }
+            // Waited long enough to match the rate
+            if let Some(conn) = delayed.remove(&token) {
+                dispatch(&pool, conn, Some(Verdict::Pass));
+            }
+        }
+
+    }
+
+    // Accepted connections are owed a response, the listeners close on return
+    for (_, conn) in connections.drain() {
+        let _ = poller.delete(conn.stream.as_raw_fd());
+        dispatch(&pool, conn, None);
+    }
+    for (_, conn) in delayed.drain() {
+        dispatch(&pool, conn, Some(Verdict::Pass));
+    }
+
+    Ok(())
+
+}
+
+// Read whatever arrived without blocking, so that a slow client holds no worker
+#[cfg(target_os = "linux")]
+fn progress(conn: &mut Connection) -> Progress {
+
+    let limit = if conn.proxy { proxy::MAX + HEADER_MAX } else { HEADER_MAX };
+    let open = match fill(&mut conn.stream, &mut conn.buffer, limit) {
+        Ok(open) => open,
+        Err(err) => {
+            log::debug(format_args!("Reading the request header failed ({}), {}", err, client(&conn.stream, None)));
+            return Progress::Gone;
+        }
+    };
+
+    // Behind a load balancer the client is the one it names
+    if conn.proxy {
+        match proxy::parse(&conn.buffer) {
+            Ok(Some((addr, length))) => {
+                conn.buffer.drain(..length);
+                if let Some(addr) = addr {
+                    conn.remote = addr;
+                }
+                conn.proxy = false;
+            },
+            Ok(None) if open => return Progress::Waiting,
+            Ok(None) => {},
+            Err(err) => {
+                log::info(format_args!("Invalid PROXY protocol header ({}), {}", err, client(&conn.stream, None)));
+                return Progress::Gone;
+            }
+        }
+    }
+
+    if !conn.proxy && (header_end(&conn.buffer, 0).is_some() || conn.buffer.len() > HEADER_MAX) {
+        return Progress::Ready;
+    }
+    if open {
+        return Progress::Waiting;
+    }
+    log::debug(format_args!("Connection closed before a request, {}", client(&conn.stream, None)));
+    Progress::Gone
+
+}
+
+// Append what can be read now, up to about `limit` bytes in all.
+// Returns false once the client has closed its side
+#[cfg(target_os = "linux")]
+fn fill(stream: &mut TcpStream, buffer: &mut Vec<u8>, limit: usize) -> io::Result<bool> {
+    let mut chunk = [0; 4096];
+    while buffer.len() <= limit {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(false),
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(true)
+}
+
+// A proxy may speak for other clients from this address,
+// its connections are counted once a header names the client
+#[cfg(target_os = "linux")]
+fn proxied(configs: &[ServerConfig], ip: IpAddr) -> bool {
+    configs.iter().any(|config| {
+        config.trusted_proxies.iter().any(|net| net.contains(&ip))
+    })
+}
+
+// Register a listening socket
+#[cfg(target_os = "linux")]
+fn listen(poller: &Poller, socket: &TcpListener, token: u64) -> io::Result<()> {
+    // std listens with a backlog of 128, too short for bursts of new connections
+    if unsafe { libc::listen(socket.as_raw_fd(), BACKLOG) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    socket.set_nonblocking(true)?;
+    poller.add(socket.as_raw_fd(), token, false)
+}
+
+// Apply a reloaded configuration: new ports are bound first so that
+// a failure leaves the running listeners untouched
+#[cfg(target_os = "linux")]
+fn swap(
+    poller: &Poller,
+    listeners: &mut HashMap<u64, Listener>,
+    next: &mut u64,
+    configs: Vec<Arc<Vec<ServerConfig>>>
+) -> io::Result<()> {
+
+    let mut fresh = vec![];
+    for group in configs.iter() {
+        let port = group[0].listen;
+        if !listeners.values().any(|listener| listener.port == port) {
+            let socket = TcpListener::bind(format!("0.0.0.0:{}", port))?;
+            fresh.push((socket, group.clone()));
+        }
+    }
+
+    // Stop listening on ports no longer configured
+    listeners.retain(|_, listener| {
+        let keep = configs.iter().any(|group| group[0].listen == listener.port);
+        if !keep {
+            let _ = poller.delete(listener.socket.as_raw_fd());
+        }
+        keep
+    });
+
+    // Connections accepted from now on see the new configuration
+    config::open_logs(&configs);
+    for listener in listeners.values_mut() {
+        if let Some(group) = configs.iter().find(|group| group[0].listen == listener.port) {
+            listener.configs = group.clone();
+        }
+    }
+
+    for (socket, configs) in fresh {
+        listen(poller, &socket, *next)?;
+        listeners.insert(*next, Listener {
+            socket,
+            port: configs[0].listen,
+            configs
+        });
+        *next += 1;
+    }
+
+    Ok(())
+
+}
+
+#[cfg(target_os = "linux")]
+fn dispatch(pool: &ThreadPool, conn: Connection, rate: Option<Verdict>) {
+    if conn.stream.set_nonblocking(false).is_err() {
+        return;
+    }
+    let prelude = Prelude {
+        remote: Some(conn.remote),
+        received: conn.buffer,
+        rate
+    };
+    let (configs, accepted, active, client) = (conn.configs, conn.accepted, conn.active, conn.client);
+    if let Err(stream) = pool.execute(conn.stream, move |mut stream| {
+        handle_connection(&mut stream, configs, accepted, prelude);
+        // Released before the client sees the connection close
+        drop((active, client));
+    }) {
+        overloaded(stream);
+    }
+}
+
+// Fallback for platforms without epoll: one accepting thread per listener
+#[cfg(not(target_os = "linux"))]
+pub fn run(
+    listeners: Vec<(TcpListener, Arc<Vec<ServerConfig>>)>,
+    pool: Arc<ThreadPool>,
+    signals: &Signals,
+    _reload: Reload
+) -> io::Result<()> {
+
+    for (listener, configs) in listeners {
+        let pool = pool.clone();
+        std::thread::spawn(move || {
+            let port = configs[0].listen;
+            for stream in listener.incoming().flatten() {
+                let configs = configs.clone();
+                let accepted = Instant::now();
+                let max = configs[0].limit.connections;
+                // Counted by the peer, behind a proxy too
+                let ip = match stream.peer_addr() {
+                    Ok(addr) => addr.ip(),
+                    Err(_) => continue
+                };
+                let client = match Client::connect(port, ip, max) {
+                    Some(client) => client,
+                    None => {
+                        too_many(stream, ip);
+                        continue;
+                    }
+                };
+                let active = Active::new();
+                if let Err(stream) = pool.execute(stream, move |mut stream| {
+                    if let Some(remote) = read_proxy(&mut stream, &configs, accepted) {
+                        let prelude = Prelude { remote, received: vec![], rate: None };
+                        handle_connection(&mut stream, configs, accepted, prelude);
+                    }
+                    // Released before the client sees the connection close
+                    drop((active, client));
+                }) {
+                    overloaded(stream);
+                }
+            }
+        });
+    }
+
+    loop {
+        for signal in signals.wait() {
+            match signal {
+                Signal::Shutdown => return Ok(()),
+                Signal::Reopen => log::reopen(),
+                Signal::Reload => log::warn(format_args!("Reload is not supported on this platform")),
+                Signal::Upgrade => log::warn(format_args!("Upgrade is not supported on this platform"))
+            }
+        }
+    }
+
+}
+
+// The client behind a load balancer, read by the worker without an event loop.
+// None when the connection is to be closed
+#[cfg(not(target_os = "linux"))]
+fn read_proxy(stream: &mut TcpStream, configs: &[ServerConfig], accepted: Instant) -> Option<Option<std::net::SocketAddr>> {
+
+    use std::io::ErrorKind;
+    use crate::{client, proxy, remaining, MIN_TIMEOUT};
+
+    let remote = stream.peer_addr().ok();
+    if !configs[0].proxy_protocol {
+        return Some(remote);
+    }
+    let timeout = &configs[0].timeout;
+    let deadline = earliest(
+        timeout.header.map(|t| accepted + t),
+        timeout.request.map(|t| accepted + t)
+    );
+    let read = stream.set_read_timeout(deadline.map(|t| remaining(t).max(MIN_TIMEOUT)))
+        .and_then(|_| proxy::read(stream));
+    match read {
+        Ok(addr) => Some(addr.or(remote)),
+        Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => {
+            log::debug(format_args!("Connection closed before a request, {}", client(stream, None)));
+            None
+        },
+        Err(ref err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {
+            log::info(format_args!("Timed out reading the PROXY protocol header, {}", client(stream, None)));
+            None
+        },
+        Err(err) => {
+            log::info(format_args!("Invalid PROXY protocol header ({}), {}", err, client(stream, None)));
+            None
+        }
+    }
+
+}
+
+// Every worker is busy and the queue is full
+fn overloaded(mut stream: TcpStream) {
+    let res = Response::new(StatusCode::_503, &[])
+        .header("Retry-After", "1")
+        .text("503");
+    let _ = stream.write_all(&res);
+}
+
+// The client has as many connections open as it may
+fn too_many(mut stream: TcpStream, ip: IpAddr) {
+    log::info(format_args!("Too many connections, client: {}", ip));
+    let res = Response::new(StatusCode::_429, &[])
+        .header("Retry-After", "1")
+        .text("429");
+    let _ = stream.write_all(&res);
+}
+
+// Best effort, the client may be gone already
+#[cfg(target_os = "linux")]
+fn timed_out(mut stream: TcpStream) {
+    let res = Response::new(StatusCode::_408, &[])
+        .text("408");
+    let _ = stream.write_all(&res);
+}
+
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+    use crate::event::{Event, Poller};
+
+    #[test]
+    fn test_poller() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let poller = Poller::new().unwrap();
+        poller.add(server.as_raw_fd(), 7, true).unwrap();
+
+        let mut events = vec![];
+        poller.wait(&mut events, 0).unwrap();
+        assert_eq!(events, vec![]);
+
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        poller.wait(&mut events, 1000).unwrap();
+        assert_eq!(events, vec![Event { token: 7, readable: true, hangup: false }]);
+
+        // One-shot registration stays silent until re-armed
+        poller.wait(&mut events, 0).unwrap();
+        assert_eq!(events, vec![]);
+        poller.delete(server.as_raw_fd()).unwrap();
+    }
+
+}
@@ -20,7 +20,7 @@ pub const TEMPLATE: &'static str = r#"<!DOCTYPE html>
             display: grid;
             grid-template-columns: {main};
         }
-        a:first-child{
+        .parent{
             grid-column: {first};
         }
         a, time, span{
@@ -28,18 +28,179 @@ pub const TEMPLATE: &'static str = r#"<!DOCTYPE html>
             word-wrap: break-word;
             margin-top: 6px;
         }
-        time, span{
+        time, span, .column{
             padding-left: 20px;
         }
+        .sort{
+            color: inherit;
+            font-weight: bold;
+            text-decoration: none;
+        }
+        .upload, .search{
+            margin-bottom: 16px;
+        }
+        .pages{
+            margin-top: 16px;
+        }
+        .pages a, .pages span{
+            margin-right: 16px;
+        }
+        .readme{
+            margin-top: 24px;
+            border-top: 1px solid #eee;
+            max-width: 860px;
+        }
+        pre{
+            padding: 12px;
+            overflow: auto;
+            background: #f6f8fa;
+        }
+        td, th{
+            padding: 4px 12px;
+            border: 1px solid #ddd;
+        }
+        table{
+            border-collapse: collapse;
+        }
+        img{
+            max-width: 100%;
+        }
     </style>
 </head>
 <body>
     <h1>Index of {title}</h1>
+    {upload}
+    {search}
     <main>
-        <a href="../">../</a>
+        {head}
+        <a class="parent" href="../">../</a>
         {files}
     </main>
+    {pages}
+    {readme}
 </body>
 </html>"#;
 
 
+
+// Upload form of a listing, the files go to the directory itself
+pub const UPLOAD: &str = r#"<form class="upload" method="post" enctype="multipart/form-data">
+        <input type="file" name="file" multiple required>
+        <button type="submit">Upload</button>
+    </form>"#;
+
+// Filter box of a listing, a glob with * and ? or part of the name
+pub const SEARCH: &str = r#"<form class="search" method="get">
+        <input type="search" name="q" value="{q}" placeholder="Filter, * and ? match any">
+        <input type="hidden" name="sort" value="{sort}">
+        <input type="hidden" name="order" value="{order}">
+    </form>"#;
+
+// Markdown file template
+pub const MARKDOWN: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{title}</title>
+    <style>
+        body {
+            font-family: "pingfang sc", "microsoft yahei", "Helvetica Neue";
+            padding: 0 24px 24px;
+            max-width: 860px;
+            margin: 0 auto;
+            line-height: 1.6;
+        }
+        h1, h2, h3, h4, h5, h6 {
+            font-weight: normal;
+        }
+        pre {
+            padding: 12px;
+            overflow: auto;
+            background: #f6f8fa;
+        }
+        code {
+            background: #f6f8fa;
+        }
+        blockquote {
+            margin-left: 0;
+            padding-left: 16px;
+            color: #666;
+            border-left: 4px solid #ddd;
+        }
+        table {
+            border-collapse: collapse;
+        }
+        td, th {
+            padding: 4px 12px;
+            border: 1px solid #ddd;
+        }
+        img {
+            max-width: 100%;
+        }
+    </style>
+</head>
+<body>
+{body}
+</body>
+</html>"#;
+
+// Status page template
+pub const STATUS: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Status</title>
+    <style>
+        body {
+            font-family: "pingfang sc", "microsoft yahei", "Helvetica Neue";
+            padding: 0 24px 0;
+        }
+        h1, h2 {
+            font-weight: normal;
+        }
+        th, td {
+            text-align: left;
+            padding: 4px 20px 4px 0;
+        }
+    </style>
+</head>
+<body>
+    <h1>Status</h1>
+    <table>
+        <tr><th>Version</th><td>{version}</td></tr>
+        <tr><th>Uptime</th><td>{uptime}</td></tr>
+        <tr><th>Config</th><td>{config}</td></tr>
+        <tr><th>Connections</th><td>{connections}</td></tr>
+    </table>
+    <h2>Servers</h2>
+    <table>
+        <tr><th>Port</th><th>Hosts</th></tr>
+        {servers}
+    </table>
+    <h2>Errors</h2>
+    <table>
+        <tr><th>Last</th><th>4xx</th><th>5xx</th><th>Logged</th></tr>
+        {errors}
+    </table>
+</body>
+</html>"#;
+
+// Text placed in HTML, braces too so it can't pass for a template placeholder
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            '{' => out.push_str("&#123;"),
+            '}' => out.push_str("&#125;"),
+            c => out.push(c)
+        }
+    }
+    out
+}
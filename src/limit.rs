@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+// Each shard has its own lock so clients rarely wait on each other
+const SHARDS: usize = 16;
+
+// Forget idle clients once a shard holds that many
+const PRUNE: usize = 4096;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// What a client is recognized by
+#[derive(Debug, Clone, PartialEq)]
+pub enum Key {
+    Ip,
+    // Value of this request header, the address when it is missing
+    Header(String)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Pass,
+    // Slowed down to the rate
+    Delay(Duration),
+    // Over the burst, try again after that
+    Reject(Duration)
+}
+
+// Leaky bucket per client: `rate` requests a second, up to `burst`
+// more are delayed to match the rate, or served at once with `nodelay`
+#[derive(Debug)]
+pub struct Rate {
+    interval: Duration,
+    burst: u32,
+    nodelay: bool,
+    pub key: Key,
+    hasher: RandomState,
+    // When the bucket of each client is empty again
+    shards: Vec<Mutex<HashMap<String, Instant>>>
+}
+
+impl Rate {
+
+    pub fn new(rate: f64, burst: u32, nodelay: bool, key: Key) -> Rate {
+        Rate {
+            interval: Duration::from_secs_f64(1.0 / rate),
+            burst,
+            nodelay,
+            key,
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect()
+        }
+    }
+
+    pub fn check(&self, client: &str, now: Instant) -> Verdict {
+
+        let mut shard = lock(&self.shards[self.hasher.hash_one(client) as usize % SHARDS]);
+
+        if shard.len() >= PRUNE {
+            shard.retain(|_, empty| *empty > now);
+        }
+
+        let empty = shard.get(client).map_or(now, |empty| (*empty).max(now));
+        let excess = empty - now;
+        let allowed = self.interval * self.burst;
+        if excess > allowed {
+            return Verdict::Reject(excess - allowed);
+        }
+
+        shard.insert(client.to_string(), empty + self.interval);
+        if self.nodelay || excess.is_zero() {
+            Verdict::Pass
+        }else {
+            Verdict::Delay(excess)
+        }
+
+    }
+
+}
+
+
+// Open connections per port and address
+static CLIENTS: [Mutex<BTreeMap<(i64, IpAddr), usize>>; SHARDS] = [const { Mutex::new(BTreeMap::new()) }; SHARDS];
+
+// Counts a connection of a client while it is open
+pub struct Client {
+    key: Option<(i64, IpAddr)>
+}
+
+impl Client {
+
+    // None when the client already has `max` connections on this port
+    pub fn connect(port: i64, ip: IpAddr, max: Option<usize>) -> Option<Client> {
+
+        let max = match max {
+            Some(max) => max,
+            None => return Some(Client { key: None })
+        };
+
+        let key = (port, ip);
+        let mut clients = lock(&CLIENTS[shard(&key)]);
+        let count = clients.entry(key).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(Client { key: Some(key) })
+
+    }
+
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            let mut clients = lock(&CLIENTS[shard(&key)]);
+            if let Some(count) = clients.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    clients.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+fn shard(key: &(i64, IpAddr)) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % SHARDS
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::{Duration, Instant};
+    use crate::limit::{Client, Key, Rate, Verdict};
+
+    #[test]
+    fn test_rate() {
+        let now = Instant::now();
+        let rate = Rate::new(10.0, 2, false, Key::Ip);
+        assert_eq!(rate.check("a", now), Verdict::Pass);
+        assert_eq!(rate.check("a", now), Verdict::Delay(Duration::from_millis(100)));
+        assert_eq!(rate.check("a", now), Verdict::Delay(Duration::from_millis(200)));
+        assert_eq!(rate.check("a", now), Verdict::Reject(Duration::from_millis(100)));
+        // Others have their own bucket
+        assert_eq!(rate.check("b", now), Verdict::Pass);
+        // Drained at the rate
+        assert_eq!(rate.check("a", now + Duration::from_millis(300)), Verdict::Pass);
+
+        let rate = Rate::new(10.0, 1, true, Key::Ip);
+        assert_eq!(rate.check("a", now), Verdict::Pass);
+        assert_eq!(rate.check("a", now), Verdict::Pass);
+        assert_eq!(rate.check("a", now), Verdict::Reject(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_connections() {
+        let ip = "192.0.2.1".parse().unwrap();
+        let first = Client::connect(1, ip, Some(2));
+        let second = Client::connect(1, ip, Some(2));
+        assert!(first.is_some() && second.is_some());
+        assert!(Client::connect(1, ip, Some(2)).is_none());
+        assert!(Client::connect(2, ip, Some(2)).is_some());
+        drop(first);
+        assert!(Client::connect(1, ip, Some(2)).is_some());
+    }
+
+}
@@ -0,0 +1,663 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+use chrono::{DateTime, Local};
+use percent_encoding::{define_encode_set, utf8_percent_encode, PATH_SEGMENT_ENCODE_SET, QUERY_ENCODE_SET};
+use crate::config::DirectoryOption;
+use crate::html::{self, SEARCH, TEMPLATE, UPLOAD};
+use crate::log;
+use crate::markdown;
+use crate::template::{object, Value};
+use crate::request::Request;
+use crate::webdav;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Sort {
+    #[default]
+    Name,
+    Size,
+    Time
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc
+}
+
+impl Sort {
+    pub fn new(value: &str) -> Result<Sort, String> {
+        match value {
+            "name" => Ok(Sort::Name),
+            "size" => Ok(Sort::Size),
+            "time" => Ok(Sort::Time),
+            _ => Err(format!("Invalid sort '{}', expected name, size or time", value))
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Sort::Name => "name",
+            Sort::Size => "size",
+            Sort::Time => "time"
+        }
+    }
+}
+
+impl Order {
+    pub fn new(value: &str) -> Result<Order, String> {
+        match value {
+            "asc" => Ok(Order::Asc),
+            "desc" => Ok(Order::Desc),
+            _ => Err(format!("Invalid order '{}', expected asc or desc", value))
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Order::Asc => "asc",
+            Order::Desc => "desc"
+        }
+    }
+}
+
+// What the client asked for, HTML unless told otherwise
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    Json,
+    Text
+}
+
+impl Format {
+
+    // ?format=json|text|html first, then the Accept header
+    pub fn of(request: &Request) -> Format {
+        match request.param("format").as_deref() {
+            Some("json") => return Format::Json,
+            Some("text") => return Format::Text,
+            Some("html") => return Format::Html,
+            _ => {}
+        }
+        let accept = request.headers.get("accept").map_or("", |accept| accept.as_str());
+        if accept.contains("application/json") {
+            Format::Json
+        }else if accept.contains("text/plain") && !accept.contains("text/html") {
+            Format::Text
+        }else {
+            Format::Html
+        }
+    }
+
+}
+
+// One file or directory of a listing
+pub struct Entry {
+    pub name: String,
+    pub dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>
+}
+
+// Names that aren't UTF-8 can't be linked to and are left out,
+// so are the dead properties of WebDAV below its paths
+pub fn read(path: &Path, dav: bool) -> io::Result<Vec<Entry>> {
+
+    let mut entries = vec![];
+
+    for entry in fs::read_dir(path)? {
+        let entry = match entry {
+            Ok(entry) => entry.path(),
+            Err(_) => continue
+        };
+        let name = match entry.file_name().and_then(|name| name.to_str()) {
+            Some(name) if !(dav && webdav::is_sidecar(name)) => name.to_string(),
+            _ => continue
+        };
+        // Symbolic links are followed, broken ones are listed as they are
+        let meta = fs::metadata(&entry).ok();
+        entries.push(Entry {
+            name,
+            dir: meta.as_ref().is_some_and(|meta| meta.is_dir()),
+            size: meta.as_ref().filter(|meta| meta.is_file()).map_or(0, |meta| meta.len()),
+            modified: meta.and_then(|meta| meta.modified().ok())
+        });
+    }
+
+    Ok(entries)
+
+}
+
+// Directories first, then by `sort`, ties broken by name
+pub fn sort(entries: &mut [Entry], sort: Sort, order: Order) {
+    entries.sort_by(|a, b| {
+        let ordering = match sort {
+            Sort::Name => Ordering::Equal,
+            Sort::Size => a.size.cmp(&b.size),
+            Sort::Time => a.modified.cmp(&b.modified)
+        }.then_with(|| natural(&a.name, &b.name));
+        let ordering = match order {
+            Order::Asc => ordering,
+            Order::Desc => ordering.reverse()
+        };
+        b.dir.cmp(&a.dir).then(ordering)
+    });
+}
+
+// "file2" before "file10", case is ignored unless that is the only difference
+fn natural(a: &str, b: &str) -> Ordering {
+
+    let (mut x, mut y) = (a.chars().peekable(), b.chars().peekable());
+
+    loop {
+        match (x.peek().copied(), y.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(c), Some(d)) if c.is_ascii_digit() && d.is_ascii_digit() => {
+                let mut m = String::new();
+                while let Some(c) = x.next_if(|c| c.is_ascii_digit()) {
+                    m.push(c);
+                }
+                let mut n = String::new();
+                while let Some(d) = y.next_if(|d| d.is_ascii_digit()) {
+                    n.push(d);
+                }
+                let (m, n) = (m.trim_start_matches('0'), n.trim_start_matches('0'));
+                let ordering = m.len().cmp(&n.len()).then_with(|| m.cmp(n));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            },
+            (Some(c), Some(d)) => {
+                let ordering = c.to_lowercase().cmp(d.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                x.next();
+                y.next();
+            }
+        }
+    }
+
+}
+
+define_encode_set! {
+    // A value of the query string
+    pub VALUE_ENCODE_SET = [QUERY_ENCODE_SET] | {'%', '&', '+', '=', ';'}
+}
+
+// What the query string asks of a listing
+#[derive(Debug, Default)]
+pub struct View {
+    pub sort: Sort,
+    pub order: Order,
+    // ?q=, a glob with '*' and '?', or part of the name
+    pub filter: Option<String>,
+    // ?page=, from 1
+    pub page: usize
+}
+
+impl View {
+
+    // From ?sort=size&order=desc&q=nightly&page=2, the configured defaults otherwise
+    pub fn of(request: &Request, option: &DirectoryOption) -> View {
+        let sort = request.param("sort").and_then(|value| Sort::new(&value).ok());
+        let order = request.param("order").and_then(|value| Order::new(&value).ok());
+        let filter = request.param("q")
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let page = request.param("page").and_then(|value| value.parse().ok()).unwrap_or(1);
+        View {
+            sort: sort.unwrap_or(option.sort),
+            order: order.unwrap_or(option.order),
+            filter,
+            page: page.max(1)
+        }
+    }
+
+    // Query string of a link, the filter is kept
+    fn query(&self, sort: Sort, order: Order, page: usize) -> String {
+        let mut out = format!("?sort={}&order={}", sort.as_str(), order.as_str());
+        if let Some(filter) = &self.filter {
+            let _ = write!(out, "&q={}", utf8_percent_encode(filter, VALUE_ENCODE_SET));
+        }
+        if page > 1 {
+            let _ = write!(out, "&page={}", page);
+        }
+        out
+    }
+
+}
+
+// Entries the filter lets through, case doesn't matter
+pub fn filter(entries: Vec<Entry>, filter: Option<&str>) -> Vec<Entry> {
+    let filter = match filter {
+        Some(filter) => filter.to_lowercase(),
+        None => return entries
+    };
+    let glob = filter.contains(['*', '?']);
+    entries.into_iter()
+        .filter(|entry| {
+            let name = entry.name.to_lowercase();
+            if glob { matches(&filter, &name) } else { name.contains(&filter) }
+        })
+        .collect()
+}
+
+// '*' is any run of characters, '?' any one of them
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last '*' was, and the name position it stands in for
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        }else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        }else if let Some((at, from)) = star {
+            // The '*' takes one more character
+            p = at + 1;
+            n = from + 1;
+            star = Some((at, from + 1));
+        }else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// The entries of the page, and how many pages there are
+pub fn paginate(entries: &[Entry], page: usize, size: Option<usize>) -> (&[Entry], usize) {
+    let size = match size {
+        Some(size) if entries.len() > size => size,
+        _ => return (entries, 1)
+    };
+    let pages = entries.len().div_ceil(size);
+    // Past the end shows the last page
+    let start = (page.min(pages) - 1) * size;
+    (&entries[start..(start + size).min(entries.len())], pages)
+}
+
+// `entries` are filtered but not paged yet.
+// `readme` is HTML, shown below the files, `upload` adds a form for POST
+pub fn html(
+    entries: &[Entry],
+    title: &str,
+    option: &DirectoryOption,
+    view: &View,
+    readme: Option<&str>,
+    upload: bool
+) -> String {
+
+    let (entries, pages) = paginate(entries, view.page, option.page_size);
+    let page = view.page.min(pages);
+
+    if let Some(template) = &option.template {
+        return template.render(&context(entries, title, option, view, (page, pages), readme, upload));
+    }
+
+    let (show_time, show_size) = (option.time, option.size);
+
+    let (
+        mut files,
+        mut main,
+        mut first
+    ) = (
+        String::new(),
+        "auto auto 1fr",
+        "1 / 4"
+    );
+
+    if !show_time && !show_size{
+        main = "auto";
+        first = "1 / 2";
+    }else if (!show_time && show_size) || (show_time && !show_size) {
+        main = "auto 1fr";
+        first = "1 / 3";
+    }
+
+    let mut head = heading(Sort::Name, "Name", "sort", view);
+    if show_time {
+        head.push_str(&heading(Sort::Time, "Last modified", "sort column", view));
+    }
+    if show_size {
+        head.push_str(&heading(Sort::Size, "Size", "sort column", view));
+    }
+
+    for entry in entries {
+
+        // Names may hold anything but '/', in a link they are one path segment
+        let mut href = utf8_percent_encode(&entry.name, PATH_SEGMENT_ENCODE_SET).to_string();
+        let mut text = html::escape(&entry.name);
+        if entry.dir {
+            href.push('/');
+            text.push('/');
+        }
+
+        let _ = write!(files, "<a href=\"{}\">{}</a>", html::escape(&href), text);
+
+        if show_time {
+            let time = match entry.modified {
+                Some(time) => {
+                    let datetime: DateTime<Local> = DateTime::from(time);
+                    datetime.format("%Y-%m-%d %H:%M").to_string()
+                },
+                None => String::new()
+            };
+            let _ = write!(files, "<time>{}</time>", time);
+        }
+        if show_size {
+            let size = if entry.dir {
+                String::new()
+            }else {
+                bytes_to_size(entry.size as f64)
+            };
+            let _ = write!(files, "<span>{}</span>", size);
+        }
+
+    }
+
+    TEMPLATE
+        .replace("{title}", &html::escape(title))
+        .replace("{main}", main)
+        .replace("{first}", first)
+        .replace("{head}", &head)
+        .replace("{files}", &files)
+        .replace("{upload}", if upload { UPLOAD } else { "" })
+        .replace("{search}", &search(view))
+        .replace("{pages}", &navigation(view, page, pages))
+        .replace("{readme}", &readme.map_or(String::new(), |readme| format!("<article class=\"readme\">\n{}</article>", readme)))
+
+}
+
+// [{"name", "type", "size", "mtime", "link"}], links are absolute paths
+pub fn json(entries: &[Entry], path: &str) -> String {
+
+    let base = encode_path(path);
+    let mut out = String::from("[");
+
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str("{\"name\":");
+        log::escape(&mut out, &entry.name);
+        let kind = if entry.dir { "directory" } else { "file" };
+        let _ = write!(out, ",\"type\":\"{}\",\"size\":{},\"mtime\":", kind, entry.size);
+        match entry.modified {
+            Some(time) => {
+                let datetime: DateTime<Local> = DateTime::from(time);
+                let _ = write!(out, "\"{}\"", datetime.to_rfc3339());
+            },
+            None => out.push_str("null")
+        }
+        out.push_str(",\"link\":");
+        log::escape(&mut out, &link(&base, entry));
+        out.push('}');
+    }
+
+    out.push(']');
+    out
+
+}
+
+// What a user template gets to see
+fn context(
+    entries: &[Entry],
+    path: &str,
+    option: &DirectoryOption,
+    view: &View,
+    (page, pages): (usize, usize),
+    readme: Option<&str>,
+    upload: bool
+) -> Value {
+
+    let base = encode_path(path);
+
+    // /a/b/ -> a, b
+    let mut href = String::from("/");
+    let breadcrumbs = path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            let _ = write!(href, "{}/", utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET));
+            object(vec![
+                ("name", Value::Str(segment.to_string())),
+                ("href", Value::Str(href.clone()))
+            ])
+        })
+        .collect();
+
+    let entries = entries.iter()
+        .map(|entry| {
+            let link = link(&base, entry);
+            object(vec![
+                ("name", Value::Str(entry.name.clone())),
+                ("href", Value::Str(link[base.len()..].to_string())),
+                ("link", Value::Str(link)),
+                ("dir", Value::Bool(entry.dir)),
+                ("size", Value::Num(entry.size)),
+                ("mtime", Value::Time(entry.modified))
+            ])
+        })
+        .collect();
+
+    object(vec![
+        ("title", Value::Str(path.to_string())),
+        ("root", Value::Bool(base == "/")),
+        ("breadcrumbs", Value::List(breadcrumbs)),
+        ("entries", Value::List(entries)),
+        ("sort", Value::Str(view.sort.as_str().to_string())),
+        ("order", Value::Str(view.order.as_str().to_string())),
+        ("query", Value::Str(view.filter.clone().unwrap_or_default())),
+        ("page", Value::Num(page as u64)),
+        ("pages", Value::Num(pages as u64)),
+        ("previous", Value::Str(if page > 1 { view.query(view.sort, view.order, page - 1) } else { String::new() })),
+        ("next", Value::Str(if page < pages { view.query(view.sort, view.order, page + 1) } else { String::new() })),
+        ("time", Value::Bool(option.time)),
+        ("size", Value::Bool(option.size)),
+        ("readme", Value::Str(readme.unwrap_or("").to_string())),
+        ("upload", Value::Bool(upload))
+    ])
+
+}
+
+// README.md of the directory as HTML, when the client may read it
+pub fn readme<F: Fn(&str) -> bool>(dir: &Path, entries: &[Entry], readable: F) -> Option<String> {
+    let entry = entries.iter()
+        .find(|entry| !entry.dir && entry.name.eq_ignore_ascii_case("readme.md"))
+        .filter(|entry| entry.size <= markdown::MAX && readable(&entry.name))?;
+    let source = fs::read(dir.join(&entry.name)).ok()?;
+    Some(markdown::html(&String::from_utf8_lossy(&source)))
+}
+
+// One name a line, directories end with '/'
+pub fn text(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(&entry.name);
+        if entry.dir {
+            out.push('/');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn link(base: &str, entry: &Entry) -> String {
+    let mut link = format!("{}{}", base, utf8_percent_encode(&entry.name, PATH_SEGMENT_ENCODE_SET));
+    if entry.dir {
+        link.push('/');
+    }
+    link
+}
+
+// Each segment of a decoded path, ending with '/'
+fn encode_path(path: &str) -> String {
+    let mut out = String::from("/");
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let _ = write!(out, "{}/", utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET));
+    }
+    out
+}
+
+// Column header, a second click on the current column reverses the order.
+// Sorting starts over at the first page
+fn heading(column: Sort, text: &str, class: &str, view: &View) -> String {
+    let (next, arrow) = match (column == view.sort, view.order) {
+        (true, Order::Asc) => (Order::Desc, " \u{2191}"),
+        (true, Order::Desc) => (Order::Asc, " \u{2193}"),
+        (false, _) => (Order::Asc, "")
+    };
+    format!(
+        "<a class=\"{}\" href=\"{}\">{}{}</a>",
+        class, html::escape(&view.query(column, next, 1)), text, arrow
+    )
+}
+
+// The search box keeps the sorting
+fn search(view: &View) -> String {
+    SEARCH
+        .replace("{q}", &html::escape(view.filter.as_deref().unwrap_or("")))
+        .replace("{sort}", view.sort.as_str())
+        .replace("{order}", view.order.as_str())
+}
+
+// Links to the pages around this one, nothing for a single page
+fn navigation(view: &View, page: usize, pages: usize) -> String {
+    if pages <= 1 {
+        return String::new();
+    }
+    let mut out = String::from("<nav class=\"pages\">");
+    if page > 1 {
+        let _ = write!(out, "<a href=\"{}\">&larr; Previous</a>", html::escape(&view.query(view.sort, view.order, page - 1)));
+    }
+    let _ = write!(out, "<span>Page {} of {}</span>", page, pages);
+    if page < pages {
+        let _ = write!(out, "<a href=\"{}\">Next &rarr;</a>", html::escape(&view.query(view.sort, view.order, page + 1)));
+    }
+    out.push_str("</nav>");
+    out
+}
+
+pub fn bytes_to_size(bytes: f64) -> String {
+    let k = 1024_f64;
+    let sizes = ["B", "KB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
+    if bytes <= 1_f64 {
+        return format!("{:.2} B", bytes)
+    }
+    let i = (bytes.ln() / k.ln()) as i32;
+    format!("{:.2} {}", bytes / k.powi(i), sizes[i as usize])
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::{Duration, SystemTime};
+    use crate::listing::{self, bytes_to_size, Entry, Order, Sort};
+
+    fn entry(name: &str, dir: bool, size: u64, age: u64) -> Entry {
+        Entry {
+            name: name.to_string(),
+            dir,
+            size,
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1000 - age))
+        }
+    }
+
+    fn names(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut entries = vec![
+            entry("file10.txt", false, 5, 1),
+            entry("b", true, 0, 2),
+            entry("File2.txt", false, 50, 3),
+            entry("file2.txt", false, 7, 4),
+            entry("a", true, 0, 5),
+            entry("file1.txt", false, 6, 6)
+        ];
+
+        listing::sort(&mut entries, Sort::Name, Order::Asc);
+        assert_eq!(names(&entries), ["a", "b", "file1.txt", "File2.txt", "file2.txt", "file10.txt"]);
+
+        listing::sort(&mut entries, Sort::Name, Order::Desc);
+        assert_eq!(names(&entries), ["b", "a", "file10.txt", "file2.txt", "File2.txt", "file1.txt"]);
+
+        listing::sort(&mut entries, Sort::Size, Order::Desc);
+        assert_eq!(names(&entries), ["b", "a", "File2.txt", "file2.txt", "file1.txt", "file10.txt"]);
+
+        listing::sort(&mut entries, Sort::Time, Order::Asc);
+        assert_eq!(names(&entries), ["a", "b", "file1.txt", "file2.txt", "File2.txt", "file10.txt"]);
+    }
+
+    #[test]
+    fn test_formats() {
+        let entries = vec![
+            entry("sub dir", true, 0, 0),
+            entry("a\"b.txt", false, 12, 0)
+        ];
+        let json = listing::json(&entries, "/builds/v1.0");
+        assert!(json.starts_with("[{\"name\":\"sub dir\",\"type\":\"directory\",\"size\":0,\"mtime\":\"1970-01-01T"));
+        assert!(json.contains("\"link\":\"/builds/v1.0/sub%20dir/\"}"));
+        assert!(json.contains("{\"name\":\"a\\\"b.txt\",\"type\":\"file\",\"size\":12,"));
+        assert!(json.ends_with("\"link\":\"/builds/v1.0/a%22b.txt\"}]"));
+        assert_eq!(listing::json(&[], "/"), "[]");
+
+        assert_eq!(listing::text(&entries), "sub dir/\na\"b.txt\n");
+    }
+
+    #[test]
+    fn test_filter() {
+        let entries = || vec![
+            entry("nightly-2024-01-02.tar.gz", false, 1, 0),
+            entry("Nightly-2024-02-01.zip", false, 1, 0),
+            entry("release", true, 0, 0)
+        ];
+        assert_eq!(names(&listing::filter(entries(), Some("NIGHTLY"))), ["nightly-2024-01-02.tar.gz", "Nightly-2024-02-01.zip"]);
+        assert_eq!(names(&listing::filter(entries(), Some("*.zip"))), ["Nightly-2024-02-01.zip"]);
+        assert_eq!(names(&listing::filter(entries(), Some("nightly-2024-0?-0*"))).len(), 2);
+        assert_eq!(names(&listing::filter(entries(), Some("rel"))), ["release"]);
+        assert_eq!(listing::filter(entries(), None).len(), 3);
+
+        assert!(listing::matches("*a*b*", "xaxxb"));
+        assert!(listing::matches("**", ""));
+        assert!(!listing::matches("a?", "a"));
+        assert!(!listing::matches("*.gz", "a.gz.zip"));
+    }
+
+    #[test]
+    fn test_paginate() {
+        let entries: Vec<Entry> = (0..5).map(|i| entry(&i.to_string(), false, 0, 0)).collect();
+        let (page, pages) = listing::paginate(&entries, 2, Some(2));
+        assert_eq!((names(page), pages), (vec!["2", "3"], 3));
+        assert_eq!(names(listing::paginate(&entries, 9, Some(2)).0), ["4"]);
+        assert_eq!(listing::paginate(&entries, 2, Some(5)).1, 1);
+        assert_eq!(listing::paginate(&entries, 2, None).0.len(), 5);
+    }
+
+    #[test]
+    fn test_bytes_to_size() {
+        assert_eq!(bytes_to_size(0_f64), "0.00 B");
+        assert_eq!(bytes_to_size(0.5_f64), "0.50 B");
+        assert_eq!(bytes_to_size(1_f64), "1.00 B");
+        assert_eq!(bytes_to_size(12_f64), "12.00 B");
+        assert_eq!(bytes_to_size(1024_f64), "1.00 KB");
+        assert_eq!(bytes_to_size(1025_f64), "1.00 KB");
+        assert_eq!(bytes_to_size(123456_f64), "120.56 KB");
+        assert_eq!(bytes_to_size(99999999_f64), "95.37 MB");
+        assert_eq!(bytes_to_size(99999999999_f64), "93.13 GB");
+    }
+
+}
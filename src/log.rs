@@ -1,16 +1,174 @@
-
-
 use std::fs::OpenOptions;
 use std::io::prelude::*;
+use std::io;
+use std::io::BufWriter;
 use std::fs::File;
 use std::path::Path;
 use std::fs;
-use chrono::{DateTime, prelude, Local};
+use std::fmt;
+use std::fmt::Write as FmtWrite;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex, OnceLock, RwLock, RwLockReadGuard, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local, NaiveDate};
+use crate::compress;
+use crate::request::Request;
+use crate::response::Sent;
+use crate::syslog::{self, Syslog};
+use crate::status::{self, Kind};
+use crate::fill_path;
 
 
 #[derive(Debug)]
 pub struct Log {
-    file: Option<File>
+    target: Target,
+    // Set by `open`, `None` when the file couldn't be opened
+    writer: OnceLock<Option<Arc<Writer>>>,
+    options: Options
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub format: Format,
+    // Lines waiting for the writer
+    pub buffer: usize,
+    // Longest time a line stays in memory
+    pub flush: Duration,
+    pub overflow: Overflow,
+    pub rotate: Rotate
+}
+
+// When the file is moved aside for a new one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rotate {
+    // In bytes
+    pub size: Option<u64>,
+    pub daily: bool,
+    // Rotated files kept, the oldest are deleted
+    pub keep: usize,
+    pub compress: bool
+}
+
+// What to do with a line when the buffer is full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Drop,
+    // Wait for the writer, slowing the request down
+    Block
+}
+
+pub const DEFAULT_BUFFER: usize = 1024;
+pub const DEFAULT_FLUSH: Duration = Duration::from_secs(1);
+pub const DEFAULT_KEEP: usize = 7;
+
+// Layout of an access log line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Template(Vec<Token>),
+    // One JSON object per line
+    Json
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Text(String),
+    Var(Var)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Var {
+    RemoteAddr,
+    RemotePort,
+    RemoteUser,
+    TimeLocal,
+    TimeIso8601,
+    Request,
+    RequestMethod,
+    RequestUri,
+    Uri,
+    Args,
+    Protocol,
+    Host,
+    Status,
+    BytesSent,
+    BodyBytesSent,
+    RequestTime,
+    // $http_user_agent -> user-agent
+    Header(String)
+}
+
+const COMMON: &str = "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent";
+const COMBINED: &str = "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent \"$http_referer\" \"$http_user_agent\"";
+
+// One answered request
+pub struct Access<'a> {
+    pub request: &'a Request,
+    pub remote: Option<SocketAddr>,
+    pub sent: Sent,
+    // From accepting the connection to the end of the response
+    pub duration: Duration,
+    pub time: DateTime<Local>
+}
+
+// How much goes to the error log
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error
+}
+
+// The process-wide error log, see `init`
+struct Diagnostic {
+    writer: Arc<Writer>,
+    level: Level
+}
+
+// Until it is set up, warnings and errors go straight to stderr
+static DIAGNOSTIC: RwLock<Option<Diagnostic>> = RwLock::new(None);
+
+// Every live writer, flushed before the process exits and shared by the logs of one file
+static WRITERS: Mutex<Vec<Weak<Writer>>> = Mutex::new(Vec::new());
+
+// Where the lines of a log go
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    File(String),
+    Stdout,
+    Stderr,
+    // The local socket when `None`
+    Syslog(Option<SocketAddr>)
+}
+
+enum Message {
+    // With its syslog severity
+    Line(String, u8),
+    // Write out everything received so far, then answer
+    Flush(mpsc::Sender<()>),
+    // Open the file again, after it was moved or deleted
+    Reopen,
+    // A reload changed the flush interval or rotation, and with the buffer
+    // the channel the lines come through from now on
+    Configure(Duration, Rotate, Option<mpsc::Receiver<Message>>),
+    Close
+}
+
+// The background thread owning one log file
+#[derive(Debug)]
+struct Writer {
+    // The resolved path of a file, `None` for writers that aren't shared
+    key: Option<String>,
+    // Replaced when a reload changes the buffer
+    sender: RwLock<mpsc::SyncSender<Message>>,
+    // Buffer, flush and rotate in effect, see `Options::shares`
+    options: Mutex<Options>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+    // Lines lost to a full buffer
+    dropped: Arc<AtomicU64>
 }
 
 
@@ -46,40 +204,1103 @@ fn create_log_file(path: String) -> Option<File> {
 
 impl Log {
 
-    pub fn new(path: String) -> Log {
+    // Nothing is created until `open`, checking a configuration leaves no trace
+    pub fn new(target: Target, options: Options) -> Log {
         Log {
-            file: create_log_file(path)
+            target,
+            writer: OnceLock::new(),
+            options
         }
     }
 
-    pub fn write(&self, method: &str, status: i32, path: &str)  {
+    // Start the writer, once the configuration is the one in use
+    pub fn open(&self) {
+        self.writer.get_or_init(|| match Writer::open(&self.target, &self.options, "access") {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                eprintln!("Couldn't open the log {}: {}", self.target, e);
+                None
+            }
+        });
+    }
 
-        let mut file = if let Some(file) = &self.file {
-            file.try_clone().unwrap()
-        }else {
+    pub fn write(&self, access: &Access)  {
+
+        let writer = match self.writer.get() {
+            Some(Some(writer)) => writer,
+            _ => return
+        };
+        let severity = match access.sent.status {
+            500..=599 => syslog::ERR,
+            400..=499 => syslog::WARNING,
+            _ => syslog::INFO
+        };
+        let line = Message::Line(self.options.format.line(access), severity);
+
+        let sender = writer.sender();
+        match self.options.overflow {
+            Overflow::Block => {
+                let _ = sender.send(line);
+            },
+            Overflow::Drop => {
+                if let Err(mpsc::TrySendError::Full(_)) = sender.try_send(line) {
+                    writer.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+    }
+
+}
+
+
+// Write out the lines every log is holding
+pub fn flush() {
+
+    let writers: Vec<Arc<Writer>> = WRITERS.lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+
+    for writer in writers {
+        writer.flush();
+    }
+
+}
+
+// Send the error log to `target`, dropping messages below `level`
+pub fn init(target: &Target, level: Level) {
+
+    let writer = match Writer::open(target, &Options::default(), "error") {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("Couldn't open the error log {}: {}", target, e);
             return;
+        }
+    };
+
+    *DIAGNOSTIC.write().unwrap_or_else(|err| err.into_inner()) = Some(Diagnostic {
+        writer,
+        level
+    });
+
+}
+
+pub fn error(message: fmt::Arguments) {
+    status::error(Kind::Logged);
+    diagnose(Level::Error, message);
+}
+
+pub fn warn(message: fmt::Arguments) {
+    diagnose(Level::Warn, message);
+}
+
+pub fn info(message: fmt::Arguments) {
+    diagnose(Level::Info, message);
+}
+
+pub fn debug(message: fmt::Arguments) {
+    diagnose(Level::Debug, message);
+}
+
+fn diagnose(level: Level, message: fmt::Arguments) {
+
+    let diagnostic = DIAGNOSTIC.read().unwrap_or_else(|err| err.into_inner());
+    let enabled = match &*diagnostic {
+        Some(diagnostic) => level >= diagnostic.level,
+        None => level >= Level::Warn
+    };
+    if !enabled {
+        return;
+    }
+
+    let line = format!("{} [{}] {}", Local::now().format("%Y/%m/%d %H:%M:%S"), level.name(), message);
+    match &*diagnostic {
+        Some(diagnostic) => {
+            let _ = diagnostic.writer.sender().send(Message::Line(line, level.severity()));
+        },
+        None => eprintln!("{}", line)
+    }
+
+}
+
+// Open every log file again, see `kill -USR1`
+pub fn reopen() {
+
+    let writers: Vec<Arc<Writer>> = WRITERS.lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+
+    for writer in writers {
+        let _ = writer.sender().send(Message::Reopen);
+    }
+
+}
+
+
+impl Writer {
+
+    // The writer of a file is reused by every server and reload logging to it,
+    // a reload brings its buffer, flush and rotate options along
+    fn open(target: &Target, options: &Options, msg_id: &'static str) -> io::Result<Arc<Writer>> {
+
+        let key = target.key();
+        let mut writers = WRITERS.lock().unwrap_or_else(|err| err.into_inner());
+        writers.retain(|w| w.strong_count() > 0);
+
+        if key.is_some() {
+            let shared = writers.iter()
+                .filter_map(Weak::upgrade)
+                .find(|writer| writer.key == key);
+            if let Some(writer) = shared {
+                writer.configure(options);
+                return Ok(writer);
+            }
+        }
+
+        let sink = Sink::open(target, &options.rotate, msg_id)?;
+        let writer = Writer::spawn(sink, options, key);
+        writers.push(Arc::downgrade(&writer));
+        Ok(writer)
+
+    }
+
+    fn spawn(sink: Sink, options: &Options, key: Option<String>) -> Arc<Writer> {
+
+        let (sender, receiver) = mpsc::sync_channel(options.buffer);
+        let interval = options.flush;
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&dropped);
+
+        let thread = thread::Builder::new()
+            .name(String::from("log-writer"))
+            .spawn(move || write_lines(sink, receiver, interval, &counter))
+            .expect("failed to spawn log writer thread");
+
+        Arc::new(Writer {
+            key,
+            sender: RwLock::new(sender),
+            options: Mutex::new(options.clone()),
+            thread: Mutex::new(Some(thread)),
+            dropped
+        })
+
+    }
+
+    fn sender(&self) -> RwLockReadGuard<'_, mpsc::SyncSender<Message>> {
+        self.sender.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // Take the options of a reloaded log, lines already sent are written the old way
+    fn configure(&self, options: &Options) {
+
+        let mut current = self.options.lock().unwrap_or_else(|err| err.into_inner());
+        if current.shares(options) {
+            return;
+        }
+        let mut sender = self.sender.write().unwrap_or_else(|err| err.into_inner());
+        let (previous, receiver) = if current.buffer != options.buffer {
+            let (next, receiver) = mpsc::sync_channel(options.buffer);
+            (mem::replace(&mut *sender, next), Some(receiver))
+        }else {
+            (sender.clone(), None)
+        };
+        // Behind every line of the previous channel
+        let _ = previous.send(Message::Configure(options.flush, options.rotate.clone(), receiver));
+        *current = options.clone();
+
+    }
+
+    fn flush(&self) {
+
+        let (done, wait) = mpsc::channel();
+        if self.sender().send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+
+    }
+
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let _ = self.sender().send(Message::Close);
+        let thread = self.thread.lock().unwrap_or_else(|err| err.into_inner()).take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+}
+
+
+// Batch lines in memory, writing them out at most `interval` after they arrive
+fn write_lines(
+    mut sink: Sink,
+    mut receiver: mpsc::Receiver<Message>,
+    mut interval: Duration,
+    dropped: &AtomicU64
+) {
+
+    // When the oldest unwritten line is due
+    let mut due: Option<Instant> = None;
+    let mut failed = false;
+    let mut reported = 0;
+
+    loop {
+
+        let lost = dropped.load(Ordering::Relaxed);
+        if lost > reported {
+            eprintln!("Log buffer of {} full, {} lines dropped", sink, lost - reported);
+            reported = lost;
+        }
+
+        let message = match due {
+            Some(due) => receiver.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+        };
+
+        let result = match message {
+            Ok(Message::Line(line, severity)) => {
+                if due.is_none() {
+                    due = Some(Instant::now() + interval);
+                }
+                sink.write(&line, severity)
+            },
+            Ok(Message::Flush(done)) => {
+                due = None;
+                let result = sink.flush();
+                let _ = done.send(());
+                result
+            },
+            Ok(Message::Reopen) => {
+                due = None;
+                sink.reopen()
+            },
+            Ok(Message::Configure(flush, rotate, next)) => {
+                interval = flush;
+                if let Sink::File(file) = &mut sink {
+                    file.rotate = rotate;
+                }
+                if let Some(next) = next {
+                    receiver = next;
+                }
+                Ok(())
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                due = None;
+                sink.flush()
+            },
+            Ok(Message::Close) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let _ = sink.flush();
+                return;
+            }
+        };
+
+        // Reported once, the file may come back later
+        match result {
+            Err(e) if !failed => {
+                eprintln!("Couldn't write to {}: {}", sink, e);
+                failed = true;
+            },
+            Ok(()) => failed = false,
+            _ => {}
+        }
+
+    }
+
+}
+
+
+enum Sink {
+    File(LogFile),
+    Console(BufWriter<Box<dyn Write + Send>>, &'static str),
+    Syslog(Syslog, &'static str)
+}
+
+impl Sink {
+
+    // `msg_id` tells syslog which log the lines come from
+    fn open(target: &Target, rotate: &Rotate, msg_id: &'static str) -> io::Result<Sink> {
+        let sink = match target {
+            Target::File(path) => {
+                let file = match create_log_file(path.clone()) {
+                    Some(file) => file,
+                    None => return Err(io::Error::other("can't create the file"))
+                };
+                Sink::File(LogFile::new(path.clone(), file, rotate.clone()))
+            },
+            Target::Stdout => Sink::Console(BufWriter::new(Box::new(io::stdout())), "stdout"),
+            Target::Stderr => Sink::Console(BufWriter::new(Box::new(io::stderr())), "stderr"),
+            Target::Syslog(addr) => Sink::Syslog(Syslog::connect(*addr)?, msg_id)
+        };
+        Ok(sink)
+    }
+
+    fn write(&mut self, line: &str, severity: u8) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.write(line),
+            Sink::Console(out, _) => writeln!(out, "{}", line),
+            Sink::Syslog(syslog, msg_id) => syslog.send(severity, msg_id, line)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.flush(),
+            Sink::Console(out, _) => out.flush(),
+            Sink::Syslog(..) => Ok(())
+        }
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.reopen(),
+            _ => self.flush()
+        }
+    }
+
+}
+
+impl fmt::Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sink::File(file) => write!(f, "{}", file.path),
+            Sink::Console(_, name) => write!(f, "{}", name),
+            Sink::Syslog(..) => write!(f, "syslog")
+        }
+    }
+}
+
+
+// The file behind a log, moved aside when it grows too large or the day changes
+struct LogFile {
+    path: String,
+    // `None` after the file couldn't be opened again
+    out: Option<BufWriter<File>>,
+    size: u64,
+    // Local date of the first line in the file
+    day: NaiveDate,
+    rotate: Rotate
+}
+
+impl LogFile {
+
+    fn new(path: String, file: File, rotate: Rotate) -> LogFile {
+
+        let mut output = LogFile {
+            path,
+            out: None,
+            size: 0,
+            day: Local::today().naive_local(),
+            rotate
+        };
+        output.open(file);
+        output
+
+    }
+
+    fn open(&mut self, file: File) {
+
+        if let Ok(meta) = file.metadata() {
+            self.size = meta.len();
+            // Started on another day, rotate before the first line
+            if let Ok(modified) = meta.modified() {
+                if self.size > 0 {
+                    let modified: DateTime<Local> = DateTime::from(modified);
+                    self.day = modified.date().naive_local();
+                }
+            }
+        }
+        self.out = Some(BufWriter::with_capacity(64 * 1024, file));
+
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+
+        // The last reopen failed, the line still goes out if this one works
+        if self.out.is_none() {
+            self.reopen()?;
+        }
+        let today = Local::today().naive_local();
+        let full = match self.rotate.size {
+            Some(max) => self.size > 0 && self.size + line.len() as u64 + 1 > max,
+            None => false
         };
-        let method = String::from(method);
-        let path = String::from(path);
+        if full || (self.rotate.daily && today != self.day && self.size > 0) {
+            self.rotate()?;
+        }
+        if self.size == 0 {
+            self.day = today;
+        }
+
+        match &mut self.out {
+            Some(out) => {
+                writeln!(out, "{}", line)?;
+                self.size += line.len() as u64 + 1;
+                Ok(())
+            },
+            None => Err(io::Error::other("the file is closed"))
+        }
+
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.out {
+            Some(out) => out.flush(),
+            None => Ok(())
+        }
+    }
+
+    // The file may have been moved away by logrotate
+    fn reopen(&mut self) -> io::Result<()> {
+
+        let _ = self.flush();
+        self.out = None;
+        match create_log_file(self.path.clone()) {
+            Some(file) => {
+                self.open(file);
+                Ok(())
+            },
+            None => Err(io::Error::other("can't open the file"))
+        }
+
+    }
+
+    // success.log -> success.log.1 -> ... -> success.log.{keep}
+    fn rotate(&mut self) -> io::Result<()> {
+
+        self.flush()?;
+        self.out = None;
+
+        let ext = if self.rotate.compress { ".gz" } else { "" };
+        let name = |n: usize| format!("{}.{}{}", self.path, n, ext);
+
+        let _ = fs::remove_file(name(self.rotate.keep));
+        for n in (1..self.rotate.keep).rev() {
+            let _ = fs::rename(name(n), name(n + 1));
+        }
+
+        let first = format!("{}.1", self.path);
+        fs::rename(&self.path, &first)?;
+        if self.rotate.compress {
+            match compress::gzip_file(&first, &name(1)) {
+                Ok(()) => {
+                    let _ = fs::remove_file(&first);
+                },
+                Err(e) => eprintln!("Couldn't compress {}: {}", first, e)
+            }
+        }
+
+        self.reopen()
+
+    }
+
+}
+
+
+impl Level {
+
+    pub fn new(value: &str) -> Result<Level, String> {
+        match value {
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _ => Err(format!("Wrong log level \"{}\"", value))
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error"
+        }
+    }
+
+    fn severity(self) -> u8 {
+        match self {
+            Level::Debug => syslog::DEBUG,
+            Level::Info => syslog::INFO,
+            Level::Warn => syslog::WARNING,
+            Level::Error => syslog::ERR
+        }
+    }
+
+}
+
 
-        std::thread::spawn(move || {
-            let time: DateTime<Local> = prelude::Local::now();
-            if let Err(e) = writeln!(file, "{0}  {1: <6}  {2}  {3}", time, method, status, path) {
-                eprintln!("Couldn't write to file: {}", e);
+impl Target {
+
+    // "stdout", "stderr", "syslog", "syslog://127.0.0.1:514" or a file path
+    pub fn new(value: &str, root: &str) -> Result<Target, String> {
+        let target = match value {
+            "stdout" => Target::Stdout,
+            "stderr" => Target::Stderr,
+            "syslog" => Target::Syslog(None),
+            _ => match value.strip_prefix("syslog://") {
+                Some(addr) => match addr.parse() {
+                    Ok(addr) => Target::Syslog(Some(addr)),
+                    Err(_) => return Err(format!("Wrong syslog address \"{}\"", addr))
+                },
+                None => Target::File(fill_path(root, value))
             }
+        };
+        Ok(target)
+    }
+
+    // Files by the path they resolve to, syslog messages don't share a writer
+    fn key(&self) -> Option<String> {
+        match self {
+            Target::File(path) => {
+                let path = Path::new(path);
+                let resolved = match (path.parent(), path.file_name()) {
+                    (Some(parent), Some(name)) => fs::canonicalize(parent).map(|parent| parent.join(name)),
+                    _ => fs::canonicalize(path)
+                };
+                let resolved = resolved.unwrap_or_else(|_| path.to_path_buf());
+                // A link to the file is the file
+                let resolved = fs::canonicalize(&resolved).unwrap_or(resolved);
+                Some(resolved.to_string_lossy().to_string())
+            },
+            Target::Stdout | Target::Stderr => Some(self.to_string()),
+            Target::Syslog(_) => None
+        }
+    }
+
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::File(path) => write!(f, "{}", path),
+            Target::Stdout => write!(f, "stdout"),
+            Target::Stderr => write!(f, "stderr"),
+            Target::Syslog(None) => write!(f, "syslog"),
+            Target::Syslog(Some(addr)) => write!(f, "syslog://{}", addr)
+        }
+    }
+}
+
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::new("combined").unwrap()
+    }
+}
+
+impl Options {
+
+    // Logs of one file share its writer, so they must agree on these,
+    // `format` and `overflow` are each log's own
+    fn shares(&self, other: &Options) -> bool {
+        self.buffer == other.buffer && self.flush == other.flush && self.rotate == other.rotate
+    }
+
+}
+
+// Logs of a configuration naming the same file must agree on how it is written
+pub fn check(logs: &[&Log]) -> Result<(), String> {
+    for (i, log) in logs.iter().enumerate() {
+        let key = log.target.key();
+        let conflict = key.is_some() && logs[i + 1..].iter().any(|other| {
+            other.target.key() == key && !other.options.shares(&log.options)
         });
+        if conflict {
+            return Err(format!("Logs to {} must agree on 'buffer', 'flush' and 'rotate'", log.target));
+        }
+    }
+    Ok(())
+}
 
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            format: Format::default(),
+            buffer: DEFAULT_BUFFER,
+            flush: DEFAULT_FLUSH,
+            overflow: Overflow::Block,
+            rotate: Rotate::default()
+        }
     }
+}
 
+impl Default for Rotate {
+    fn default() -> Self {
+        Rotate {
+            size: None,
+            daily: false,
+            keep: DEFAULT_KEEP,
+            compress: false
+        }
+    }
 }
 
+impl Format {
+
+    // "common", "combined", "json" or a template with $variables
+    pub fn new(value: &str) -> Result<Format, String> {
+        match value {
+            "common" => Format::template(COMMON),
+            "combined" => Format::template(COMBINED),
+            "json" => Ok(Format::Json),
+            template => Format::template(template)
+        }
+    }
+
+    fn template(template: &str) -> Result<Format, String> {
+
+        let mut tokens = vec![];
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                text.push(c);
+                continue;
+            }
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !c.is_ascii_alphanumeric() && c != '_' {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            if name.is_empty() {
+                text.push('$');
+                continue;
+            }
+            if !text.is_empty() {
+                tokens.push(Token::Text(text.split_off(0)));
+            }
+            tokens.push(Token::Var(Var::new(&name)?));
+        }
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+
+        Ok(Format::Template(tokens))
 
-#[test]
-fn test_log() {
-    let log = Log::new(String::from("./logs/test.log"));
-    log.write("GET", 200, "/api");
-    log.write("HEAD", 404, "/img");
-    log.write("DELETE", 500, "/img");
+    }
+
+    pub fn line(&self, access: &Access) -> String {
+        match self {
+            Format::Template(tokens) => {
+                let mut line = String::new();
+                for token in tokens {
+                    match token {
+                        Token::Text(text) => line.push_str(text),
+                        Token::Var(var) => match var.value(access) {
+                            Some(value) => quote(&mut line, &value),
+                            None => line.push('-')
+                        }
+                    }
+                }
+                line
+            },
+            Format::Json => json(access)
+        }
+    }
+
+}
+
+
+impl Var {
+
+    fn new(name: &str) -> Result<Var, String> {
+        let var = match name {
+            "remote_addr" => Var::RemoteAddr,
+            "remote_port" => Var::RemotePort,
+            "remote_user" => Var::RemoteUser,
+            "time_local" => Var::TimeLocal,
+            "time_iso8601" => Var::TimeIso8601,
+            "request" => Var::Request,
+            "request_method" => Var::RequestMethod,
+            "request_uri" => Var::RequestUri,
+            "uri" => Var::Uri,
+            "args" | "query_string" => Var::Args,
+            "server_protocol" => Var::Protocol,
+            "host" => Var::Host,
+            "status" => Var::Status,
+            "bytes_sent" => Var::BytesSent,
+            "body_bytes_sent" => Var::BodyBytesSent,
+            "request_time" => Var::RequestTime,
+            _ => match name.strip_prefix("http_") {
+                Some(header) if !header.is_empty() => Var::Header(header.replace('_', "-")),
+                _ => return Err(format!("Unknown log variable \"${}\"", name))
+            }
+        };
+        Ok(var)
+    }
+
+    // `None` when the request doesn't have it
+    fn value(&self, access: &Access) -> Option<String> {
+        let request = access.request;
+        match self {
+            Var::RemoteAddr => access.remote.map(|addr| addr.ip().to_string()),
+            Var::RemotePort => access.remote.map(|addr| addr.port().to_string()),
+            Var::RemoteUser => remote_user(request),
+            Var::TimeLocal => Some(access.time.format("%d/%b/%Y:%H:%M:%S %z").to_string()),
+            Var::TimeIso8601 => Some(access.time.format("%Y-%m-%dT%H:%M:%S%:z").to_string()),
+            Var::Request => Some(format!("{} {} {}", request.method, request.uri, request.version)),
+            Var::RequestMethod => Some(request.method.clone()),
+            Var::RequestUri => Some(request.uri.clone()),
+            Var::Uri => Some(request.path.clone()),
+            Var::Args => request.query.as_ref().map(|q| q.trim_start_matches('?').to_string()),
+            Var::Protocol => Some(request.version.clone()),
+            Var::Host => request.headers.get("host").map(|host| host_name(host).to_string()),
+            Var::Status => Some(access.sent.status.to_string()),
+            Var::BytesSent => Some(access.sent.bytes().to_string()),
+            Var::BodyBytesSent => Some(access.sent.body.to_string()),
+            Var::RequestTime => Some(format!("{:.3}", access.duration.as_secs_f64())),
+            Var::Header(name) => request.headers.get(name).cloned()
+        }
+    }
+
+}
+
+
+// User name of the basic authorization, whether it was accepted or not
+fn remote_user(request: &Request) -> Option<String> {
+    let value = request.headers.get("authorization")?;
+    let credentials = value.strip_prefix("Basic ")?;
+    let decoded = base64::decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    decoded.split(':').next().map(|user| user.to_string())
+}
+
+// Host header without the port
+fn host_name(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(i) if host[i + 1..].chars().all(|c| c.is_ascii_digit()) => &host[..i],
+        _ => host
+    }
 }
 
+fn json(access: &Access) -> String {
 
+    let fields = [
+        ("time", Var::TimeIso8601),
+        ("remote_addr", Var::RemoteAddr),
+        ("remote_user", Var::RemoteUser),
+        ("method", Var::RequestMethod),
+        ("uri", Var::RequestUri),
+        ("protocol", Var::Protocol),
+        ("host", Var::Host),
+        ("status", Var::Status),
+        ("bytes_sent", Var::BytesSent),
+        ("body_bytes_sent", Var::BodyBytesSent),
+        ("request_time", Var::RequestTime),
+        ("referer", Var::Header("referer".to_string())),
+        ("user_agent", Var::Header("user-agent".to_string()))
+    ];
+
+    let mut line = String::from("{");
+    for (i, (key, var)) in fields.iter().enumerate() {
+        if i > 0 {
+            line.push(',');
+        }
+        let _ = write!(line, "\"{}\":", key);
+        match (var, var.value(access)) {
+            (_, None) => line.push_str("null"),
+            (Var::Status, Some(value))
+            | (Var::BytesSent, Some(value))
+            | (Var::BodyBytesSent, Some(value))
+            | (Var::RequestTime, Some(value)) => line.push_str(&value),
+            (_, Some(value)) => escape(&mut line, &value)
+        }
+    }
+    line.push('}');
+    line
+
+}
+
+// Like nginx, '"', '\' and control characters become \x22 so a value can't break out of its quotes
+fn quote(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            c if c == '"' || c == '\\' || c.is_ascii_control() => {
+                let _ = write!(out, "\\x{:02X}", c as u32);
+            },
+            c => out.push(c)
+        }
+    }
+}
+
+// Write `value` as a JSON string
+pub fn escape(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use chrono::{Local, TimeZone};
+    use crate::log::{self, Access, Format, Level, Log, LogFile, Options, Overflow, Rotate, Target};
+    use crate::request::Request;
+    use crate::response::Sent;
+
+    // Out of the source tree, one name per test as they run in parallel
+    fn temp(name: &str) -> String {
+        let path = env::temp_dir().join(format!("see-log-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        let _ = fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    fn open(target: Target, options: Options) -> Log {
+        let log = Log::new(target, options);
+        log.open();
+        log
+    }
+
+    fn writer(log: &Log) -> &Arc<log::Writer> {
+        log.writer.get().unwrap().as_ref().unwrap()
+    }
+
+    fn request() -> Request {
+        Request::new(b"\
+            GET /a%20b?page=2 HTTP/1.1\r\n\
+            Host: example.com:8080\r\n\
+            Referer: https://example.com/\r\n\
+            User-Agent: curl/7.64 \"quoted\"\r\n\
+            Authorization: Basic dXNlcjpwd2Q=\r\n\
+            \r\n\
+        ").unwrap()
+    }
+
+    fn access(request: &Request) -> Access<'_> {
+        Access {
+            request,
+            remote: Some("192.168.1.2:51000".parse().unwrap()),
+            sent: Sent {
+                status: 200,
+                header: 100,
+                body: 1234,
+                error: None
+            },
+            duration: Duration::from_millis(1500),
+            time: Local.ymd(2019, 4, 28).and_hms(10, 20, 30)
+        }
+    }
+
+    #[test]
+    fn test_log() {
+        let path = temp("test.log");
+        let log = open(Target::File(path.clone()), Options::default());
+        let request = request();
+        log.write(&access(&request));
+        drop(log);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_target() {
+        assert_eq!(Target::new("stdout", "/www"), Ok(Target::Stdout));
+        assert_eq!(Target::new("syslog", "/www"), Ok(Target::Syslog(None)));
+        assert_eq!(
+            Target::new("syslog://127.0.0.1:514", "/www"),
+            Ok(Target::Syslog(Some("127.0.0.1:514".parse().unwrap())))
+        );
+        assert!(Target::new("syslog://localhost", "/www").is_err());
+        assert_eq!(Target::new("logs/a.log", "/www"), Ok(Target::File(String::from("/www/logs/a.log"))));
+    }
+
+    #[test]
+    fn test_level() {
+        assert_eq!(Level::new("warn"), Ok(Level::Warn));
+        assert!(Level::new("warning").is_err());
+        assert!(Level::Debug < Level::Info && Level::Info < Level::Warn && Level::Warn < Level::Error);
+    }
+
+    #[test]
+    fn test_flush() {
+        let path = &temp("flush.log");
+        let log = open(Target::File(path.clone()), Options {
+            flush: Duration::from_secs(3600),
+            ..Options::default()
+        });
+        let request = request();
+        for _ in 0..10 {
+            log.write(&access(&request));
+        }
+        log::flush();
+        assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 10);
+        log.write(&access(&request));
+        // Closing the log writes out the rest
+        drop(log);
+        assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 11);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = temp("rotate");
+        let path = format!("{}/access.log", dir);
+        let log = open(Target::File(path.clone()), Options {
+            format: Format::new("$request_method $status").unwrap(),
+            rotate: Rotate {
+                size: Some(12),
+                daily: false,
+                keep: 2,
+                compress: true
+            },
+            ..Options::default()
+        });
+        let request = request();
+        for _ in 0..5 {
+            log.write(&access(&request));
+        }
+        drop(log);
+        // 2 lines per file, the first one is gone
+        assert_eq!(fs::read_to_string(&path).unwrap(), "GET 200\n");
+        assert!(fs::metadata(format!("{}.1.gz", path)).is_ok());
+        assert!(fs::metadata(format!("{}.2.gz", path)).is_ok());
+        assert!(fs::metadata(format!("{}.3.gz", path)).is_err());
+        assert!(fs::metadata(format!("{}.1", path)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shared_writer() {
+        let dir = temp("shared");
+        let a = open(Target::File(format!("{}/a.log", dir)), Options::default());
+        let b = open(Target::File(format!("{}/../{}/a.log", dir, dir.rsplit('/').next().unwrap())), Options::default());
+        let c = open(Target::File(format!("{}/c.log", dir)), Options::default());
+        assert!(Arc::ptr_eq(writer(&a), writer(&b)));
+        assert!(!Arc::ptr_eq(writer(&a), writer(&c)));
+        drop((a, b, c));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_options() {
+        let dir = temp("reload");
+        let path = format!("{}/access.log", dir);
+        let old = open(Target::File(path.clone()), Options::default());
+        // Refused in one configuration
+        let rotate = Options {
+            format: Format::new("$request_method $status").unwrap(),
+            buffer: 1,
+            rotate: Rotate {
+                size: Some(12),
+                daily: false,
+                keep: 2,
+                compress: false
+            },
+            ..Options::default()
+        };
+        let new = Log::new(Target::File(path.clone()), rotate.clone());
+        assert!(log::check(&[&old, &new]).is_err());
+        assert!(log::check(&[&old, &Log::new(Target::File(format!("{}/other.log", dir)), rotate)]).is_ok());
+
+        // After a reload the writer takes them on
+        new.open();
+        assert!(Arc::ptr_eq(writer(&old), writer(&new)));
+        let request = request();
+        for _ in 0..5 {
+            new.write(&access(&request));
+        }
+        drop((old, new));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "GET 200\n");
+        assert!(fs::metadata(format!("{}.2", path)).is_ok());
+        assert!(fs::metadata(format!("{}.3", path)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_failed() {
+        let path = &temp("reopen.log");
+        let file = log::create_log_file(path.clone()).unwrap();
+        let mut output = LogFile::new(path.clone(), file, Rotate::default());
+        // As after the file couldn't be opened again
+        output.out = None;
+        output.write("first").unwrap();
+        output.flush().unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "first\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_overflow_drop() {
+        let path = &temp("drop.log");
+        let log = open(Target::File(path.clone()), Options {
+            buffer: 1,
+            overflow: Overflow::Drop,
+            ..Options::default()
+        });
+        let request = request();
+        for _ in 0..1000 {
+            log.write(&access(&request));
+        }
+        let dropped = writer(&log).dropped.load(Ordering::Relaxed);
+        drop(log);
+        let written = fs::read_to_string(path).unwrap().lines().count() as u64;
+        assert_eq!(written + dropped, 1000);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_combined() {
+        let request = request();
+        let line = Format::new("combined").unwrap().line(&access(&request));
+        assert!(line.starts_with("192.168.1.2 - user [28/Apr/2019:10:20:30 "));
+        assert!(line.ends_with(
+            "] \"GET /a%20b?page=2 HTTP/1.1\" 200 1234 \"https://example.com/\" \"curl/7.64 \\x22quoted\\x22\""
+        ));
+        let mut out = String::new();
+        log::quote(&mut out, "a\\b\r\n");
+        assert_eq!(out, "a\\x5Cb\\x0D\\x0A");
+    }
+
+    #[test]
+    fn test_template() {
+        let request = request();
+        let format = Format::new("$host $uri $args $status $bytes_sent $request_time $http_x_missing $$").unwrap();
+        assert_eq!(
+            format.line(&access(&request)),
+            "example.com /a b page=2 200 1334 1.500 - $$"
+        );
+        assert_eq!(
+            Format::new("$remote_addr $nope"),
+            Err(String::from("Unknown log variable \"$nope\""))
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let request = request();
+        let line = Format::new("json").unwrap().line(&access(&request));
+        assert!(line.starts_with("{\"time\":\"2019-04-28T10:20:30"));
+        assert!(line.contains(",\"remote_addr\":\"192.168.1.2\",\"remote_user\":\"user\",\"method\":\"GET\""));
+        assert!(line.contains(",\"host\":\"example.com\",\"status\":200,\"bytes_sent\":1334,\"body_bytes_sent\":1234,\"request_time\":1.500,"));
+        assert!(line.ends_with(",\"user_agent\":\"curl/7.64 \\\"quoted\\\"\"}"));
+    }
+
+}
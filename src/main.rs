@@ -9,29 +9,59 @@ mod log;
 mod app;
 mod pool;
 mod compress;
+mod event;
+mod signal;
+mod syslog;
+mod metrics;
+mod status;
+mod cidr;
+mod limit;
+mod proxy;
+mod listing;
+mod template;
+mod archive;
+mod markdown;
+mod upload;
+mod manage;
+mod xml;
+mod webdav;
+#[cfg(unix)]
+mod upgrade;
 use std::sync::Arc;
 use std::{fs, fs::File};
 use std::env;
 use std::{process, process::Command};
 use std::path::Path;
+use std::io;
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::ErrorKind;
+use std::fmt;
 use std::fmt::Write as FmtWrite;
-use std::net::{TcpStream, TcpListener};
-use std::thread::JoinHandle;
-use chrono::{DateTime, Local};
-use response::{StatusCode, Response};
+use std::net::{IpAddr, TcpStream, TcpListener, SocketAddr};
+use std::time::{Duration, Instant};
+use chrono::Local;
+use percent_encoding::{utf8_percent_encode, DEFAULT_ENCODE_SET};
+use webdav::{Depth, HREF_ENCODE_SET};
+use response::{StatusCode, Response, Sent};
 use request::Request;
-use html::TEMPLATE;
+use log::{Access, Target};
 use config::{
     ServerConfig,
+    GlobalConfig,
     DirectoryOption,
+    Archive,
+    Upload,
+    Manage,
     RewriteType,
     ContentEncoding,
     DEFAULT_METHODS
 };
 use app::App;
 use pool::ThreadPool;
+use signal::Signals;
+use status::Kind;
+use limit::{Key, Verdict};
+use listing::Format;
 
 
 #[cfg(target_os = "macos")]
@@ -43,7 +73,10 @@ static PID_PATH: &str = "./see.pid";
 
 const DEFAULT_CONFIG_PATH: &str = "config.yml";
 const DEFAULT_PORT: i64 = 80;
-const THREAD_POOL_MAX: usize = 125;
+const HEADER_MAX: usize = 8192;
+const BODY_MAX: usize = 8 * 1024 * 1024;
+// A zero timeout means blocking forever for std
+const MIN_TIMEOUT: Duration = Duration::from_millis(1);
 
 fn main() {
 
@@ -61,7 +94,17 @@ fn main() {
         return stop_daemon();
     }
 
-    let mut configs: Vec<Arc<Vec<ServerConfig>>>;
+    if app.reload() {
+        return reload_daemon();
+    }
+
+    if app.reopen() {
+        return reopen_daemon();
+    }
+
+    let configs: Vec<Arc<Vec<ServerConfig>>>;
+    let mut global = GlobalConfig::default();
+    let mut config_file = None;
     let current_buff = env::current_dir()
         .unwrap();
     let current_dir = current_buff.to_str()
@@ -73,8 +116,10 @@ fn main() {
         config.root = String::from(current_dir);
         config.directory = Some(DirectoryOption {
             time: true,
-            size: true
+            size: true,
+            ..DirectoryOption::default()
         });
+        config.markdown = true;
         config.methods = DEFAULT_METHODS
             .iter()
             .map(|m| String::from(*m))
@@ -108,72 +153,136 @@ fn main() {
                 process::exit(1);
             }
         };
+        global = match GlobalConfig::new(&config_path) {
+            Ok(config) => config,
+            Err(msg) => {
+                eprintln!("{}", msg);
+                process::exit(1);
+            }
+        };
 
         // Check configuration file
         if app.test() {
             return println!("the configuration file {} syntax is ok", config_path);
         }
 
+        config_file = Some(config_path);
+
     }
 
     if app.detach() {
         return start_daemon(&app.args, app.detach_args());
     }
 
-    let mut tasks: Vec<JoinHandle<()>> = vec![];
+    log::init(&global.error_log, global.log_level);
+    config::open_logs(&configs);
+    status::init(config_file.as_deref());
+    status::serving(status::servers(&configs));
+
+    // Installed before binding: once the ports accept, signals must not kill us
+    let signals = match Signals::new() {
+        Ok(signals) => signals,
+        Err(err) => {
+            eprintln!("Installing signal handlers failed");
+            eprintln!("{:?}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut listeners = vec![];
     let start = app.start();
 
+    // Sockets passed on by the process we are replacing
+    #[cfg(unix)]
+    let mut inherited = upgrade::inherited();
+
     for config in configs {
 
-        let task = std::thread::spawn(move || {
+        let listen = config[0].listen;
+        let address = format!("0.0.0.0:{}", listen);
 
-            let listen = config[0].listen;
-            let address = format!("0.0.0.0:{}", listen);
+        #[cfg(unix)]
+        {
+            if let Some(listener) = inherited.remove(&listen) {
+                listeners.push((listener, config));
+                continue;
+            }
+        }
 
-            match TcpListener::bind(&address) {
-                Ok(listener) => {
-                    if start {
-                        println!("Serving path   : \x1b[92m{}\x1b[0m",  &config[0].root);
-                        if listen != 80 {
-                            println!("Serving address: \x1b[93mhttp://127.0.0.1:{}\x1b[0m",  listen);
-                        }else {
-                            println!("Serving address: \x1b[93mhttp://127.0.0.1\x1b[0m");
-                        }
+        match TcpListener::bind(&address) {
+            Ok(listener) => {
+                if start {
+                    println!("Serving path   : \x1b[92m{}\x1b[0m",  &config[0].root);
+                    if listen != 80 {
+                        println!("Serving address: \x1b[93mhttp://127.0.0.1:{}\x1b[0m",  listen);
+                    }else {
+                        println!("Serving address: \x1b[93mhttp://127.0.0.1\x1b[0m");
                     }
-                    incoming(listener, config);
-                },
-                Err(err) => {
-                    eprintln!("Binding {} failed", address);
-                    eprintln!("{:?}", err);
-                    process::exit(1);
                 }
-            };
+                listeners.push((listener, config));
+            },
+            Err(err) => {
+                fatal(&global, format_args!("Binding {} failed ({})", address, err));
+            }
+        };
 
-        });
+    }
+
+    #[cfg(unix)]
+    upgrade::ready();
+
+    incoming(listeners, &signals, &global, config_file);
 
-        tasks.push(task);
+}
+
+
+// All listeners share one event loop and one worker pool
+fn incoming(
+    listeners: Vec<(TcpListener, Arc<Vec<ServerConfig>>)>,
+    signals: &Signals,
+    global: &GlobalConfig,
+    config_file: Option<String>
+) {
+
+    let pool = Arc::new(ThreadPool::new(global.worker_threads, global.worker_queue));
+    metrics::watch(&pool);
+
+    // Global options only change on restart, but must still be valid
+    let reload = move || {
+        match &config_file {
+            Some(path) => {
+                GlobalConfig::new(path)?;
+                ServerConfig::new(path)
+            },
+            None => Err(String::from("Not started from a configuration file"))
+        }
+    };
 
+    let mut code = 0;
+    if let Err(err) = event::run(listeners, pool.clone(), signals, &reload) {
+        log::error(format_args!("Event loop failed ({})", err));
+        code = 1;
     }
 
-    for task in tasks {
-        task.join().unwrap();
+    // No longer accepting, let the requests in flight finish
+    if !pool.join(global.shutdown_timeout) {
+        log::warn(format_args!("Shutdown timed out, aborting the remaining requests"));
     }
+    log::flush();
+    process::exit(code);
 
 }
 
 
-fn incoming(listener: TcpListener, configs: Arc<Vec<ServerConfig>>) {
-
-    let pool = ThreadPool::new(THREAD_POOL_MAX);
+// Also shown on the terminal when the error log goes elsewhere
+fn fatal(global: &GlobalConfig, message: fmt::Arguments) -> ! {
 
-    for stream in listener.incoming() {
-        if let Ok(stream) = stream {
-            let configs = configs.clone();
-            pool.execute(|| {
-                handle_connection(stream, configs);
-            });
-        }
+    log::error(message);
+    log::flush();
+    if global.error_log != Target::Stderr {
+        eprintln!("{}", message);
     }
+    process::exit(1);
 
 }
 
@@ -207,115 +316,425 @@ fn start_daemon(args: &Vec<String>, detach: [&str; 2]) {
 
 
 fn stop_daemon() {
+    if signal_daemon("-TERM") {
+        if let Err(e) = fs::remove_file(PID_PATH) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+
+fn reload_daemon() {
+    signal_daemon("-HUP");
+}
+
+
+fn reopen_daemon() {
+    signal_daemon("-USR1");
+}
+
+
+// Send a signal to the process in the PID file
+fn signal_daemon(signal: &str) -> bool {
     match fs::read_to_string(PID_PATH) {
         Ok(pid) => {
             let kill = Command::new("kill")
-                .arg(pid)
+                .arg(signal)
+                .arg(pid.trim())
                 .status();
             match kill {
                 Ok(status) => {
-                    if status.success() {
-                        if let Err(e) = fs::remove_file(PID_PATH) {
-                            eprintln!("{}", e);
-                        }
-                    }
+                    status.success()
                 },
                 Err(e) => {
                     eprintln!("{}", e);
+                    false
                 }
             }
         },
         Err(e) => {
             eprintln!("open \"{}\" failed, {:?}", PID_PATH, e.to_string());
+            false
         }
     }
 }
 
 
-fn handle_connection(mut stream: TcpStream, configs: Arc<Vec<ServerConfig>>) {
+// What is known of a connection before a worker takes it
+pub struct Prelude {
+    // The client, as the PROXY protocol header names it
+    pub remote: Option<SocketAddr>,
+    // Read ahead of the request header, the whole header once the event loop waited for it
+    pub received: Vec<u8>,
+    // The rate limit was applied already, any delay is over
+    pub rate: Option<Verdict>
+}
 
-    // Default 8k
-    let mut reader = BufReader::new(&stream);
-    let buffer = if let Ok(buffer) = reader.fill_buf() {
-        buffer
-    }else {
-        let res = Response::new(StatusCode::_500, &vec![])
-            .text("500");
-        stream.write(&res).unwrap();
-        stream.flush().unwrap();
-        return;
+pub fn handle_connection(stream: &mut TcpStream, configs: Arc<Vec<ServerConfig>>, accepted: Instant, prelude: Prelude) {
+
+    // The server is not known before the Host header, use the first one of the port
+    let timeout = &configs[0].timeout;
+    let deadline = earliest(
+        timeout.header.map(|t| accepted + t),
+        timeout.request.map(|t| accepted + t)
+    );
+
+    let res: Vec<u8>;
+    let (header, rest) = match read_header(stream, deadline, prelude.received) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            let client = client(stream, None);
+            res = match err.kind() {
+                ErrorKind::UnexpectedEof => {
+                    log::debug(format_args!("Connection closed before a request, {}", client));
+                    return;
+                },
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                    log::info(format_args!("Timed out reading the request header, {}", client));
+                    Response::new(StatusCode::_408, &[])
+                        .text("408")
+                },
+                ErrorKind::InvalidData => {
+                    log::info(format_args!("Invalid request header ({}), {}", err, client));
+                    Response::new(StatusCode::_400, &[])
+                        .text("400")
+                },
+                _ => {
+                    log::error(format_args!("Reading the request header failed ({}), {}", err, client));
+                    Response::new(StatusCode::_500, &[])
+                        .text("500")
+                }
+            };
+            let _ = stream.write_all(&res);
+            return;
+        }
     };
 
-    let mut res: Vec<u8>;
-    let req = if let Ok(req) = Request::new(&buffer) {
+    let mut req = if let Ok(req) = Request::new(&header) {
         req
     }else {
-        res = Response::new(StatusCode::_400, &vec![])
+        let line = header.split(|b| *b == b'\n').next().unwrap_or(&[]);
+        log::info(format_args!(
+            "Invalid request line \"{}\", {}",
+            String::from_utf8_lossy(line).trim_end(),
+            client(stream, None)
+        ));
+        res = Response::new(StatusCode::_400, &[])
             .text("400");
-        stream.write(&res).unwrap();
-        stream.flush().unwrap();
+        let _ = stream.write_all(&res);
         return;
     };
+    req.body = rest;
+    req.remote = prelude.remote;
 
+    let mut config = None;
     if let Some(host) = req.headers.get("host") {
-        let mut index = None;
-        let host = &host.replace(&format!(":{}", configs[0].listen), "");
-        'configs: for (i, config) in configs.iter().enumerate() {
-            if let Some(hosts) = &config.hosts {
-                for val in hosts {
-                    if val == host {
-                        index = Some(i);
-                        break 'configs;
-                    }
-                }
-            }
-        }
-        if let None = index {
-            for (i, conf) in configs.iter().enumerate() {
-                if let None = conf.hosts {
-                    index = Some(i);
-                    break;
-                }
-            }
-        }
-        if let Some(i) = index {
-            res = output(req, &configs[i], &stream);
+        if let Some(i) = server(&configs, host) {
+            res = receive(&mut req, &configs[i], stream, accepted, prelude.rate);
+            config = Some(&configs[i]);
         }else {
-            res = Response::new(StatusCode::_403, &vec![])
+            log::info(format_args!("No server for host \"{}\", {}", host, client(stream, Some(&req))));
+            res = Response::new(StatusCode::_403, &[])
                 .text("403");
         }
     }else {
         // A Host header field must be sent in all HTTP/1.1 request messages
-        res = Response::new(StatusCode::_400, &vec![])
+        log::info(format_args!("Missing Host header, {}", client(stream, Some(&req))));
+        res = Response::new(StatusCode::_400, &[])
             .text("400");
     }
 
-    if res.len() != 0 {
-        stream.write(&res).unwrap();
+    if !res.is_empty() {
+        match stream.write_all(&res) {
+            Ok(()) => req.sent.set(Sent {
+                error: req.sent.get().error,
+                ..Sent::of(&res)
+            }),
+            Err(err) => req.sent.set(Sent {
+                error: Some(err.kind()),
+                ..req.sent.get()
+            })
+        }
     }
 
-    stream.flush().unwrap();
+    let _ = stream.flush();
+
+    if let Some(config) = config {
+        record(config, &req, stream, accepted);
+    }
 
 }
 
 
-fn output(mut request: Request, config: &ServerConfig, stream: &TcpStream) -> Vec<u8> {
+// Index of the server for the Host header
+fn server(configs: &[ServerConfig], host: &str) -> Option<usize> {
 
-    // Not allowed method
-    let allow = config.methods.iter().find(|m| {
-        return **m == request.method;
+    let host = &host.replace(&format!(":{}", configs[0].listen), "");
+    let named = configs.iter().position(|config| {
+        config.hosts.as_ref().is_some_and(|hosts| hosts.iter().any(|val| val == host))
     });
-    if let None = allow {
-        if let Some(log) = &config.log.error {
-            log.write(&request.method, 405, &request.path);
+    named.or_else(|| configs.iter().position(|config| config.hosts.is_none()))
+
+}
+
+// Who sent a request whose header is in, and what the rate limit says about it.
+// The event loop asks before handing the connection to a worker,
+// anything the header doesn't tell is left to `receive`
+pub fn screen(configs: &[ServerConfig], header: &[u8], remote: SocketAddr) -> (IpAddr, Option<Verdict>) {
+
+    let mut request = match Request::new(header) {
+        Ok(request) => request,
+        Err(_) => return (remote.ip(), None)
+    };
+    let config = match request.headers.get("host").and_then(|host| server(configs, host)) {
+        Some(i) => &configs[i],
+        None => return (remote.ip(), None)
+    };
+    request.remote = Some(remote);
+    forwarded(config, &mut request);
+
+    let ip = request.remote.map_or(remote.ip(), |addr| addr.ip());
+    if !cidr::allowed(config.access(&request.path), &ip) {
+        return (ip, None);
+    }
+    (ip, limit_rate(config, &request, ip))
+
+}
+
+// The client a trusted proxy names in X-Forwarded-For
+fn forwarded(config: &ServerConfig, request: &mut Request) {
+    if let (Some(peer), Some(header)) = (request.remote, request.headers.get("x-forwarded-for")) {
+        let ip = cidr::forwarded(peer.ip(), header, &config.trusted_proxies);
+        if ip != peer.ip() {
+            request.remote = Some(SocketAddr::new(ip, 0));
+        }
+    }
+}
+
+// None without a rate limit
+fn limit_rate(config: &ServerConfig, request: &Request, ip: IpAddr) -> Option<Verdict> {
+    let rate = config.limit.rate.as_ref()?;
+    let key = match &rate.key {
+        Key::Header(name) => request.headers.get(name).cloned(),
+        Key::Ip => None
+    };
+    Some(rate.check(&key.unwrap_or_else(|| ip.to_string()), Instant::now()))
+}
+
+
+// Answered requests go to the success or the error log by their status
+fn record(config: &ServerConfig, request: &Request, stream: &TcpStream, accepted: Instant) {
+
+    let sent = request.sent.get();
+    if let Some(kind) = sent.error {
+        log::info(format_args!(
+            "Sending the response failed ({:?}) after {} bytes, {}",
+            kind,
+            sent.bytes(),
+            client(stream, Some(request))
+        ));
+    }
+    if sent.status == 0 {
+        return;
+    }
+
+    let duration = accepted.elapsed();
+    let host = config.hosts.as_ref()
+        .and_then(|hosts| hosts.first())
+        .map_or("*", |host| host.as_str());
+    metrics::request(&format!("{}:{}", host, config.listen), &request.method, sent.status, sent.bytes(), duration);
+
+    if sent.status >= 500 {
+        status::error(Kind::Server);
+    }else if sent.status >= 400 {
+        status::error(Kind::Client);
+    }
+
+    let log = if sent.status >= 400 {
+        &config.log.error
+    }else {
+        &config.log.success
+    };
+
+    if let Some(log) = log {
+        log.write(&Access {
+            request,
+            remote: request.remote,
+            sent,
+            duration,
+            time: Local::now()
+        });
+    }
+
+}
+
+
+// Who asked for what, appended to error log messages
+fn client(stream: &TcpStream, request: Option<&Request>) -> String {
+
+    let remote = request.and_then(|request| request.remote);
+    let mut client = match remote.map_or_else(|| stream.peer_addr(), Ok) {
+        Ok(addr) => format!("client: {}", addr.ip()),
+        Err(_) => String::from("client: -")
+    };
+    if let Some(request) = request {
+        let _ = write!(client, ", request: \"{} {} {}\"", request.method, request.uri, request.version);
+    }
+    client
+
+}
+
+
+// Read the body and answer the request within the server's timeouts
+fn receive(
+    request: &mut Request,
+    config: &ServerConfig,
+    stream: &TcpStream,
+    accepted: Instant,
+    rate: Option<Verdict>
+) -> Vec<u8> {
+
+    let timeout = &config.timeout;
+    request.deadline = timeout.request.map(|t| accepted + t);
+
+    forwarded(config, request);
+
+    if let Some(remote) = request.remote {
+        if !cidr::allowed(config.access(&request.path), &remote.ip()) {
+            log::info(format_args!("Access forbidden, {}", client(stream, Some(request))));
+            return Response::new(StatusCode::_403, &config.headers)
+                .text("403");
+        }
+        match rate.or_else(|| limit_rate(config, request, remote.ip())) {
+            None | Some(Verdict::Pass) => {},
+            // A delay is waited out in the event loop, a worker doesn't sleep through it
+            Some(Verdict::Delay(wait)) | Some(Verdict::Reject(wait)) => {
+                log::info(format_args!("Request rate limited, {}", client(stream, Some(request))));
+                return Response::new(StatusCode::_429, &config.headers)
+                    .header("Retry-After", &wait.as_secs_f64().ceil().max(1.0).to_string())
+                    .text("429");
+            }
+        }
+    }
+
+    if let Some(res) = admit(request, config, stream) {
+        return res;
+    }
+
+    let length = match request.headers.get("content-length") {
+        Some(value) => match value.trim().parse::<usize>() {
+            Ok(length) => length,
+            Err(err) => {
+                log::info(format_args!(
+                    "Invalid Content-Length \"{}\" ({}), {}",
+                    value, err, client(stream, Some(request))
+                ));
+                return Response::new(StatusCode::_400, &config.headers)
+                    .text("400");
+            }
+        },
+        None => 0
+    };
+
+    // Uploads bring their own limit
+    let max = match (&config.webdav, &config.upload) {
+        (Some(webdav), _) if request.method == "PUT" && webdav.allows(&request.path) => webdav.max_size,
+        (_, Some(upload)) if (request.method == "PUT" || request.method == "POST") && upload.allows(&request.path) => upload.max_size,
+        _ => BODY_MAX
+    };
+    if length > max {
+        log::info(format_args!(
+            "Request body of {} bytes is too large, {}",
+            length, client(stream, Some(request))
+        ));
+        return Response::new(StatusCode::_413, &config.headers)
+            .text("413");
+    }
+
+    // The client waits for this before sending a large body
+    let expect = request.headers.get("expect").is_some_and(|value| value.eq_ignore_ascii_case("100-continue"));
+    if expect && length > request.body.len() {
+        let mut writer = stream;
+        if let Err(err) = writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n") {
+            log::info(format_args!("Sending 100 Continue failed ({}), {}", err, client(stream, Some(request))));
+            return vec![];
+        }
+    }
+
+    if let Err(err) = read_body(stream, &mut request.body, length, timeout.body, request.deadline) {
+        let client = client(stream, Some(request));
+        return match err.kind() {
+            ErrorKind::UnexpectedEof => {
+                log::info(format_args!("Connection closed while reading the request body, {}", client));
+                vec![]
+            },
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                log::info(format_args!("Timed out reading the request body, {}", client));
+                Response::new(StatusCode::_408, &config.headers)
+                    .text("408")
+            },
+            _ => {
+                log::warn(format_args!("Reading the request body failed ({}), {}", err, client));
+                Response::new(StatusCode::_400, &config.headers)
+                    .text("400")
+            }
+        };
+    }
+
+    let send = earliest(timeout.send.map(|t| Instant::now() + t), request.deadline);
+    if let Err(err) = stream.set_write_timeout(send.map(|t| remaining(t).max(MIN_TIMEOUT))) {
+        log::error(format_args!("Setting the send timeout failed ({}), {}", err, client(stream, Some(request))));
+        return vec![];
+    }
+
+    output(request, config, stream)
+
+}
+
+
+// Answered from the request header alone, before 100 Continue is sent and the body is read
+fn admit(request: &mut Request, config: &ServerConfig, stream: &TcpStream) -> Option<Vec<u8>> {
+
+    // Load balancer checks, answered before anything else
+    if config.health.as_ref() == Some(&request.path) {
+        return Some(Response::new(StatusCode::_200, &config.headers)
+            .text("ok"));
+    }
+
+    if let Some(page) = &config.status {
+        if request.path == page.path {
+            let allowed = match request.remote {
+                Some(addr) => page.allow.iter().any(|net| net.contains(&addr.ip())),
+                None => false
+            };
+            if !allowed {
+                log::info(format_args!("Status page forbidden, {}", client(stream, Some(request))));
+                return Some(Response::new(StatusCode::_403, &config.headers)
+                    .text("403"));
+            }
+            let json = request.param("format").as_deref() == Some("json")
+                || request.headers.get("accept").is_some_and(|accept| accept.contains("application/json"));
+            return Some(if json {
+                Response::new(StatusCode::_200, &config.headers)
+                    .json(status::json())
+            }else {
+                Response::new(StatusCode::_200, &config.headers)
+                    .html(status::html())
+            });
         }
+    }
+
+    // Not allowed method
+    let allow = config.methods.iter().find(|m| **m == request.method);
+    if allow.is_none() {
+        log::info(format_args!("Method not allowed, {}", client(stream, Some(request))));
         if &request.method == "OPTIONS" {
-            return Response::new(StatusCode::_405, &config.headers)
+            return Some(Response::new(StatusCode::_405, &config.headers)
                 .header("Allow", &config.methods.join(", "))
-                .text("405");
+                .text("405"));
         }else {
-            return Response::new(StatusCode::_405, &config.headers)
-                .text("405");
+            return Some(Response::new(StatusCode::_405, &config.headers)
+                .text("405"));
         }
     }
 
@@ -323,20 +742,23 @@ fn output(mut request: Request, config: &ServerConfig, stream: &TcpStream) -> Ve
         let authorization = request.headers.get("authorization");
         if let Some(value) = authorization {
             if auth != value {
-                if let Some(log) = &config.log.error {
-                    log.write(&request.method, 401, &request.path);
-                }
-                return Response::new(StatusCode::_401, &config.headers)
+                log::info(format_args!("Wrong user or password, {}", client(stream, Some(request))));
+                return Some(Response::new(StatusCode::_401, &config.headers)
                     .header("WWW-Authenticate", "Basic realm=\"User Visible Realm\"")
-                    .text("401");
+                    .text("401"));
             }
         }else {
-            if let Some(log) = &config.log.error {
-                log.write(&request.method, 401, &request.path);
-            }
-            return Response::new(StatusCode::_401, &config.headers)
+            log::debug(format_args!("Authorization required, {}", client(stream, Some(request))));
+            return Some(Response::new(StatusCode::_401, &config.headers)
                 .header("WWW-Authenticate", "Basic realm=\"User Visible Realm\"")
-                .text("401");
+                .text("401"));
+        }
+    }
+
+    if let Some(metrics) = &config.metrics {
+        if &request.path == metrics {
+            return Some(Response::new(StatusCode::_200, &config.headers)
+                .text(&metrics::render()));
         }
     }
 
@@ -344,18 +766,12 @@ fn output(mut request: Request, config: &ServerConfig, stream: &TcpStream) -> Ve
         if let Some(rewrite) = rewrite.get(&request.path) {
             match rewrite.status {
                 RewriteType::_301 => {
-                    if let Some(log) = &config.log.success {
-                        log.write(&request.method, 301, &request.path);
-                    }
-                    return Response::new(StatusCode::_301, &config.headers)
-                        .rewrite(rewrite.url.to_string());
+                    return Some(Response::new(StatusCode::_301, &config.headers)
+                        .rewrite(rewrite.url.to_string()));
                 }
                 RewriteType::_302 => {
-                    if let Some(log) = &config.log.success {
-                        log.write(&request.method, 302, &request.path);
-                    }
-                    return Response::new(StatusCode::_302, &config.headers)
-                        .rewrite(rewrite.url.to_string());
+                    return Some(Response::new(StatusCode::_302, &config.headers)
+                        .rewrite(rewrite.url.to_string()));
                 }
                 RewriteType::Path => {
                     request.path = rewrite.url.to_string();
@@ -364,9 +780,194 @@ fn output(mut request: Request, config: &ServerConfig, stream: &TcpStream) -> Ve
         }
     }
 
-    let cur_path = String::from(".") + &request.path;
+    let dav_method = webdav::METHODS.contains(&request.method.as_str());
+    // Answered by `dav`
+    let dav = dav_method && config.webdav.as_ref().is_some_and(|option| option.allows(&request.path));
+
+    // Dead properties are only read through PROPFIND
+    let sidecar = request.path.trim_end_matches('/').rsplit('/').next().is_some_and(webdav::is_sidecar);
+    if sidecar && !dav && config.webdav.as_ref().is_some_and(|option| option.allows(&request.path)) {
+        log::info(format_args!("{} of WebDAV properties, {}", request.method, client(stream, Some(request))));
+        return Some(Response::new(StatusCode::_404, &config.headers)
+            .text("404"));
+    }
+
+    if config.webdav.is_some() && dav_method && !dav {
+        // Still for `upload` and `manage`, or the file itself
+        let other = match request.method.as_str() {
+            "PUT" => config.upload.is_some(),
+            "DELETE" | "MOVE" => config.manage.is_some(),
+            "OPTIONS" => true,
+            _ => false
+        };
+        if !other {
+            log::info(format_args!("{} outside of the WebDAV paths, {}", request.method, client(stream, Some(request))));
+            return Some(Response::new(StatusCode::_403, &config.headers)
+                .text("403"));
+        }
+    }
+
+    if let Some(option) = &config.upload {
+        if (request.method == "PUT" || request.method == "POST") && !dav {
+            if !option.allows(&request.path) {
+                log::info(format_args!("Upload outside of the upload paths, {}", client(stream, Some(request))));
+                return Some(Response::new(StatusCode::_403, &config.headers)
+                    .text("403"));
+            }
+            if request.method == "PUT" && !request.path.ends_with('/') && !option.accepts(&request.path) {
+                log::info(format_args!(
+                    "Upload \"{}\" has an extension that isn't allowed, {}",
+                    request.path, client(stream, Some(request))
+                ));
+                return Some(Response::new(StatusCode::_403, &config.headers)
+                    .text("403"));
+            }
+        }
+    }
+
+    if let Some(option) = &config.manage {
+        if (request.method == "DELETE" || request.method == "MOVE") && !dav && !option.allows(&request.path) {
+            log::info(format_args!("{} outside of the manage paths, {}", request.method, client(stream, Some(request))));
+            return Some(Response::new(StatusCode::_403, &config.headers)
+                .text("403"));
+        }
+    }
+
+    None
+
+}
+
+
+// Read until the empty line ending the header,
+// returns the header and whatever part of the body came with it
+fn read_header(
+    stream: &mut TcpStream,
+    deadline: Option<Instant>,
+    mut buffer: Vec<u8>
+) -> io::Result<(Vec<u8>, Vec<u8>)> {
+
+    let mut chunk = [0; 4096];
+    let mut from = 0;
+
+    loop {
+
+        if let Some(end) = header_end(&buffer, from) {
+            let rest = buffer.split_off(end);
+            return Ok((buffer, rest));
+        }
+        if buffer.len() > HEADER_MAX {
+            return Err(io::Error::new(ErrorKind::InvalidData, "header too large"));
+        }
+        // The end may straddle two reads
+        from = buffer.len().saturating_sub(3);
+
+        if let Some(deadline) = deadline {
+            let timeout = remaining(deadline);
+            if timeout == Duration::from_secs(0) {
+                return Err(io::Error::new(ErrorKind::TimedOut, "header timeout"));
+            }
+            stream.set_read_timeout(Some(timeout))?;
+        }
+
+        let n = match stream.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+            Ok(n) => n,
+            // A signal arrived, timed reads are not restarted
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        };
+
+        buffer.extend_from_slice(&chunk[..n]);
+
+    }
+
+}
+
+// Length of the header up to its empty line, searched from `from` on
+pub fn header_end(buffer: &[u8], from: usize) -> Option<usize> {
+    buffer.get(from..)?
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| from + i + 4)
+}
+
+
+// Fill `body` up to `length` bytes, `timeout` applies between two reads
+fn read_body(
+    mut stream: &TcpStream,
+    body: &mut Vec<u8>,
+    length: usize,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>
+) -> io::Result<()> {
+
+    body.truncate(length);
+    let mut chunk = [0; 8192];
+
+    while body.len() < length {
+
+        let limit = earliest(timeout.map(|t| Instant::now() + t), deadline);
+        if let Some(limit) = limit {
+            let timeout = remaining(limit);
+            if timeout == Duration::from_secs(0) {
+                return Err(io::Error::new(ErrorKind::TimedOut, "body timeout"));
+            }
+            stream.set_read_timeout(Some(timeout))?;
+        }
+
+        let want = (length - body.len()).min(chunk.len());
+        let n = match stream.read(&mut chunk[..want]) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+            Ok(n) => n,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        };
+        body.extend_from_slice(&chunk[..n]);
+
+    }
+
+    Ok(())
+
+}
+
+
+pub fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b
+    }
+}
+
+fn remaining(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
+}
+
+
+fn output(request: &mut Request, config: &ServerConfig, stream: &TcpStream) -> Vec<u8> {
+
+    // `admit` turned away what is outside of their paths
+    if let Some(option) = &config.webdav {
+        if webdav::METHODS.contains(&request.method.as_str()) && option.allows(&request.path) {
+            return dav(request, config, stream);
+        }
+    }
+
+    if let Some(option) = &config.upload {
+        if request.method == "PUT" || request.method == "POST" {
+            return upload(request, config, stream, option);
+        }
+    }
+
+    if let Some(option) = &config.manage {
+        if request.method == "DELETE" || request.method == "MOVE" {
+            return manage(request, config, stream, option);
+        }
+    }
+
+    let cur_path = request.path.trim_start_matches('/');
     let path_buff = Path::new(&config.root)
-        .join(&cur_path);
+        .join(cur_path);
     let path = path_buff
         .to_str()
         .unwrap();
@@ -375,42 +976,69 @@ fn output(mut request: Request, config: &ServerConfig, stream: &TcpStream) -> Ve
         Ok(meta) => {
             if meta.is_dir() {
                 if request.path.chars().last().unwrap_or('.') == '/' {
+                    let archive = config.directory.as_ref().and_then(|option| option.archive.as_ref());
+                    if let (Some(archive), Some(_)) = (archive, request.param("download")) {
+                        return download(request, config, stream, Path::new(&path), archive);
+                    }
                     if let Some(index) = &config.index {
                         let index_path = fill_path(&path, &index);
-                        match File::open(index_path) {
+                        match File::open(&index_path) {
                             Ok(file) => {
-                                if let Some(log) = &config.log.success {
-                                    log.write(&request.method, 200, &request.path);
-                                }
                                 let ext = get_extension(index);
                                 return Response::new(StatusCode::_200, &config.headers)
                                     .content_type(ext)
-                                    .compress(can_compress(&request, &config, &ext))
-                                    .file(&stream, file);
+                                    .compress(can_compress(request, config, ext))
+                                    .deadline(request.deadline)
+                                    .sent(&request.sent)
+                                    .file(stream, file);
                             },
-                            Err(_) => {
-                                if let Some(log) = &config.log.error {
-                                    log.write(&request.method, 404, &request.path);
-                                }
-                                return output_error(&config, &stream, StatusCode::_404);
+                            Err(err) => {
+                                open_failed(&err, &index_path, request, stream);
+                                return output_error(config, request, stream, StatusCode::_404);
                             }
                         }
                     }
                     if let Some(option) = &config.directory {
-                        if let Some(log) = &config.log.success {
-                            log.write(&request.method, 200, &request.path);
-                        }
-                        return Response::new(StatusCode::_200, &config.headers)
-                            .html(response_dir_html(&path, &request.path, option.time, option.size));
-                    }
-                    if let Some(log) = &config.log.error {
-                        log.write(&request.method, 404, &request.path);
+                        let dav = config.webdav.as_ref().is_some_and(|option| option.allows(&request.path));
+                        return match listing::read(Path::new(&path), dav) {
+                            Ok(mut entries) => {
+                                let view = listing::View::of(request, option);
+                                listing::sort(&mut entries, view.sort, view.order);
+                                let format = Format::of(request);
+                                // The README is there whatever the filter
+                                let readme = match format {
+                                    Format::Html => listing::readme(Path::new(&path), &entries, |name| {
+                                        let file = format!("{}{}", request.path, name);
+                                        request.remote.is_none_or(|addr| cidr::allowed(config.access(&file), &addr.ip()))
+                                    }),
+                                    _ => None
+                                };
+                                let entries = listing::filter(entries, view.filter.as_deref());
+                                let res = Response::new(StatusCode::_200, &config.headers);
+                                match format {
+                                    Format::Html => {
+                                        let form = config.upload.as_ref().is_some_and(|upload| upload.allows(&request.path));
+                                        res.html(listing::html(&entries, &request.path, option, &view, readme.as_deref(), form))
+                                    },
+                                    Format::Json => res.json(listing::json(&entries, &request.path)),
+                                    Format::Text => res.text(&listing::text(&entries))
+                                }
+                            },
+                            Err(err) => {
+                                log::error(format_args!(
+                                    "Reading the directory \"{}\" failed ({}), {}",
+                                    path, err, client(stream, Some(request))
+                                ));
+                                output_error(config, request, stream, StatusCode::_500)
+                            }
+                        };
                     }
-                    return output_error(&config, &stream, StatusCode::_404);
+                    log::info(format_args!(
+                        "Directory \"{}\" has no index and listing is off, {}",
+                        path, client(stream, Some(request))
+                    ));
+                    output_error(config, request, stream, StatusCode::_404)
                 }else {
-                    if let Some(log) = &config.log.success {
-                        log.write(&request.method, 301, &request.path);
-                    }
                     let aims;
                     if let Some(query) = &request.query {
                         aims = format!("{}/{}", request.path, query);
@@ -421,54 +1049,592 @@ fn output(mut request: Request, config: &ServerConfig, stream: &TcpStream) -> Ve
                         .rewrite(aims);
                 }
             }else {
+                let ext = get_extension(path);
+                let markdown = config.markdown && markdown::is_markdown(ext);
+                if markdown && request.param("raw").is_none() && meta.len() <= markdown::MAX {
+                    return match fs::read(path) {
+                        Ok(source) => {
+                            Response::new(StatusCode::_200, &config.headers)
+                                .html(markdown::page(&String::from_utf8_lossy(&source), &request.path))
+                        },
+                        Err(err) => {
+                            open_failed(&err, path, request, stream);
+                            output_error(config, request, stream, StatusCode::_500)
+                        }
+                    };
+                }
                 match File::open(&path) {
                     Ok(file) => {
-                        if let Some(log) = &config.log.success {
-                            log.write(&request.method, 200, &request.path);
+                        let mut res = Response::new(StatusCode::_200, &config.headers)
+                            .content_type(ext);
+                        // ?raw shows the source in the browser
+                        if markdown {
+                            res = res.header("Content-Type", "text/plain; charset=utf-8");
                         }
-                        let ext = get_extension(&path);
-                        return Response::new(StatusCode::_200, &config.headers)
-                            .content_type(&ext)
-                            .compress(can_compress(&request, &config, &ext))
-                            .file(&stream, file);
+                        res
+                            .compress(can_compress(request, config, ext))
+                            .deadline(request.deadline)
+                            .sent(&request.sent)
+                            .file(stream, file)
                     },
-                    Err(_) => {
-                        if let Some(log) = &config.log.error {
-                            log.write(&request.method, 500, &request.path);
-                        }
-                        return output_error(&config, &stream, StatusCode::_500);
+                    Err(err) => {
+                        open_failed(&err, path, request, stream);
+                        output_error(config, request, stream, StatusCode::_500)
                     }
                 }
             }
         },
-        Err(_) => {
+        Err(err) => {
             if let Some(exts) = &config.extensions {
                 match fallbacks(&path, exts) {
                     Ok(fallback) => {
-                        if let Some(log) = &config.log.success {
-                            log.write(&request.method, 200, &request.path);
-                        }
                         return Response::new(StatusCode::_200, &config.headers)
                             .content_type(&fallback.ext)
-                            .compress(can_compress(&request, &config, &fallback.ext))
-                            .file(&stream, fallback.file);
+                            .compress(can_compress(request, config, &fallback.ext))
+                            .deadline(request.deadline)
+                            .sent(&request.sent)
+                            .file(stream, fallback.file);
                     },
                     Err(_) => {
-                        if let Some(log) = &config.log.error {
-                            log.write(&request.method, 404, &request.path);
-                        }
-                        return output_error(&config, &stream, StatusCode::_404);
+                        open_failed(&err, path, request, stream);
+                        output_error(config, request, stream, StatusCode::_404)
                     }
                 }
             }else {
-                if let Some(log) = &config.log.error {
-                    log.write(&request.method, 404, &request.path);
-                }
-                return output_error(&config, &stream, StatusCode::_404);
+                open_failed(&err, path, request, stream);
+                output_error(config, request, stream, StatusCode::_404)
+            }
+        }
+    }
+
+}
+
+
+// The whole tree of a directory in one archive, written while it is sent
+fn download(request: &Request, config: &ServerConfig, stream: &TcpStream, dir: &Path, option: &Archive) -> Vec<u8> {
+
+    let kind = match request.param("download").as_deref().map(archive::Kind::new) {
+        Some(Ok(kind)) if option.formats.contains(&kind) => kind,
+        _ => {
+            return Response::new(StatusCode::_400, &config.headers)
+                .text("400");
+        }
+    };
+
+    let ip = request.remote.map(|addr| addr.ip());
+    let entries = match archive::collect(dir, &request.path, config, ip, kind, option.max_size) {
+        Ok(entries) => entries,
+        Err(archive::Error::TooLarge) => {
+            log::info(format_args!(
+                "Archive of \"{}\" is over the size limit, {}",
+                dir.display(), client(stream, Some(request))
+            ));
+            return Response::new(StatusCode::_403, &config.headers)
+                .text("403");
+        },
+        Err(archive::Error::Io(err)) => {
+            log::error(format_args!(
+                "Reading the directory \"{}\" failed ({}), {}",
+                dir.display(), err, client(stream, Some(request))
+            ));
+            return output_error(config, request, stream, StatusCode::_500);
+        }
+    };
+
+    // Named after the directory
+    let name = match request.path.trim_end_matches('/').rsplit('/').next() {
+        Some(name) if !name.is_empty() => name,
+        _ => "root"
+    };
+    let name = format!("{}.{}", name, kind.as_str());
+
+    Response::new(StatusCode::_200, &config.headers)
+        .header("Content-Type", kind.content_type())
+        .header("Content-Disposition", &disposition(&name))
+        .deadline(request.deadline)
+        .sent(&request.sent)
+        .stream(stream, |out| kind.write(&entries, out))
+
+}
+
+// PUT writes the body to its path, POST takes the files of a form into its directory
+fn upload(request: &Request, config: &ServerConfig, stream: &TcpStream, option: &Upload) -> Vec<u8> {
+
+    let target = match upload::confine(&config.root, &request.path) {
+        Some(target) => target,
+        None => {
+            log::info(format_args!("Upload outside of the root, {}", client(stream, Some(request))));
+            return Response::new(StatusCode::_403, &config.headers)
+                .text("403");
+        }
+    };
+    // Only bodies with a length are read
+    if request.headers.contains_key("transfer-encoding") {
+        return Response::new(StatusCode::_411, &config.headers)
+            .text("411");
+    }
+
+    let conflict = |name: &str| {
+        log::info(format_args!("Upload \"{}\" conflicts with an existing file, {}", name, client(stream, Some(request))));
+        Response::new(StatusCode::_409, &config.headers)
+            .text("409")
+    };
+    let forbidden = |name: &str| {
+        log::info(format_args!("Upload \"{}\" has an extension that isn't allowed, {}", name, client(stream, Some(request))));
+        Response::new(StatusCode::_403, &config.headers)
+            .text("403")
+    };
+    let failed = |err: io::Error| {
+        log::error(format_args!("Saving the upload failed ({}), {}", err, client(stream, Some(request))));
+        output_error(config, request, stream, StatusCode::_500)
+    };
+
+    if request.method == "PUT" {
+        // The extension was checked by `admit`
+        if request.path.ends_with('/') {
+            return conflict(&request.path);
+        }
+        return match upload::save(&target, &request.body, option.overwrite) {
+            Ok(true) => {
+                let location = request.uri.split('?').next().unwrap_or("/");
+                Response::new(StatusCode::_201, &config.headers)
+                    .header("Location", location)
+                    .text("201")
+            },
+            Ok(false) => Response::new(StatusCode::_204, &config.headers).empty(),
+            Err(upload::Error::Conflict) => conflict(&request.path),
+            Err(upload::Error::Io(err)) => failed(err)
+        };
+    }
+
+    if !target.is_dir() {
+        return output_error(config, request, stream, StatusCode::_404);
+    }
+    let content_type = request.headers.get("content-type").map_or("", |value| value.as_str());
+    let parts = match upload::multipart(content_type, &request.body) {
+        Some(parts) if !parts.is_empty() => parts,
+        _ => {
+            log::info(format_args!("Upload without files, {}", client(stream, Some(request))));
+            return Response::new(StatusCode::_400, &config.headers)
+                .text("400");
+        }
+    };
+
+    // All of them or none
+    let mut files = vec![];
+    for part in &parts {
+        let name = match upload::basename(&part.filename) {
+            Some(name) => name,
+            None => return forbidden(&part.filename)
+        };
+        if !option.accepts(name) {
+            return forbidden(name);
+        }
+        if !option.overwrite && target.join(name).exists() {
+            return conflict(name);
+        }
+        files.push((name, part.data));
+    }
+    for (name, data) in &files {
+        match upload::save(&target.join(name), data, option.overwrite) {
+            Ok(_) => {},
+            Err(upload::Error::Conflict) => return conflict(name),
+            Err(upload::Error::Io(err)) => return failed(err)
+        }
+    }
+
+    // Back to the listing for the form, the names for everyone else
+    let browser = request.headers.get("accept").is_some_and(|accept| accept.contains("text/html"));
+    if browser {
+        let location = request.uri.split('?').next().unwrap_or("/");
+        return Response::new(StatusCode::_303, &config.headers)
+            .rewrite(location.to_string());
+    }
+    let mut names = String::new();
+    for (name, _) in &files {
+        let _ = writeln!(names, "{}", name);
+    }
+    Response::new(StatusCode::_201, &config.headers)
+        .text(&names)
+
+}
+
+// DELETE removes the path, MOVE renames it to the Destination header
+fn manage(request: &Request, config: &ServerConfig, stream: &TcpStream, option: &Manage) -> Vec<u8> {
+
+    let forbidden = |reason: &str| {
+        log::info(format_args!("{} {}, {}", request.method, reason, client(stream, Some(request))));
+        Response::new(StatusCode::_403, &config.headers)
+            .text("403")
+    };
+    let status = |err: manage::Error, path: &str| {
+        match err {
+            manage::Error::NotFound => output_error(config, request, stream, StatusCode::_404),
+            manage::Error::Conflict => {
+                log::info(format_args!("{} of \"{}\" conflicts with the tree, {}", request.method, path, client(stream, Some(request))));
+                Response::new(StatusCode::_409, &config.headers)
+                    .text("409")
+            },
+            manage::Error::Exists => Response::new(StatusCode::_412, &config.headers)
+                .text("412"),
+            manage::Error::Io(err) => {
+                log::error(format_args!("{} of \"{}\" failed ({}), {}", request.method, path, err, client(stream, Some(request))));
+                output_error(config, request, stream, StatusCode::_500)
+            }
+        }
+    };
+
+    // The root itself stays
+    let target = match upload::confine(&config.root, &request.path) {
+        Some(target) if target != Path::new(&config.root) => target,
+        _ => return forbidden("outside of the root")
+    };
+
+    if request.method == "DELETE" {
+        return match manage::delete(&target, option.recursive) {
+            Ok(()) => Response::new(StatusCode::_204, &config.headers).empty(),
+            Err(err) => status(err, &request.path)
+        };
+    }
+
+    let path = match request.headers.get("destination").and_then(|value| manage::destination(value)) {
+        Some(path) => path,
+        None => {
+            log::info(format_args!("MOVE without a valid Destination, {}", client(stream, Some(request))));
+            return Response::new(StatusCode::_400, &config.headers)
+                .text("400");
+        }
+    };
+    // The destination is held to the same rules as the source
+    let allowed = request.remote.is_none_or(|addr| cidr::allowed(config.access(&path), &addr.ip()));
+    if !option.allows(&path) || !allowed {
+        return forbidden("to a destination that isn't allowed");
+    }
+    let destination = match upload::confine(&config.root, &path) {
+        Some(destination) if destination != Path::new(&config.root) => destination,
+        _ => return forbidden("to outside of the root")
+    };
+    if destination == target {
+        return forbidden("onto itself");
+    }
+
+    let overwrite = request.headers.get("overwrite").is_none_or(|value| !value.trim().eq_ignore_ascii_case("f"));
+    match manage::rename(&target, &destination, overwrite, option.recursive) {
+        Ok(true) => Response::new(StatusCode::_201, &config.headers)
+            .header("Location", &utf8_percent_encode(&path, DEFAULT_ENCODE_SET).to_string())
+            .text("201"),
+        Ok(false) => Response::new(StatusCode::_204, &config.headers).empty(),
+        Err(err) => status(err, &path)
+    }
+
+}
+
+// WebDAV methods below the paths of `webdav`
+fn dav(request: &Request, config: &ServerConfig, stream: &TcpStream) -> Vec<u8> {
+
+    let forbidden = |reason: &str| {
+        log::info(format_args!("{} {}, {}", request.method, reason, client(stream, Some(request))));
+        Response::new(StatusCode::_403, &config.headers)
+            .text("403")
+    };
+    let target = match upload::confine(&config.root, &request.path) {
+        Some(target) => target,
+        None => return forbidden("outside of the root")
+    };
+    if target.file_name().and_then(|name| name.to_str()).is_some_and(webdav::is_sidecar) {
+        return forbidden("on the properties of another resource");
+    }
+
+    let tokens = webdav::tokens(request.headers.get("if"));
+    let locked = || {
+        log::info(format_args!("{} of a locked resource, {}", request.method, client(stream, Some(request))));
+        Response::new(StatusCode::_423, &config.headers)
+            .text("423")
+    };
+    let bad = |reason: &str| {
+        log::info(format_args!("{} {}, {}", request.method, reason, client(stream, Some(request))));
+        Response::new(StatusCode::_400, &config.headers)
+            .text("400")
+    };
+    let failed = |err: io::Error| {
+        log::error(format_args!("{} of \"{}\" failed ({}), {}", request.method, request.path, err, client(stream, Some(request))));
+        output_error(config, request, stream, StatusCode::_500)
+    };
+    let root = target == Path::new(&config.root);
+
+    match request.method.as_str() {
+        "OPTIONS" => Response::new(StatusCode::_200, &config.headers)
+            .header("DAV", "1, 2")
+            .header("MS-Author-Via", "DAV")
+            .header("Allow", &config.methods.join(", "))
+            .text(""),
+        "PROPFIND" => {
+            let depth = match Depth::new(request.headers.get("depth"), Depth::Infinity) {
+                Some(depth) => depth,
+                None => return bad("with an invalid Depth")
+            };
+            let find = match webdav::find(&request.body) {
+                Some(find) => find,
+                None => return bad("with an invalid body")
+            };
+            match webdav::walk(&target, &request.path, depth, config, request.remote.map(|addr| addr.ip())) {
+                Ok(resources) => Response::new(StatusCode::_207, &config.headers)
+                    .xml(webdav::multistatus(&resources, &find)),
+                Err(webdav::Error::TooMany) => {
+                    log::info(format_args!("PROPFIND over too many resources, {}", client(stream, Some(request))));
+                    Response::new(StatusCode::_403, &config.headers)
+                        .xml(String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n"))
+                },
+                Err(webdav::Error::Io(err)) if err.kind() == ErrorKind::NotFound => output_error(config, request, stream, StatusCode::_404),
+                Err(webdav::Error::Io(err)) => failed(err)
+            }
+        },
+        "PROPPATCH" => {
+            if !target.exists() {
+                return output_error(config, request, stream, StatusCode::_404);
+            }
+            if webdav::locked(&target, &tokens, false, false) {
+                return locked();
+            }
+            let updates = match webdav::updates(&request.body) {
+                Some(updates) => updates,
+                None => return bad("with an invalid body")
+            };
+            match webdav::proppatch(&target, &request.path, &updates) {
+                Ok(xml) => Response::new(StatusCode::_207, &config.headers).xml(xml),
+                Err(err) => failed(err)
+            }
+        },
+        "MKCOL" => {
+            if !request.body.is_empty() {
+                return Response::new(StatusCode::_415, &config.headers)
+                    .text("415");
+            }
+            if webdav::locked(&target, &tokens, true, false) {
+                return locked();
+            }
+            match fs::create_dir(&target) {
+                Ok(()) => Response::new(StatusCode::_201, &config.headers).text("201"),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => Response::new(StatusCode::_405, &config.headers)
+                    .header("Allow", &config.methods.join(", "))
+                    .text("405"),
+                Err(err) if err.kind() == ErrorKind::NotFound || err.kind() == ErrorKind::NotADirectory => {
+                    Response::new(StatusCode::_409, &config.headers).text("409")
+                },
+                Err(err) => failed(err)
+            }
+        },
+        "PUT" => {
+            // Only bodies with a length are read
+            if request.headers.contains_key("transfer-encoding") {
+                return Response::new(StatusCode::_411, &config.headers)
+                    .text("411");
+            }
+            if target.is_dir() || request.path.ends_with('/') {
+                return Response::new(StatusCode::_405, &config.headers)
+                    .header("Allow", &config.methods.join(", "))
+                    .text("405");
+            }
+            // Parents aren't made up, unlike `upload`
+            if !target.parent().is_some_and(Path::is_dir) {
+                return Response::new(StatusCode::_409, &config.headers).text("409");
+            }
+            if webdav::locked(&target, &tokens, true, false) {
+                return locked();
+            }
+            match upload::save(&target, &request.body, true) {
+                Ok(true) => Response::new(StatusCode::_201, &config.headers).text("201"),
+                Ok(false) => Response::new(StatusCode::_204, &config.headers).empty(),
+                Err(upload::Error::Conflict) => Response::new(StatusCode::_409, &config.headers).text("409"),
+                Err(upload::Error::Io(err)) => failed(err)
+            }
+        },
+        "DELETE" => {
+            if root {
+                return forbidden("of the root");
+            }
+            if webdav::locked(&target, &tokens, true, true) {
+                return locked();
+            }
+            match webdav::delete(&target) {
+                Ok(()) => {
+                    webdav::release(&target);
+                    Response::new(StatusCode::_204, &config.headers).empty()
+                },
+                Err(manage::Error::NotFound) => output_error(config, request, stream, StatusCode::_404),
+                Err(manage::Error::Io(err)) => failed(err),
+                Err(_) => Response::new(StatusCode::_409, &config.headers).text("409")
+            }
+        },
+        "COPY" | "MOVE" => dav_transfer(request, config, stream, &target, &tokens),
+        "LOCK" => dav_lock(request, config, &target, &tokens),
+        "UNLOCK" => {
+            let token = request.headers.get("lock-token").map(|value| value.trim().trim_start_matches('<').trim_end_matches('>'));
+            match token {
+                Some(token) if webdav::unlock(&target, token) => Response::new(StatusCode::_204, &config.headers).empty(),
+                Some(_) => Response::new(StatusCode::_409, &config.headers).text("409"),
+                None => bad("without a Lock-Token")
             }
+        },
+        _ => Response::new(StatusCode::_405, &config.headers).text("405")
+    }
+
+}
+
+// COPY and MOVE to the Destination header, held to the same rules as the source
+fn dav_transfer(request: &Request, config: &ServerConfig, stream: &TcpStream, target: &Path, tokens: &[String]) -> Vec<u8> {
+
+    let forbidden = |reason: &str| {
+        log::info(format_args!("{} {}, {}", request.method, reason, client(stream, Some(request))));
+        Response::new(StatusCode::_403, &config.headers)
+            .text("403")
+    };
+    let moving = request.method == "MOVE";
+    let depth = match Depth::new(request.headers.get("depth"), Depth::Infinity) {
+        Some(Depth::Infinity) => Depth::Infinity,
+        Some(Depth::Zero) if !moving => Depth::Zero,
+        _ => {
+            log::info(format_args!("{} with an invalid Depth, {}", request.method, client(stream, Some(request))));
+            return Response::new(StatusCode::_400, &config.headers)
+                .text("400");
+        }
+    };
+    let path = match request.headers.get("destination").and_then(|value| manage::destination(value)) {
+        Some(path) => path,
+        None => {
+            log::info(format_args!("{} without a valid Destination, {}", request.method, client(stream, Some(request))));
+            return Response::new(StatusCode::_400, &config.headers)
+                .text("400");
+        }
+    };
+    let allowed = request.remote.is_none_or(|addr| cidr::allowed(config.access(&path), &addr.ip()));
+    if !config.webdav.as_ref().is_some_and(|option| option.allows(&path)) || !allowed {
+        return forbidden("to a destination that isn't allowed");
+    }
+    let destination = match upload::confine(&config.root, &path) {
+        Some(destination) if destination != Path::new(&config.root) => destination,
+        _ => return forbidden("to outside of the root")
+    };
+    if destination == target || (moving && target == Path::new(&config.root)) {
+        return forbidden("onto itself");
+    }
+    if destination.file_name().and_then(|name| name.to_str()).is_some_and(webdav::is_sidecar) {
+        return forbidden("onto the properties of another resource");
+    }
+    if (moving && webdav::locked(target, tokens, true, true)) || webdav::locked(&destination, tokens, true, true) {
+        log::info(format_args!("{} of a locked resource, {}", request.method, client(stream, Some(request))));
+        return Response::new(StatusCode::_423, &config.headers)
+            .text("423");
+    }
+
+    let dir = match fs::symlink_metadata(target) {
+        Ok(meta) => meta.is_dir(),
+        Err(_) => return output_error(config, request, stream, StatusCode::_404)
+    };
+    let overwrite = request.headers.get("overwrite").is_none_or(|value| !value.trim().eq_ignore_ascii_case("f"));
+    let conflict = || Response::new(StatusCode::_409, &config.headers).text("409");
+    let failed = |err: io::Error| {
+        log::error(format_args!("{} to \"{}\" failed ({}), {}", request.method, path, err, client(stream, Some(request))));
+        output_error(config, request, stream, StatusCode::_500)
+    };
+
+    let result = if moving {
+        manage::rename(target, &destination, overwrite, true).inspect(|_| {
+            webdav::moved(target, &destination, dir);
+            webdav::release(target);
+        })
+    }else {
+        if destination.starts_with(target) || !destination.parent().is_some_and(Path::is_dir) {
+            return conflict();
         }
+        let existed = fs::symlink_metadata(&destination).is_ok();
+        if existed && !overwrite {
+            return Response::new(StatusCode::_412, &config.headers).text("412");
+        }
+        // What was there goes first, properties and all
+        let cleared = if existed { webdav::delete(&destination) } else { Ok(()) };
+        cleared
+            .and_then(|_| webdav::copy(target, &destination, depth == Depth::Infinity).map_err(manage::Error::Io))
+            .map(|_| !existed)
+    };
+
+    match result {
+        Ok(true) => Response::new(StatusCode::_201, &config.headers)
+            .header("Location", &utf8_percent_encode(&path, HREF_ENCODE_SET).to_string())
+            .text("201"),
+        Ok(false) => Response::new(StatusCode::_204, &config.headers).empty(),
+        Err(manage::Error::NotFound) => output_error(config, request, stream, StatusCode::_404),
+        Err(manage::Error::Conflict) => conflict(),
+        Err(manage::Error::Exists) => Response::new(StatusCode::_412, &config.headers).text("412"),
+        Err(manage::Error::Io(err)) => failed(err)
+    }
+
+}
+
+// LOCK takes a new lock, or renews one of the If header without a body
+fn dav_lock(request: &Request, config: &ServerConfig, target: &Path, tokens: &[String]) -> Vec<u8> {
+
+    let timeout = webdav::timeout(request.headers.get("timeout"));
+    if request.body.iter().all(u8::is_ascii_whitespace) {
+        return match webdav::refresh(target, tokens, timeout) {
+            Some(lock) => Response::new(StatusCode::_200, &config.headers)
+                .xml(webdav::discovery(&lock)),
+            None => Response::new(StatusCode::_412, &config.headers).text("412")
+        };
+    }
+
+    let info = webdav::lockinfo(&request.body);
+    let depth = Depth::new(request.headers.get("depth"), Depth::Infinity);
+    let (info, infinite) = match (info, depth) {
+        (Some(info), Some(Depth::Zero)) => (info, false),
+        (Some(info), Some(Depth::Infinity)) => (info, true),
+        _ => return Response::new(StatusCode::_400, &config.headers).text("400")
+    };
+    let exists = target.exists();
+    if !exists && !target.parent().is_some_and(Path::is_dir) {
+        return Response::new(StatusCode::_409, &config.headers).text("409");
+    }
+    let lock = match webdav::lock(target, &request.path, info, infinite, timeout) {
+        Some(lock) => lock,
+        None => return Response::new(StatusCode::_423, &config.headers).text("423")
     };
+    // Locking a name that is free makes an empty file of it
+    if !exists {
+        if let Err(err) = File::create(target) {
+            log::error(format_args!("Creating \"{}\" for a lock failed ({})", request.path, err));
+            webdav::unlock(target, &lock.token);
+            return Response::new(StatusCode::_500, &config.headers).text("500");
+        }
+    }
+    Response::new(if exists { StatusCode::_200 } else { StatusCode::_201 }, &config.headers)
+        .header("Lock-Token", &format!("<{}>", lock.token))
+        .xml(webdav::discovery(&lock))
+
+}
 
+// attachment; filename="..." for every client, filename* keeps names that aren't ASCII
+fn disposition(name: &str) -> String {
+    let plain: String = name.chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect();
+    let mut encoded = String::new();
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        }else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", plain, encoded)
+}
+
+// Missing files are expected, anything else is worth a look
+fn open_failed(err: &io::Error, path: &str, request: &Request, stream: &TcpStream) {
+    if err.kind() == ErrorKind::NotFound {
+        log::info(format_args!("\"{}\" is not found ({}), {}", path, err, client(stream, Some(request))));
+    }else {
+        log::error(format_args!("Opening \"{}\" failed ({}), {}", path, err, client(stream, Some(request))));
+    }
 }
 
 
@@ -542,7 +1708,7 @@ pub fn fill_path(root: &str, file: &str) -> String {
 }
 
 
-fn output_error(config: &ServerConfig, stream: &TcpStream, status: StatusCode) -> Vec<u8> {
+fn output_error(config: &ServerConfig, request: &Request, stream: &TcpStream, status: StatusCode) -> Vec<u8> {
 
     let (path, text) = match status {
         StatusCode::_404 => (&config.error._404, "404"),
@@ -556,9 +1722,14 @@ fn output_error(config: &ServerConfig, stream: &TcpStream, status: StatusCode) -
             Ok(f) => {
                 return res
                     .content_type(get_extension(path))
-                    .file(&stream, f);
+                    .sent(&request.sent)
+                    .file(stream, f);
             },
-            Err(_) => {
+            Err(err) => {
+                log::error(format_args!(
+                    "Opening the error page \"{}\" failed ({}), {}",
+                    path, err, client(stream, Some(request))
+                ));
                 return res.text(text);
             }
         }
@@ -614,102 +1785,6 @@ fn fallbacks(file: &str, exts: &Vec<String>) -> Result<Fallbacks, ()> {
 }
 
 
-fn response_dir_html(path: &str, title: &str, show_time: bool, show_size: bool) -> String {
-
-    let dir = match fs::read_dir(path) {
-        Ok(dir) => dir,
-        Err(_) => {
-            return String::new()
-        }
-    };
-
-    let (
-        mut files,
-        mut main,
-        mut first
-    ) = (
-        String::new(),
-        "auto auto 1fr",
-        "1 / 4"
-    );
-
-    if !show_time && !show_size{
-        main = "auto";
-        first = "1 / 2";
-    }else if (!show_time && show_size) || (show_time && !show_size) {
-        main = "auto 1fr";
-        first = "1 / 3";
-    }
-
-    for x in dir {
-
-        let entry = match x {
-            Ok(entry) => entry,
-            Err(_) => continue
-        }.path();
-
-        let filename = match entry.file_name() {
-            Some(d) => {
-                match d.to_str() {
-                    Some(n) => {
-                        if entry.is_dir() {
-                            format!("{}/", n)
-                        }else {
-                            n.to_string()
-                        }
-                    },
-                    None => continue
-                }
-            },
-            None => continue
-        };
-
-        let _ = write!(files, "<a href=\"{}\">{}</a>", filename, filename);
-
-        if show_size || show_time {
-            if let Ok(meta) = fs::metadata(&entry) {
-                if show_time {
-                    let time = if let Ok(time) = meta.modified() {
-                        let datetime: DateTime<Local> = DateTime::from(time);
-                        datetime.format("%Y-%m-%d %H:%M").to_string()
-                    }else {
-                        String::new()
-                    };
-                    files.push_str(&format!("<time>{}</time>", time));
-                }
-                if show_size {
-                    let size = if entry.is_file() {
-                        bytes_to_size(meta.len() as f64)
-                    }else {
-                        String::new()
-                    };
-                    files.push_str(&format!("<span>{}</span>", size));
-                }
-            }
-        }
-
-    }
-
-    TEMPLATE
-        .replace("{title}", title)
-        .replace("{main}", main)
-        .replace("{first}", first)
-        .replace("{files}", &files)
-
-}
-
-
-fn bytes_to_size(bytes: f64) -> String {
-    let k = 1024_f64;
-    let sizes = ["B", "KB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
-    if bytes <= 1_f64 {
-        return format!("{:.2} B", bytes)
-    }
-    let i = (bytes.ln() / k.ln()) as i32;
-    format!("{:.2} {}", bytes / k.powi(i), sizes[i as usize])
-}
-
-
 #[test]
 fn test_get_extension() {
     assert_eq!(get_extension("index.html"), "html");
@@ -717,16 +1792,3 @@ fn test_get_extension() {
     assert_eq!(get_extension(""), "");
     assert_eq!(get_extension("index"), "");
 }
-
-#[test]
-fn test_bytes_to_size() {
-    assert_eq!(bytes_to_size(0_f64), "0.00 B");
-    assert_eq!(bytes_to_size(0.5_f64), "0.50 B");
-    assert_eq!(bytes_to_size(1_f64), "1.00 B");
-    assert_eq!(bytes_to_size(12_f64), "12.00 B");
-    assert_eq!(bytes_to_size(1024_f64), "1.00 KB");
-    assert_eq!(bytes_to_size(1025_f64), "1.00 KB");
-    assert_eq!(bytes_to_size(123456_f64), "120.56 KB");
-    assert_eq!(bytes_to_size(99999999_f64), "95.37 MB");
-    assert_eq!(bytes_to_size(99999999999_f64), "93.13 GB");
-}
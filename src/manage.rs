@@ -0,0 +1,118 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use percent_encoding::percent_decode;
+
+// DELETE and MOVE of files below the root
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    // A directory that isn't empty, or a parent that is missing
+    Conflict,
+    // The destination exists and may not be replaced
+    Exists,
+    Io(io::Error)
+}
+
+fn error(err: io::Error) -> Error {
+    match err.kind() {
+        ErrorKind::NotFound => Error::NotFound,
+        ErrorKind::DirectoryNotEmpty | ErrorKind::NotADirectory => Error::Conflict,
+        _ => Error::Io(err)
+    }
+}
+
+// Links are removed, not what they point to
+pub fn delete(target: &Path, recursive: bool) -> Result<(), Error> {
+    let meta = fs::symlink_metadata(target).map_err(error)?;
+    if !meta.is_dir() {
+        return fs::remove_file(target).map_err(error);
+    }
+    if recursive {
+        fs::remove_dir_all(target).map_err(error)
+    }else {
+        fs::remove_dir(target).map_err(error)
+    }
+}
+
+// Returns whether the destination is new
+pub fn rename(from: &Path, to: &Path, overwrite: bool, recursive: bool) -> Result<bool, Error> {
+
+    let source = fs::symlink_metadata(from).map_err(error)?;
+    if to.starts_with(from) {
+        return Err(Error::Conflict);
+    }
+    if !to.parent().is_some_and(Path::is_dir) {
+        return Err(Error::Conflict);
+    }
+
+    let existing = fs::symlink_metadata(to).ok();
+    if let Some(meta) = &existing {
+        if !overwrite {
+            return Err(Error::Exists);
+        }
+        // A file takes the place of another in one step
+        if meta.is_dir() || source.is_dir() {
+            delete(to, recursive)?;
+        }
+    }
+    fs::rename(from, to).map_err(error)?;
+    Ok(existing.is_none())
+
+}
+
+// Path of a Destination header, which may be a whole URL
+pub fn destination(value: &str) -> Option<String> {
+    let value = value.trim();
+    let path = match value.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => value
+    };
+    let path = path.split(['?', '#']).next()?;
+    if !path.starts_with('/') {
+        return None;
+    }
+    percent_decode(path.as_bytes()).decode_utf8().ok().map(|path| path.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use crate::manage::{self, Error};
+
+    #[test]
+    fn test_delete_and_rename() {
+        let dir = env::temp_dir().join(format!("see-manage-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::write(dir.join("a/b/c.txt"), "c").unwrap();
+        fs::write(dir.join("d.txt"), "d").unwrap();
+
+        assert!(matches!(manage::delete(&dir.join("a"), false), Err(Error::Conflict)));
+        assert!(matches!(manage::delete(&dir.join("x"), false), Err(Error::NotFound)));
+
+        assert!(manage::rename(&dir.join("d.txt"), &dir.join("a/e.txt"), false, false).unwrap());
+        fs::write(dir.join("d.txt"), "d").unwrap();
+        assert!(matches!(manage::rename(&dir.join("d.txt"), &dir.join("a/e.txt"), false, false), Err(Error::Exists)));
+        assert!(!manage::rename(&dir.join("d.txt"), &dir.join("a/e.txt"), true, false).unwrap());
+        assert!(matches!(manage::rename(&dir.join("a"), &dir.join("a/b/a"), true, false), Err(Error::Conflict)));
+        assert!(matches!(manage::rename(&dir.join("a/e.txt"), &dir.join("x/e.txt"), true, false), Err(Error::Conflict)));
+
+        manage::delete(&dir.join("a"), true).unwrap();
+        assert!(!dir.join("a").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_destination() {
+        assert_eq!(manage::destination("http://example.com:8080/a%20b/c.txt?x"), Some(String::from("/a b/c.txt")));
+        assert_eq!(manage::destination("/d/"), Some(String::from("/d/")));
+        assert_eq!(manage::destination("http://example.com"), None);
+        assert_eq!(manage::destination("relative.txt"), None);
+    }
+
+}
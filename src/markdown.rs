@@ -0,0 +1,763 @@
+use std::fmt::Write;
+use crate::html::{self, MARKDOWN};
+
+// Markdown to HTML: the blocks and spans of CommonMark, GitHub tables and ~~strikethrough~~.
+// Raw HTML is shown as text so a file can't bring its own scripts,
+// reference links and footnotes aren't supported
+
+// Larger files are sent as they are
+pub const MAX: u64 = 4 * 1024 * 1024;
+
+pub fn is_markdown(ext: &str) -> bool {
+    ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown")
+}
+
+// A whole page, `title` is escaped
+pub fn page(source: &str, title: &str) -> String {
+    MARKDOWN
+        .replace("{title}", &html::escape(title))
+        .replace("{body}", &html(source))
+}
+
+pub fn html(source: &str) -> String {
+    let source = source.replace("\r\n", "\n").replace('\t', "    ");
+    let lines: Vec<&str> = source.lines().collect();
+    let mut out = String::new();
+    blocks(&lines, false, &mut out);
+    out
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+// ``` or ~~~ and the language after it
+fn fence(text: &str) -> Option<(String, &str)> {
+    let c = text.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = text.len() - text.trim_start_matches(c).len();
+    let info = text[len..].trim();
+    if len < 3 || (c == '`' && info.contains('`')) {
+        return None;
+    }
+    Some((text[..len].to_string(), info.split_whitespace().next().unwrap_or("")))
+}
+
+// # Title #
+fn heading(text: &str) -> Option<(usize, &str)> {
+    let level = text.len() - text.trim_start_matches('#').len();
+    let rest = &text[level..];
+    if level == 0 || level > 6 || !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    let content = rest.trim();
+    let stripped = content.trim_end_matches('#');
+    if stripped.is_empty() || stripped.ends_with(' ') {
+        return Some((level, stripped.trim_end()));
+    }
+    Some((level, content))
+}
+
+// ---, ***, ___ with spaces in between
+fn rule(text: &str) -> bool {
+    let marks: Vec<char> = text.chars().filter(|c| *c != ' ').collect();
+    marks.len() >= 3 && "-*_".contains(marks[0]) && marks.iter().all(|c| *c == marks[0])
+}
+
+struct Marker {
+    ordered: bool,
+    // The bullet, or the '.' or ')' after the number
+    kind: char,
+    start: u64,
+    // Where the content starts
+    width: usize
+}
+
+fn marker(line: &str) -> Option<Marker> {
+
+    let lead = indent(line);
+    if lead >= 4 {
+        return None;
+    }
+    let rest = &line[lead..];
+
+    let (ordered, kind, start, len) = match rest.chars().next()? {
+        c @ ('-' | '*' | '+') => (false, c, 0, 1),
+        _ => {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let kind = rest[digits..].chars().next()?;
+            if digits == 0 || digits > 9 || (kind != '.' && kind != ')') {
+                return None;
+            }
+            (true, kind, rest[..digits].parse().ok()?, digits + 1)
+        }
+    };
+
+    let after = &rest[len..];
+    if !after.is_empty() && !after.starts_with(' ') {
+        return None;
+    }
+    // Code in an item keeps the spaces beyond one
+    let spaces = match after.len() - after.trim_start_matches(' ').len() {
+        n if n == 0 || n > 4 || after.trim().is_empty() => 1,
+        n => n
+    };
+    Some(Marker { ordered, kind, start, width: lead + len + spaces })
+
+}
+
+// Lines that end a paragraph without a blank line
+fn interrupts(line: &str) -> bool {
+    if indent(line) >= 4 {
+        return false;
+    }
+    let text = line.trim_start();
+    fence(text).is_some()
+        || heading(text).is_some()
+        || rule(text)
+        || text.starts_with('>')
+        || marker(line).is_some_and(|m| !line[m.width.min(line.len())..].trim().is_empty() && (!m.ordered || m.start == 1))
+}
+
+// `tight` list items hold their text without <p>
+fn blocks(lines: &[&str], tight: bool, out: &mut String) {
+
+    let mut i = 0;
+
+    while i < lines.len() {
+
+        let line = lines[i];
+        let text = line.trim_start();
+        if text.is_empty() {
+            i += 1;
+            continue;
+        }
+
+        if indent(line) >= 4 {
+            let mut code = vec![];
+            while i < lines.len() && (indent(lines[i]) >= 4 || lines[i].trim().is_empty()) {
+                code.push(lines[i].get(4..).unwrap_or(""));
+                i += 1;
+            }
+            while code.last().is_some_and(|line| line.trim().is_empty()) {
+                code.pop();
+            }
+            let _ = writeln!(out, "<pre><code>{}\n</code></pre>", html::escape(&code.join("\n")));
+            continue;
+        }
+
+        if let Some((open, info)) = fence(text) {
+            let lead = indent(line);
+            let mut code = vec![];
+            i += 1;
+            while i < lines.len() {
+                let close = lines[i].trim();
+                if indent(lines[i]) < 4 && close.starts_with(&open) && close.chars().all(|c| open.starts_with(c)) {
+                    i += 1;
+                    break;
+                }
+                // As far in as the opening fence
+                code.push(&lines[i][indent(lines[i]).min(lead)..]);
+                i += 1;
+            }
+            let mut body = code.join("\n");
+            if !code.is_empty() {
+                body.push('\n');
+            }
+            if info.is_empty() {
+                let _ = writeln!(out, "<pre><code>{}</code></pre>", html::escape(&body));
+            }else {
+                let _ = writeln!(out, "<pre><code class=\"language-{}\">{}</code></pre>", html::escape(info), html::escape(&body));
+            }
+            continue;
+        }
+
+        if let Some((level, content)) = heading(text) {
+            let _ = write!(out, "<h{} id=\"{}\">", level, slug(content));
+            inline(content, out);
+            let _ = writeln!(out, "</h{}>", level);
+            i += 1;
+            continue;
+        }
+
+        if rule(text) {
+            out.push_str("<hr />\n");
+            i += 1;
+            continue;
+        }
+
+        if text.starts_with('>') {
+            let mut quoted = vec![];
+            while i < lines.len() {
+                match lines[i].trim_start().strip_prefix('>') {
+                    Some(rest) => quoted.push(rest.strip_prefix(' ').unwrap_or(rest)),
+                    None => break
+                }
+                i += 1;
+            }
+            out.push_str("<blockquote>\n");
+            blocks(&quoted, false, out);
+            out.push_str("</blockquote>\n");
+            continue;
+        }
+
+        if marker(line).is_some() {
+            i = list(lines, i, out);
+            continue;
+        }
+
+        if let Some(aligns) = lines.get(i + 1).and_then(|next| delimiter(next)) {
+            if text.contains('|') && cells(text).len() == aligns.len() {
+                i = table(lines, i, &aligns, out);
+                continue;
+            }
+        }
+
+        // Paragraph, or a heading underlined with === or ---
+        let start = i;
+        let mut level = 0;
+        i += 1;
+        while i < lines.len() {
+            let next = lines[i].trim();
+            if next.is_empty() {
+                break;
+            }
+            if indent(lines[i]) < 4 && next.chars().all(|c| c == '=') {
+                level = 1;
+                break;
+            }
+            if indent(lines[i]) < 4 && next.chars().all(|c| c == '-') {
+                level = 2;
+                break;
+            }
+            if interrupts(lines[i]) {
+                break;
+            }
+            i += 1;
+        }
+        let content: Vec<&str> = lines[start..i].iter().map(|line| line.trim_start()).collect();
+        let content = content.join("\n");
+        let content = content.trim_end();
+        if level > 0 {
+            let _ = write!(out, "<h{} id=\"{}\">", level, slug(content));
+            inline(content, out);
+            let _ = writeln!(out, "</h{}>", level);
+            i += 1;
+        }else if tight {
+            inline(content, out);
+            out.push('\n');
+        }else {
+            out.push_str("<p>");
+            inline(content, out);
+            out.push_str("</p>\n");
+        }
+
+    }
+
+}
+
+// Items of one kind of marker, returns the line after the list
+fn list(lines: &[&str], start: usize, out: &mut String) -> usize {
+
+    let first = match marker(lines[start]) {
+        Some(first) => first,
+        None => return start + 1
+    };
+    let mut items: Vec<Vec<&str>> = vec![];
+    let mut loose = false;
+    let mut i = start;
+
+    while i < lines.len() {
+
+        let m = match marker(lines[i]) {
+            Some(m) if m.ordered == first.ordered && m.kind == first.kind => m,
+            _ => break
+        };
+        let mut item = vec![lines[i].get(m.width..).unwrap_or("")];
+        i += 1;
+
+        while i < lines.len() {
+            let line = lines[i];
+            if line.trim().is_empty() {
+                item.push("");
+            }else if indent(line) >= m.width {
+                item.push(&line[m.width..]);
+            }else if item.last().is_some_and(|last| !last.trim().is_empty()) && !interrupts(line) && marker(line).is_none() {
+                // Lazy continuation of a paragraph
+                item.push(line.trim_start());
+            }else {
+                break;
+            }
+            i += 1;
+        }
+
+        let mut blank = false;
+        while item.last().is_some_and(|line| line.trim().is_empty()) {
+            item.pop();
+            blank = true;
+        }
+        if item.iter().any(|line| line.trim().is_empty())
+            || (blank && lines.get(i).and_then(|line| marker(line)).is_some_and(|m| m.kind == first.kind)) {
+            loose = true;
+        }
+        items.push(item);
+
+    }
+
+    if !first.ordered {
+        out.push_str("<ul>\n");
+    }else if first.start == 1 {
+        out.push_str("<ol>\n");
+    }else {
+        let _ = writeln!(out, "<ol start=\"{}\">", first.start);
+    }
+    for item in items {
+        out.push_str("<li>");
+        blocks(&item, !loose, out);
+        if out.ends_with('\n') {
+            out.pop();
+        }
+        out.push_str("</li>\n");
+    }
+    out.push_str(if first.ordered { "</ol>\n" } else { "</ul>\n" });
+
+    i
+
+}
+
+// | a | b |, `\|` stays in the cell
+fn cells(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => line
+    };
+    let mut cells = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '|' if !escaped => {
+                cells.push(line[start..i].trim());
+                start = i + 1;
+            },
+            _ => escaped = false
+        }
+    }
+    cells.push(line[start..].trim());
+    cells
+}
+
+// |:---|:---:|---:|
+fn delimiter(line: &str) -> Option<Vec<&'static str>> {
+    if !line.contains('-') || indent(line) >= 4 {
+        return None;
+    }
+    cells(line).iter()
+        .map(|cell| {
+            let left = cell.starts_with(':');
+            let right = cell.ends_with(':');
+            let dashes = cell.trim_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (left, right) {
+                (true, true) => " style=\"text-align: center\"",
+                (true, false) => " style=\"text-align: left\"",
+                (false, true) => " style=\"text-align: right\"",
+                (false, false) => ""
+            })
+        })
+        .collect()
+}
+
+fn table(lines: &[&str], start: usize, aligns: &[&str], out: &mut String) -> usize {
+
+    let row = |line: &str, tag: &str, out: &mut String| {
+        let cells = cells(line);
+        out.push_str("<tr>\n");
+        for (i, align) in aligns.iter().enumerate() {
+            let _ = write!(out, "<{}{}>", tag, align);
+            // Even inside code
+            inline(&cells.get(i).unwrap_or(&"").replace("\\|", "|"), out);
+            let _ = writeln!(out, "</{}>", tag);
+        }
+        out.push_str("</tr>\n");
+    };
+
+    out.push_str("<table>\n<thead>\n");
+    row(lines[start], "th", out);
+    out.push_str("</thead>\n");
+
+    let mut i = start + 2;
+    if i < lines.len() && !lines[i].trim().is_empty() && !interrupts(lines[i]) {
+        out.push_str("<tbody>\n");
+        while i < lines.len() && !lines[i].trim().is_empty() && !interrupts(lines[i]) {
+            row(lines[i], "td", out);
+            i += 1;
+        }
+        out.push_str("</tbody>\n");
+    }
+    out.push_str("</table>\n");
+
+    i
+
+}
+
+// Anchor of a heading, as GitHub makes them
+fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None
+        })
+        .collect()
+}
+
+fn push(out: &mut String, c: char) {
+    out.push_str(&html::escape(c.encode_utf8(&mut [0; 4])));
+}
+
+fn collect(chars: &[char]) -> String {
+    chars.iter().collect()
+}
+
+// Length of the run of `c` at `at`
+fn run(chars: &[char], at: usize, c: char) -> usize {
+    chars[at..].iter().take_while(|d| **d == c).count()
+}
+
+// The closing run of exactly `n` backticks for the code span at `at`
+fn code_end(chars: &[char], at: usize, n: usize) -> Option<usize> {
+    let mut j = at + n;
+    while j < chars.len() {
+        if chars[j] == '`' {
+            let len = run(chars, j, '`');
+            if len == n {
+                return Some(j);
+            }
+            j += len;
+        }else {
+            j += 1;
+        }
+    }
+    None
+}
+
+// Where the emphasis opened at `at` closes: a run of exactly `n` of `c`
+// after something other than a space, code spans and escapes skipped
+fn closer(chars: &[char], at: usize, c: char, n: usize) -> Option<usize> {
+    let mut j = at + n;
+    while j < chars.len() {
+        match chars[j] {
+            '\\' => j += 2,
+            '`' => {
+                let len = run(chars, j, '`');
+                j = code_end(chars, j, len).map_or(j + len, |end| end + len);
+            },
+            d if d == c => {
+                let len = run(chars, j, c);
+                let after = chars.get(j + len).copied().unwrap_or(' ');
+                if len == n && !chars[j - 1].is_whitespace() && j > at + n
+                    && (c != '_' || !after.is_alphanumeric()) {
+                    return Some(j);
+                }
+                j += len;
+            },
+            _ => j += 1
+        }
+    }
+    None
+}
+
+// Links and images may only go to these, or stay on the site
+fn href(url: &str) -> String {
+    let scheme = url.split(['/', '?', '#']).next().unwrap_or("");
+    if let Some((scheme, _)) = scheme.split_once(':') {
+        let scheme = scheme.to_lowercase();
+        if !["http", "https", "mailto", "ftp"].contains(&scheme.as_str()) {
+            return String::from("#");
+        }
+    }
+    html::escape(&url.replace(' ', "%20"))
+}
+
+// [text](url "title") at `at`, returns the text, url, title and the end
+fn link(chars: &[char], at: usize) -> Option<(usize, String, Option<String>, usize)> {
+
+    let mut depth = 0;
+    let mut j = at;
+    let close = loop {
+        match chars.get(j)? {
+            '\\' => j += 1,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    break j;
+                }
+            },
+            _ => {}
+        }
+        j += 1;
+    };
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+
+    let mut j = close + 2;
+    let skip = |j: &mut usize| while chars.get(*j).is_some_and(|c| c.is_whitespace()) { *j += 1 };
+    skip(&mut j);
+
+    let mut url = String::new();
+    if chars.get(j) == Some(&'<') {
+        j += 1;
+        while *chars.get(j)? != '>' {
+            url.push(chars[j]);
+            j += 1;
+        }
+        j += 1;
+    }else {
+        let mut parens = 0;
+        while let Some(c) = chars.get(j) {
+            match c {
+                '\\' if chars.get(j + 1).is_some_and(|c| c.is_ascii_punctuation()) => {
+                    url.push(chars[j + 1]);
+                    j += 2;
+                    continue;
+                },
+                '(' => parens += 1,
+                ')' if parens == 0 => break,
+                ')' => parens -= 1,
+                c if c.is_whitespace() => break,
+                _ => {}
+            }
+            url.push(*c);
+            j += 1;
+        }
+    }
+    skip(&mut j);
+
+    let mut title = None;
+    if let Some(quote) = chars.get(j).filter(|c| **c == '"' || **c == '\'') {
+        let quote = *quote;
+        let mut text = String::new();
+        j += 1;
+        while *chars.get(j)? != quote {
+            text.push(chars[j]);
+            j += 1;
+        }
+        title = Some(text);
+        j += 1;
+        skip(&mut j);
+    }
+    if chars.get(j) != Some(&')') {
+        return None;
+    }
+    Some((close, url, title, j + 1))
+
+}
+
+fn inline(text: &str, out: &mut String) {
+    let chars: Vec<char> = text.chars().collect();
+    spans(&chars, out);
+}
+
+fn spans(chars: &[char], out: &mut String) {
+
+    let mut i = 0;
+
+    while i < chars.len() {
+
+        let c = chars[i];
+        match c {
+            '\\' => match chars.get(i + 1) {
+                Some('\n') => {
+                    out.push_str("<br />\n");
+                    i += 2;
+                },
+                Some(next) if next.is_ascii_punctuation() => {
+                    push(out, *next);
+                    i += 2;
+                },
+                _ => {
+                    out.push('\\');
+                    i += 1;
+                }
+            },
+            // Two spaces at the end of a line break it
+            '\n' => {
+                let trimmed = out.trim_end_matches(' ').len();
+                let hard = out.len() - trimmed >= 2;
+                out.truncate(trimmed);
+                out.push_str(if hard { "<br />\n" } else { "\n" });
+                i += 1;
+            },
+            '`' => {
+                let n = run(chars, i, '`');
+                match code_end(chars, i, n) {
+                    Some(end) => {
+                        let code = collect(&chars[i + n..end]).replace('\n', " ");
+                        let code = match code.strip_prefix(' ').and_then(|code| code.strip_suffix(' ')) {
+                            Some(inner) if !inner.trim().is_empty() => inner.to_string(),
+                            _ => code
+                        };
+                        let _ = write!(out, "<code>{}</code>", html::escape(&code));
+                        i = end + n;
+                    },
+                    None => {
+                        out.push_str(&"`".repeat(n));
+                        i += n;
+                    }
+                }
+            },
+            '!' if chars.get(i + 1) == Some(&'[') => match link(chars, i + 1) {
+                Some((close, url, title, end)) => {
+                    let alt = collect(&chars[i + 2..close]);
+                    let _ = write!(out, "<img src=\"{}\" alt=\"{}\"", href(&url), html::escape(&alt));
+                    if let Some(title) = title {
+                        let _ = write!(out, " title=\"{}\"", html::escape(&title));
+                    }
+                    out.push_str(" />");
+                    i = end;
+                },
+                None => {
+                    out.push('!');
+                    i += 1;
+                }
+            },
+            '[' => match link(chars, i) {
+                Some((close, url, title, end)) => {
+                    let _ = write!(out, "<a href=\"{}\"", href(&url));
+                    if let Some(title) = title {
+                        let _ = write!(out, " title=\"{}\"", html::escape(&title));
+                    }
+                    out.push('>');
+                    spans(&chars[i + 1..close], out);
+                    out.push_str("</a>");
+                    i = end;
+                },
+                None => {
+                    out.push('[');
+                    i += 1;
+                }
+            },
+            // <https://example.com>, <mail@example.com>
+            '<' => {
+                let end = chars[i + 1..].iter().position(|c| *c == '>' || c.is_whitespace() || *c == '<');
+                let url = end.filter(|end| chars[i + 1 + end] == '>').map(|end| collect(&chars[i + 1..i + 1 + end]));
+                match url {
+                    Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                        let _ = write!(out, "<a href=\"{}\">{}</a>", href(&url), html::escape(&url));
+                        i += url.chars().count() + 2;
+                    },
+                    Some(url) if url.contains('@') && !url.contains(':') => {
+                        let _ = write!(out, "<a href=\"mailto:{}\">{}</a>", html::escape(&url), html::escape(&url));
+                        i += url.chars().count() + 2;
+                    },
+                    _ => {
+                        out.push_str("&lt;");
+                        i += 1;
+                    }
+                }
+            },
+            '*' | '_' | '~' => {
+                let n = run(chars, i, c);
+                let before = if i > 0 { chars[i - 1] } else { ' ' };
+                let opens = chars.get(i + n).is_some_and(|next| !next.is_whitespace())
+                    && (c != '_' || !before.is_alphanumeric());
+                let (open, close, len) = match (c, n) {
+                    ('~', 2) => ("<del>", "</del>", 2),
+                    ('~', _) => ("", "", n),
+                    (_, 1) => ("<em>", "</em>", 1),
+                    (_, 2) => ("<strong>", "</strong>", 2),
+                    (_, 3) => ("<em><strong>", "</strong></em>", 3),
+                    _ => ("", "", n)
+                };
+                match closer(chars, i, c, len).filter(|_| opens && !open.is_empty()) {
+                    Some(end) => {
+                        out.push_str(open);
+                        spans(&chars[i + len..end], out);
+                        out.push_str(close);
+                        i = end + len;
+                    },
+                    None => {
+                        for _ in 0..n {
+                            push(out, c);
+                        }
+                        i += n;
+                    }
+                }
+            },
+            // &copy; and &#169; are kept
+            '&' => {
+                let name = chars[i + 1..].iter().take_while(|c| c.is_ascii_alphanumeric() || **c == '#').count();
+                if name > 0 && name <= 32 && chars.get(i + 1 + name) == Some(&';') {
+                    out.extend(&chars[i..i + name + 2]);
+                    i += name + 2;
+                }else {
+                    out.push_str("&amp;");
+                    i += 1;
+                }
+            },
+            c => {
+                push(out, c);
+                i += 1;
+            }
+        }
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::markdown::html;
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!(html("# Title #\n\nSome *text*\nmore"), "<h1 id=\"title\">Title</h1>\n<p>Some <em>text</em>\nmore</p>\n");
+        assert_eq!(html("Sub\n---"), "<h2 id=\"sub\">Sub</h2>\n");
+        assert_eq!(html("***"), "<hr />\n");
+        assert_eq!(html("```rust\nfn main() {}\n<b>\n```"), "<pre><code class=\"language-rust\">fn main() &#123;&#125;\n&lt;b&gt;\n</code></pre>\n");
+        assert_eq!(html("    indented\n\n    code"), "<pre><code>indented\n\ncode\n</code></pre>\n");
+        assert_eq!(html("> quote\n> **more**"), "<blockquote>\n<p>quote\n<strong>more</strong></p>\n</blockquote>\n");
+        assert_eq!(html("<script>alert(1)</script>"), "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n");
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(html("- a\n- b\n  - c\n"), "<ul>\n<li>a</li>\n<li>b\n<ul>\n<li>c</li>\n</ul></li>\n</ul>\n");
+        assert_eq!(html("3. a\n\n4. b"), "<ol start=\"3\">\n<li><p>a</p></li>\n<li><p>b</p></li>\n</ol>\n");
+        assert_eq!(html("- a\n* b"), "<ul>\n<li>a</li>\n</ul>\n<ul>\n<li>b</li>\n</ul>\n");
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            html("| Name | Size |\n|:--|--:|\n| `a\\|b` | 1 |\n\nafter"),
+            concat!(
+                "<table>\n<thead>\n<tr>\n<th style=\"text-align: left\">Name</th>\n<th style=\"text-align: right\">Size</th>\n</tr>\n</thead>\n",
+                "<tbody>\n<tr>\n<td style=\"text-align: left\"><code>a|b</code></td>\n<td style=\"text-align: right\">1</td>\n</tr>\n</tbody>\n</table>\n",
+                "<p>after</p>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        assert_eq!(html("[see](https://example.com \"Home\") ![logo](<a b.png>)"),
+            "<p><a href=\"https://example.com\" title=\"Home\">see</a> <img src=\"a%20b.png\" alt=\"logo\" /></p>\n");
+        assert_eq!(html("[x](javascript:alert(1))"), "<p><a href=\"#\">x</a></p>\n");
+        assert_eq!(html("**bold *em* x** snake_case_name ~~gone~~ 2 * 3"),
+            "<p><strong>bold <em>em</em> x</strong> snake_case_name <del>gone</del> 2 * 3</p>\n");
+        assert_eq!(html("`` a ` b `` \\*not\\* a &copy; & b"), "<p><code>a ` b</code> *not* a &copy; &amp; b</p>\n");
+        assert_eq!(html("line  \nbreak <https://a.b/c>"), "<p>line<br />\nbreak <a href=\"https://a.b/c\">https://a.b/c</a></p>\n");
+    }
+
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+use crate::pool::ThreadPool;
+
+// Upper bounds of the response time histogram, in seconds
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Anything else is counted as "OTHER" to keep the number of series bounded
+const METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+
+// Everything below is keyed by server, "host:port"
+#[derive(Default)]
+struct Requests {
+    // (server, method, status)
+    count: BTreeMap<(String, &'static str, i32), u64>,
+    bytes: BTreeMap<String, u64>,
+    duration: BTreeMap<String, Histogram>
+}
+
+#[derive(Default)]
+struct Histogram {
+    // Not cumulative, summed up when rendered
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64
+}
+
+static REQUESTS: Mutex<Option<Requests>> = Mutex::new(None);
+static ACTIVE: AtomicI64 = AtomicI64::new(0);
+// Bytes in and out of gzip and deflate
+static COMPRESS_IN: AtomicU64 = AtomicU64::new(0);
+static COMPRESS_OUT: AtomicU64 = AtomicU64::new(0);
+static POOL: RwLock<Option<Weak<ThreadPool>>> = RwLock::new(None);
+
+// Counts an open connection for as long as it lives
+pub struct Active;
+
+impl Active {
+    pub fn new() -> Active {
+        ACTIVE.fetch_add(1, Ordering::Relaxed);
+        Active
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+
+pub fn active() -> i64 {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+// Report the queue of this pool
+pub fn watch(pool: &Arc<ThreadPool>) {
+    *POOL.write().unwrap_or_else(|err| err.into_inner()) = Some(Arc::downgrade(pool));
+}
+
+pub fn compressed(input: usize, output: usize) {
+    COMPRESS_IN.fetch_add(input as u64, Ordering::Relaxed);
+    COMPRESS_OUT.fetch_add(output as u64, Ordering::Relaxed);
+}
+
+// One answered request
+pub fn request(server: &str, method: &str, status: i32, bytes: u64, duration: Duration) {
+
+    let method = METHODS.iter().find(|m| **m == method).copied().unwrap_or("OTHER");
+    let seconds = duration.as_secs_f64();
+
+    let mut requests = REQUESTS.lock().unwrap_or_else(|err| err.into_inner());
+    let requests = requests.get_or_insert_with(Requests::default);
+
+    *requests.count.entry((server.to_string(), method, status)).or_insert(0) += 1;
+    *requests.bytes.entry(server.to_string()).or_insert(0) += bytes;
+
+    let histogram = requests.duration.entry(server.to_string()).or_default();
+    if let Some(i) = BUCKETS.iter().position(|le| seconds <= *le) {
+        histogram.buckets[i] += 1;
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+
+}
+
+// Prometheus text exposition format
+pub fn render() -> String {
+
+    let mut out = String::new();
+
+    {
+        let requests = REQUESTS.lock().unwrap_or_else(|err| err.into_inner());
+        let empty = Requests::default();
+        let requests = requests.as_ref().unwrap_or(&empty);
+
+        header(&mut out, "see_requests_total", "counter", "Answered requests");
+        for ((server, method, status), n) in requests.count.iter() {
+            let _ = writeln!(
+                out,
+                "see_requests_total{{server=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape(server), method, status, n
+            );
+        }
+
+        header(&mut out, "see_sent_bytes_total", "counter", "Bytes written in responses");
+        for (server, n) in requests.bytes.iter() {
+            let _ = writeln!(out, "see_sent_bytes_total{{server=\"{}\"}} {}", escape(server), n);
+        }
+
+        header(&mut out, "see_request_duration_seconds", "histogram", "From accepting the connection to the end of the response");
+        for (server, histogram) in requests.duration.iter() {
+            let server = escape(server);
+            let mut count = 0;
+            for (le, n) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+                count += n;
+                let _ = writeln!(out, "see_request_duration_seconds_bucket{{server=\"{}\",le=\"{}\"}} {}", server, le, count);
+            }
+            let _ = writeln!(out, "see_request_duration_seconds_bucket{{server=\"{}\",le=\"+Inf\"}} {}", server, histogram.count);
+            let _ = writeln!(out, "see_request_duration_seconds_sum{{server=\"{}\"}} {}", server, histogram.sum);
+            let _ = writeln!(out, "see_request_duration_seconds_count{{server=\"{}\"}} {}", server, histogram.count);
+        }
+    }
+
+    header(&mut out, "see_connections_active", "gauge", "Open client connections");
+    let _ = writeln!(out, "see_connections_active {}", active());
+
+    let pool = POOL.read().unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .and_then(Weak::upgrade);
+    if let Some(pool) = pool {
+        header(&mut out, "see_worker_queue_depth", "gauge", "Connections waiting for a worker");
+        let _ = writeln!(out, "see_worker_queue_depth {}", pool.queued());
+    }
+
+    // The ratio is out / in
+    header(&mut out, "see_compress_in_bytes_total", "counter", "Bytes given to gzip and deflate");
+    let _ = writeln!(out, "see_compress_in_bytes_total {}", COMPRESS_IN.load(Ordering::Relaxed));
+    header(&mut out, "see_compress_out_bytes_total", "counter", "Bytes produced by gzip and deflate");
+    let _ = writeln!(out, "see_compress_out_bytes_total {}", COMPRESS_OUT.load(Ordering::Relaxed));
+
+    out
+
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// Label values are quoted
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+    use crate::metrics::{self, Active};
+
+    #[test]
+    fn test_render() {
+        metrics::request("test.render:80", "GET", 200, 100, Duration::from_millis(20));
+        metrics::request("test.render:80", "GET", 200, 50, Duration::from_secs(20));
+        metrics::request("test.render:80", "BREW", 404, 10, Duration::from_millis(1));
+        let active = Active::new();
+
+        let text = metrics::render();
+        assert!(text.contains("see_requests_total{server=\"test.render:80\",method=\"GET\",status=\"200\"} 2\n"));
+        assert!(text.contains("see_requests_total{server=\"test.render:80\",method=\"OTHER\",status=\"404\"} 1\n"));
+        assert!(text.contains("see_sent_bytes_total{server=\"test.render:80\"} 160\n"));
+        assert!(text.contains("see_request_duration_seconds_bucket{server=\"test.render:80\",le=\"0.005\"} 1\n"));
+        assert!(text.contains("see_request_duration_seconds_bucket{server=\"test.render:80\",le=\"0.025\"} 2\n"));
+        assert!(text.contains("see_request_duration_seconds_bucket{server=\"test.render:80\",le=\"10\"} 2\n"));
+        assert!(text.contains("see_request_duration_seconds_bucket{server=\"test.render:80\",le=\"+Inf\"} 3\n"));
+        assert!(text.contains("see_request_duration_seconds_count{server=\"test.render:80\"} 3\n"));
+        assert!(text.contains("# TYPE see_connections_active gauge\n"));
+        drop(active);
+    }
+
+}
@@ -3,11 +3,15 @@
 use std::thread;
 use std::sync::mpsc;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use crate::log;
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Job>,
+    // Taken when the pool is joined
+    sender: Mutex<Option<mpsc::Sender<Job>>>,
+    shared: Arc<Shared>
 }
 
 trait FnBox {
@@ -20,60 +24,143 @@ impl<F: FnOnce()> FnBox for F {
     }
 }
 
-type Job = Box<FnBox + Send + 'static>;
+type Job = Box<dyn FnBox + Send + 'static>;
+
+// State shared by the pool and its workers
+struct Shared {
+    receiver: Mutex<mpsc::Receiver<Job>>,
+    workers: Mutex<Vec<Worker>>,
+    // Jobs waiting for a worker
+    queued: AtomicUsize,
+    capacity: usize
+}
+
+// A panicking job must not take the whole pool down with it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
 impl ThreadPool {
 
-    pub fn new(size: usize) -> ThreadPool {
+    pub fn new(size: usize, capacity: usize) -> ThreadPool {
 
         let (sender, receiver) = mpsc::channel();
 
-        let receiver = Arc::new(Mutex::new(receiver));
-
-        let mut workers = Vec::with_capacity(size);
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            workers: Mutex::new(Vec::with_capacity(size)),
+            queued: AtomicUsize::new(0),
+            capacity
+        });
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            let worker = Worker::new(id, Arc::clone(&shared));
+            lock(&shared.workers).push(worker);
         }
 
         ThreadPool {
-            workers,
-            sender,
+            sender: Mutex::new(Some(sender)),
+            shared
         }
 
     }
 
-    pub fn execute<F>(&self, f: F)
+    // Run `f(arg)` on a worker, `arg` is handed back when the queue is full
+    pub fn execute<T, F>(&self, arg: T, f: F) -> Result<(), T>
         where
-            F: FnOnce() + Send + 'static
+            T: Send + 'static,
+            F: FnOnce(T) + Send + 'static
     {
-        let job = Box::new(f);
+        let sender = lock(&self.sender);
+        let sender = match &*sender {
+            Some(sender) => sender,
+            None => return Err(arg)
+        };
+
+        if self.shared.queued.fetch_add(1, Ordering::SeqCst) >= self.shared.capacity {
+            self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(arg);
+        }
+
+        let job: Job = Box::new(move || f(arg));
+        if sender.send(job).is_err() {
+            self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    // Jobs waiting for a worker
+    pub fn queued(&self) -> usize {
+        self.shared.queued.load(Ordering::SeqCst)
+    }
+
+    // Stop accepting jobs, finish the queued ones and wait for every worker,
+    // gives up after `timeout` and returns whether all the work is done
+    pub fn join(&self, timeout: Option<Duration>) -> bool {
+
+        lock(&self.sender).take();
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let worker = lock(&self.shared.workers).pop();
+            let worker = match worker {
+                Some(worker) => worker,
+                None => return true
+            };
+            match deadline {
+                Some(deadline) => {
+                    while !worker.thread.is_finished() {
+                        if Instant::now() >= deadline {
+                            lock(&self.shared.workers).push(worker);
+                            return false;
+                        }
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    let _ = worker.thread.join();
+                },
+                None => {
+                    let _ = worker.thread.join();
+                }
+            }
+        }
 
-        self.sender.send(job).unwrap();
     }
 
 }
 
 
 struct Worker {
-    id: usize,
-    thread: thread::JoinHandle<()>,
+    thread: thread::JoinHandle<()>
 }
 
 impl Worker {
 
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-
-        let thread = thread::spawn(move || {
-            loop {
-                let job = receiver.lock().unwrap().recv().unwrap();
-                job.call_box();
-            }
-        });
+    fn new(id: usize, shared: Arc<Shared>) -> Worker {
+
+        let thread = thread::Builder::new()
+            .name(format!("worker-{}", id))
+            .spawn(move || {
+                let sentinel = Sentinel {
+                    id,
+                    shared: Arc::clone(&shared)
+                };
+                loop {
+                    let job = lock(&shared.receiver).recv();
+                    match job {
+                        Ok(job) => {
+                            shared.queued.fetch_sub(1, Ordering::SeqCst);
+                            job.call_box();
+                        },
+                        // The pool has been joined
+                        Err(_) => break
+                    }
+                }
+                drop(sentinel);
+            })
+            .expect("failed to spawn worker thread");
 
         Worker {
-            id,
-            thread,
+            thread
         }
 
     }
@@ -81,3 +168,85 @@ impl Worker {
 }
 
 
+// Replaces a worker whose job panicked
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            log::error(format_args!("Worker {} panicked, respawning", self.id));
+            let worker = Worker::new(self.id, Arc::clone(&self.shared));
+            let mut workers = lock(&self.shared.workers);
+            workers.retain(|w| !w.thread.is_finished());
+            workers.push(worker);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+    use std::sync::mpsc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::pool::ThreadPool;
+
+    #[test]
+    fn test_join_runs_queued_jobs() {
+        let pool = ThreadPool::new(2, 100);
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..50 {
+            pool.execute(count.clone(), |count| {
+                count.fetch_add(1, Ordering::SeqCst);
+            }).unwrap();
+        }
+        assert!(pool.join(None));
+        assert_eq!(count.load(Ordering::SeqCst), 50);
+    }
+
+    #[test]
+    fn test_panic_respawns_worker() {
+        let pool = ThreadPool::new(1, 10);
+        pool.execute((), |_| panic!("job failed")).unwrap();
+        let (tx, rx) = mpsc::channel();
+        pool.execute(tx, |tx| tx.send(1).unwrap()).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+        assert!(pool.join(None));
+    }
+
+    #[test]
+    fn test_full_queue_rejects() {
+        let pool = ThreadPool::new(1, 1);
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started, wait) = mpsc::channel();
+        // Occupy the only worker
+        pool.execute((), move |_| {
+            started.send(()).unwrap();
+            let _ = rx.recv();
+        }).unwrap();
+        wait.recv().unwrap();
+        assert_eq!(pool.execute(1, |_| {}), Ok(()));
+        assert_eq!(pool.execute(2, |_| {}), Err(2));
+        drop(tx);
+        assert!(pool.join(None));
+    }
+
+    #[test]
+    fn test_join_timeout() {
+        let pool = ThreadPool::new(1, 1);
+        let (tx, rx) = mpsc::channel::<()>();
+        pool.execute((), move |_| {
+            let _ = rx.recv();
+        }).unwrap();
+        assert!(!pool.join(Some(Duration::from_millis(50))));
+        assert_eq!(pool.execute((), |_| {}), Err(()));
+        drop(tx);
+        assert!(pool.join(None));
+    }
+
+}
@@ -0,0 +1,179 @@
+use std::io::{self, ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// PROXY protocol, sent by a load balancer ahead of the request:
+// https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt
+
+const V2_SIGNATURE: [u8; 12] = [0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a];
+
+// Longest version 1 line, "\r\n" included
+const V1_MAX: usize = 107;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+// Longest header of either version, the addresses and TLVs of version 2 take up to 64 KB
+pub const MAX: usize = 16 + 65535;
+
+// The header at the start of `buffer` and its length, None while more of it is to come
+pub fn parse(buffer: &[u8]) -> io::Result<Option<(Option<SocketAddr>, usize)>> {
+    let mut rest = buffer;
+    match read(&mut rest) {
+        Ok(addr) => Ok(Some((addr, buffer.len() - rest.len()))),
+        Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err)
+    }
+}
+
+// Read the header and nothing more, returns the address of the client
+// or None for health checks of the balancer itself (LOCAL, UNKNOWN)
+pub fn read<R: Read>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+
+    // Shorter than any header of either version
+    let mut start = [0; 12];
+    stream.read_exact(&mut start)?;
+
+    if start == V2_SIGNATURE {
+        return v2(stream);
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+
+    let mut line = start.to_vec();
+    let mut byte = [0; 1];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX {
+            return Err(invalid("PROXY protocol header too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    v1(&line[..line.len() - 2])
+
+}
+
+// PROXY TCP4 192.168.0.1 192.168.0.11 56324 443
+fn v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+
+    let line = std::str::from_utf8(line).map_err(|_| invalid("invalid PROXY protocol header"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    match parts.get(1) {
+        Some(&"UNKNOWN") => return Ok(None),
+        Some(&"TCP4") | Some(&"TCP6") if parts.len() == 6 => {},
+        _ => return Err(invalid("invalid PROXY protocol header"))
+    }
+
+    let ip: IpAddr = parts[2].parse().map_err(|_| invalid("invalid PROXY protocol address"))?;
+    let port: u16 = parts[4].parse().map_err(|_| invalid("invalid PROXY protocol port"))?;
+    if ip.is_ipv4() != (parts[1] == "TCP4") {
+        return Err(invalid("invalid PROXY protocol address"));
+    }
+    Ok(Some(SocketAddr::new(ip, port)))
+
+}
+
+// Binary: version and command, family, length, addresses, TLVs
+fn v2<R: Read>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+
+    let mut head = [0; 4];
+    stream.read_exact(&mut head)?;
+    if head[0] >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    let mut body = vec![0; u16::from_be_bytes([head[2], head[3]]) as usize];
+    stream.read_exact(&mut body)?;
+
+    match head[0] & 0x0f {
+        // LOCAL
+        0 => return Ok(None),
+        // PROXY
+        1 => {},
+        _ => return Err(invalid("unsupported PROXY protocol command"))
+    }
+
+    match head[1] >> 4 {
+        // AF_INET
+        1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        },
+        // AF_INET6
+        2 if body.len() >= 36 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+        },
+        // AF_UNSPEC, AF_UNIX
+        0 | 3 => Ok(None),
+        _ => Err(invalid("invalid PROXY protocol address"))
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Read;
+    use crate::proxy::{self, V2_SIGNATURE};
+
+    #[test]
+    fn test_v1() {
+        let mut input: &[u8] = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(proxy::read(&mut input).unwrap(), Some("192.168.0.1:56324".parse().unwrap()));
+        // The request is left in place
+        let mut rest = String::new();
+        input.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GET / HTTP/1.1\r\n");
+
+        let mut input: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n";
+        assert_eq!(proxy::read(&mut input).unwrap(), Some("[2001:db8::1]:4000".parse().unwrap()));
+
+        let mut input: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(proxy::read(&mut input).unwrap(), None);
+
+        let mut input: &[u8] = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert!(proxy::read(&mut input).is_err());
+        let mut input: &[u8] = b"PROXY TCP4 2001:db8::1 1.2.3.4 1 2\r\n";
+        assert!(proxy::read(&mut input).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let input = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(proxy::parse(&input[..20]).unwrap(), None);
+        assert_eq!(proxy::parse(input).unwrap(), Some((Some("192.168.0.1:56324".parse().unwrap()), 47)));
+        assert!(proxy::parse(b"GET / HTTP/1.1\r\n").is_err());
+    }
+
+    #[test]
+    fn test_v2() {
+        let mut input = V2_SIGNATURE.to_vec();
+        input.extend_from_slice(&[0x21, 0x11, 0, 12, 10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0, 80]);
+        input.extend_from_slice(b"GET");
+        let mut reader = &input[..];
+        assert_eq!(proxy::read(&mut reader).unwrap(), Some("10.0.0.1:8080".parse().unwrap()));
+        assert_eq!(reader, b"GET");
+
+        let mut input = V2_SIGNATURE.to_vec();
+        input.extend_from_slice(&[0x21, 0x21, 0, 36]);
+        input.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        input.extend_from_slice(&[0; 16]);
+        input.extend_from_slice(&[0, 80, 0, 80]);
+        assert_eq!(proxy::read(&mut &input[..]).unwrap(), Some("[2001:db8::1]:80".parse().unwrap()));
+
+        // LOCAL, with a TLV to skip
+        let mut input = V2_SIGNATURE.to_vec();
+        input.extend_from_slice(&[0x20, 0x00, 0, 3, 1, 0, 0]);
+        let mut reader = &input[..];
+        assert_eq!(proxy::read(&mut reader).unwrap(), None);
+        assert!(reader.is_empty());
+    }
+
+}
@@ -1,16 +1,31 @@
 
 
 extern crate percent_encoding;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
 use percent_encoding::percent_decode;
+use crate::response::Sent;
 
 
 #[derive(Debug)]
 pub struct Request {
     pub method: String,
     pub path: String,
+    // As sent, `param` decodes the parts
     pub query: Option<String>,
-    pub headers: HashMap<String, String>
+    // Request target and protocol as sent by the client
+    pub uri: String,
+    pub version: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    // The response must be finished by then
+    pub deadline: Option<Instant>,
+    // Filled in while the response is written
+    pub sent: Cell<Sent>,
+    // Client address, as told by a trusted proxy if there is one
+    pub remote: Option<SocketAddr>
 }
 
 
@@ -34,6 +49,9 @@ impl Request {
         }else {
             return Err(());
         };
+        let line = Request::split(&req[0], &SPACE);
+        let uri = String::from_utf8_lossy(&line[1]).to_string();
+        let version = String::from_utf8_lossy(&line[2]).to_string();
 
         let mut headers = HashMap::new();
         if req.len() > 1 {
@@ -54,7 +72,13 @@ impl Request {
             method,
             path,
             query,
-            headers
+            uri,
+            version,
+            headers,
+            body: vec![],
+            deadline: None,
+            sent: Cell::new(Sent::default()),
+            remote: None
         })
 
     }
@@ -85,6 +109,24 @@ impl Request {
 
     }
 
+    // Value of a query string parameter, the first one if repeated.
+    // Split before decoding, so an encoded '&' or '=' stays in its part
+    pub fn param(&self, name: &str) -> Option<String> {
+        self.query.as_deref()?
+            .trim_start_matches('?')
+            .split('&')
+            .find_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                if Request::decode(key)? == name { Request::decode(value) } else { None }
+            })
+    }
+
+    // A form sends spaces as '+', a literal '+' comes as %2B
+    fn decode(part: &str) -> Option<String> {
+        let part = part.replace('+', " ");
+        percent_decode(part.as_bytes()).decode_utf8().ok().map(|part| part.to_string())
+    }
+
     fn first(buff: &Vec<u8>) -> Result<(String, String, Option<String>), ()> {
 
         let line = Request::split(buff, &SPACE);
@@ -100,14 +142,7 @@ impl Request {
             return Err(());
         };
 
-        let query = if let Some(q) = url.get(1) {
-            match percent_decode(q).decode_utf8() {
-                Ok(q) => Some(format!("?{}", q)),
-                Err(_) => None
-            }
-        }else {
-            None
-        };
+        let query = url.get(1).map(|q| format!("?{}", String::from_utf8_lossy(q)));
 
         Ok((method, path, query))
 
@@ -177,6 +212,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_param() {
+        let req = Request::new(b"GET /abc?q=c%2B%2B+notes&x=a%26b&a%3Db=c&q=2&e HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(req.query.as_deref(), Some("?q=c%2B%2B+notes&x=a%26b&a%3Db=c&q=2&e"));
+        assert_eq!(req.param("q").as_deref(), Some("c++ notes"));
+        assert_eq!(req.param("x").as_deref(), Some("a&b"));
+        assert_eq!(req.param("a=b").as_deref(), Some("c"));
+        assert_eq!(req.param("a"), None);
+        assert_eq!(req.param("e").as_deref(), Some(""));
+    }
+
     #[test]
     fn test_parse_request() {
         let buff = b"\
@@ -189,6 +235,8 @@ mod tests {
         let req = Request::new(buff).unwrap();
         assert_eq!(&req.method, "GET");
         assert_eq!(&req.path, "/abc");
+        assert_eq!(&req.uri, "/abc?type=1");
+        assert_eq!(&req.version, "HTTP/1.1");
         assert_eq!(req.headers.get("host").unwrap(), "127.0.0.1");
         assert_eq!(req.headers.get("accept-encoding").unwrap(), "gzip, deflate, br");
     }
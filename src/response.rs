@@ -2,42 +2,174 @@
 
 use crate::config::Header;
 use crate::config::ContentEncoding;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fmt::Write as FmtWrite;
 use std::net::TcpStream;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader, BufWriter, ErrorKind};
 use std::io::prelude::*;
+use std::time::{Duration, Instant};
 use crate::compress;
+use crate::metrics;
 
 
 #[derive(Default, Debug)]
-pub struct Response {
+pub struct Response<'a> {
     version: &'static str,
     status: i32,
     header: HashMap<String, String>,
     body: Vec<u8>,
-    encoding: ContentEncoding
+    encoding: ContentEncoding,
+    deadline: Option<Instant>,
+    sent: Option<&'a Cell<Sent>>
+}
+
+// What actually went out on the wire, `status` is 0 until the header is written
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct Sent {
+    pub status: i32,
+    pub header: u64,
+    pub body: u64,
+    // Why the response was cut short
+    pub error: Option<ErrorKind>
+}
+
+impl Sent {
+
+    // Of a response built in memory
+    pub fn of(response: &[u8]) -> Sent {
+
+        let status = response.get(9..12)
+            .and_then(|s| std::str::from_utf8(s).ok())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        let header = response.windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map_or(response.len(), |i| i + 4);
+
+        Sent {
+            status,
+            header: header as u64,
+            body: (response.len() - header) as u64,
+            error: None
+        }
+
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.header + self.body
+    }
+
 }
 
 pub enum StatusCode {
     _200,
+    _201,
+    _204,
+    _207,
     _301,
     _302,
+    _303,
     _400,
     _401,
     _403,
     _404,
     _405,
-    _500
+    _408,
+    _409,
+    _411,
+    _412,
+    _413,
+    _415,
+    _423,
+    _429,
+    _500,
+    _503
 }
 
 const SERVER_NAME: &str = env!("CARGO_PKG_NAME");
 
-impl Response {
+// Content type of a file extension
+pub fn mime(ext: &str) -> &'static str {
+
+    match ext {
+        "aac" => "audio/aac",
+        "abw" => "application/x-abiword",
+        "arc" => "application/x-freearc",
+        "avi" => "video/x-msvideo",
+        "azw" => "application/vnd.amazon.ebook",
+        "bin" => "application/octet-stream",
+        "bmp" => "image/bmp",
+        "bz" => "application/x-bzip",
+        "bz2" => "application/x-bzip2",
+        "csh" => "application/x-csh",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "eot" => "application/vnd.ms-fontobject",
+        "epub" => "application/epub+zip",
+        "gif" => "image/gif",
+        "htm" => "text/html",
+        "html" => "text/html",
+        "ico" => "image/vnd.microsoft.icon",
+        "ics" => "text/calendar",
+        "jar" => "application/java-archive",
+        "jpeg" => "image/jpeg",
+        "jpg" => "image/jpeg",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "mjs" => "text/javascript",
+        "mp3" => "audio/mpeg",
+        "mpeg" => "video/mpeg",
+        "mpkg" => "application/vnd.apple.installer+xml",
+        "odp" => "application/vnd.oasis.opendocument.presentation",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "oga" => "audio/ogg",
+        "ogv" => "video/ogg",
+        "ogx" => "application/ogg",
+        "otf" => "font/otf",
+        "png" => "image/png",
+        "pdf" => "application/pdf",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "rar" => "application/x-rar-compressed",
+        "rtf" => "application/rtf",
+        "sh" => "application/x-sh",
+        "svg" => "image/svg+xml",
+        "swf" => "application/x-shockwave-flash",
+        "tar" => "application/x-tar",
+        "tif" => "image/tiff",
+        "tiff" => "image/tiff",
+        "ttf" => "font/ttf",
+        "txt" => "text/plain",
+        "vsd" => "application/vnd.visio",
+        "wav" => "audio/wav",
+        "weba" => "audio/webm",
+        "webm" => "video/webm",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "xhtml" => "application/xhtml+xml",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "xml" => "text/xml",  // application/xml
+        "xul" => "application/vnd.mozilla.xul+xml",
+        "zip" => "application/zip",
+        "3gp" => "video/3gpp",  // audio/video
+        "3g2" => "video/3gpp2",  // audio/3gpp2
+        "7z" => "application/x-7z-compressed",
+        _ => "application/octet-stream"
+    }
+
+}
+
+impl<'a> Response<'a> {
 
     // HTTP response
-    pub fn new(status: StatusCode, headers: &Vec<Header>) -> Response {
+    pub fn new(status: StatusCode, headers: &[Header]) -> Response<'a> {
 
         let mut response = Response::default();
 
@@ -45,14 +177,27 @@ impl Response {
 
         response.status = match status {
             StatusCode::_200 => 200,
+            StatusCode::_201 => 201,
+            StatusCode::_204 => 204,
+            StatusCode::_207 => 207,
             StatusCode::_301 => 301,
             StatusCode::_302 => 302,
+            StatusCode::_303 => 303,
             StatusCode::_400 => 400,
             StatusCode::_401 => 401,
             StatusCode::_403 => 403,
             StatusCode::_404 => 404,
             StatusCode::_405 => 405,
-            StatusCode::_500 => 500
+            StatusCode::_408 => 408,
+            StatusCode::_409 => 409,
+            StatusCode::_411 => 411,
+            StatusCode::_412 => 412,
+            StatusCode::_413 => 413,
+            StatusCode::_415 => 415,
+            StatusCode::_423 => 423,
+            StatusCode::_429 => 429,
+            StatusCode::_500 => 500,
+            StatusCode::_503 => 503
         };
 
         // Add service name
@@ -70,7 +215,7 @@ impl Response {
     }
 
     // Set header
-    pub fn header(mut self, key: &str, value:  &str) -> Response {
+    pub fn header(mut self, key: &str, value:  &str) -> Response<'a> {
 
         self.header.insert(key.to_string(), value.to_string());
         self
@@ -78,91 +223,36 @@ impl Response {
     }
 
     // Set the content-type based on the file extension
-    pub fn content_type(mut self, ext: &str) -> Response {
-
-        let value = match &ext.as_ref() {
-            &"aac" => "audio/aac",
-            &"abw" => "application/x-abiword",
-            &"arc" => "application/x-freearc",
-            &"avi" => "video/x-msvideo",
-            &"azw" => "application/vnd.amazon.ebook",
-            &"bin" => "application/octet-stream",
-            &"bmp" => "image/bmp",
-            &"bz" => "application/x-bzip",
-            &"bz2" => "application/x-bzip2",
-            &"csh" => "application/x-csh",
-            &"css" => "text/css",
-            &"csv" => "text/csv",
-            &"doc" => "application/msword",
-            &"docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
-            &"eot" => "application/vnd.ms-fontobject",
-            &"epub" => "application/epub+zip",
-            &"gif" => "image/gif",
-            &"htm" => "text/html",
-            &"html" => "text/html",
-            &"ico" => "image/vnd.microsoft.icon",
-            &"ics" => "text/calendar",
-            &"jar" => "application/java-archive",
-            &"jpeg" => "image/jpeg",
-            &"jpg" => "image/jpeg",
-            &"js" => "text/javascript",
-            &"json" => "application/json",
-            &"mjs" => "text/javascript",
-            &"mp3" => "audio/mpeg",
-            &"mpeg" => "video/mpeg",
-            &"mpkg" => "application/vnd.apple.installer+xml",
-            &"odp" => "application/vnd.oasis.opendocument.presentation",
-            &"ods" => "application/vnd.oasis.opendocument.spreadsheet",
-            &"odt" => "application/vnd.oasis.opendocument.text",
-            &"oga" => "audio/ogg",
-            &"ogv" => "video/ogg",
-            &"ogx" => "application/ogg",
-            &"otf" => "font/otf",
-            &"png" => "image/png",
-            &"pdf" => "application/pdf",
-            &"ppt" => "application/vnd.ms-powerpoint",
-            &"pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
-            &"rar" => "application/x-rar-compressed",
-            &"rtf" => "application/rtf",
-            &"sh" => "application/x-sh",
-            &"svg" => "image/svg+xml",
-            &"swf" => "application/x-shockwave-flash",
-            &"tar" => "application/x-tar",
-            &"tif" => "image/tiff",
-            &"tiff" => "image/tiff",
-            &"ttf" => "font/ttf",
-            &"txt" => "text/plain",
-            &"vsd" => "application/vnd.visio",
-            &"wav" => "audio/wav",
-            &"weba" => "audio/webm",
-            &"webm" => "video/webm",
-            &"webp" => "image/webp",
-            &"woff" => "font/woff",
-            &"woff2" => "font/woff2",
-            &"xhtml" => "application/xhtml+xml",
-            &"xls" => "application/vnd.ms-excel",
-            &"xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-            &"xml" => "text/xml",  // application/xml
-            &"xul" => "application/vnd.mozilla.xul+xml",
-            &"zip" => "application/zip",
-            &"3gp" => "video/3gpp",  // audio/video
-            &"3g2" => "video/3gpp2",  // audio/3gpp2
-            &"7z" => "application/x-7z-compressed",
-            _ => "application/octet-stream"
-        };
+    pub fn content_type(mut self, ext: &str) -> Response<'a> {
 
-        self.header.insert("Content-Type".to_string(), value.to_string());
+        self.header.insert("Content-Type".to_string(), mime(ext).to_string());
         self
 
     }
 
-    pub fn compress(mut self, encoding: ContentEncoding) -> Response {
+    pub fn compress(mut self, encoding: ContentEncoding) -> Response<'a> {
 
         self.encoding = encoding;
         self
 
     }
 
+    // Give up sending the file after this point
+    pub fn deadline(mut self, deadline: Option<Instant>) -> Response<'a> {
+
+        self.deadline = deadline;
+        self
+
+    }
+
+    // Record what a streamed response writes
+    pub fn sent(mut self, sent: &'a Cell<Sent>) -> Response<'a> {
+
+        self.sent = Some(sent);
+        self
+
+    }
+
     pub fn rewrite(mut self, location: String) -> Vec<u8> {
 
         self.header.insert("Location".to_string(), location);
@@ -186,9 +276,35 @@ impl Response {
 
     }
 
+    pub fn json(mut self, json: String) -> Vec<u8> {
+
+        self.body = json.into_bytes();
+        self.header.insert("Content-Type".to_string(), "application/json".to_string());
+        self.build()
+
+    }
+
+    pub fn xml(mut self, xml: String) -> Vec<u8> {
+
+        self.body = xml.into_bytes();
+        self.header.insert("Content-Type".to_string(), "application/xml; charset=utf-8".to_string());
+        self.build()
+
+    }
+
+    // No body at all, as for 204
+    pub fn empty(self) -> Vec<u8> {
+
+        self.build()
+
+    }
+
     fn build(mut self) -> Vec<u8> {
        
-        self.header.insert("Content-Length".to_string(), self.body.len().to_string());
+        // 204 has no length to tell
+        if self.status != 204 {
+            self.header.insert("Content-Length".to_string(), self.body.len().to_string());
+        }
 
         let mut res = String::new();
 
@@ -225,7 +341,14 @@ impl Response {
 
         match self.encoding {
             ContentEncoding::None => {
-                let meta = file.metadata().unwrap();
+                let meta = match file.metadata() {
+                    Ok(meta) => meta,
+                    Err(err) => {
+                        self.record(|sent| sent.error = Some(err.kind()));
+                        self.status = 500;
+                        return self.text("500");
+                    }
+                };
                 self.header.insert(
                     String::from("Content-Length"),
                     meta.len().to_string()
@@ -245,60 +368,202 @@ impl Response {
             let _ = write!(res, "{}: {}\r\n", key, value);
         }
         res.push_str("\r\n");
-        stream.write(res.as_bytes()).unwrap();
+        if let Err(err) = stream.write_all(res.as_bytes()) {
+            return self.fail(err.kind());
+        }
+        self.record(|sent| {
+            sent.status = self.status;
+            sent.header = res.len() as u64;
+        });
+
+        let send_timeout = stream.write_timeout().unwrap_or(None);
 
         loop {
-            let mut render = BufReader::new(&file);
-            if let Ok(data) = render.fill_buf() {
-                if data.len() != 0 {
-                    match self.encoding {
-                        ContentEncoding::Gzip => {
-                            if let Ok(data) = compress::gzip(data) {
-                                let hex = format!("{:x}", data.len()).as_bytes().to_vec();
-                                let rn = b"\r\n";
-                                let chunk = [hex, rn.to_vec(), data, rn.to_vec()].concat();
-                                if let Err(_) = stream.write(&chunk) {
-                                    break;
-                                }
-                            }else {
-                                break;
-                            }
-                        },
-                        ContentEncoding::Deflate => {
-                            if let Ok(data) = compress::deflate(data) {
-                                let hex = format!("{:x}", data.len()).as_bytes().to_vec();
-                                let rn = b"\r\n";
-                                let chunk = [hex, rn.to_vec(), data, rn.to_vec()].concat();
-                                if let Err(_) = stream.write(&chunk) {
-                                    break;
-                                }
-                            }else {
-                                break;
-                            }
-                        },
-                        _ =>  {
-                            if let Err(_) = stream.write(data) {
-                                break;
-                            }
-                        }
-                    };
-                }else {
-                    break;
+            if let Some(deadline) = self.deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return self.fail(ErrorKind::TimedOut);
+                }
+                let remaining = deadline - now;
+                let timeout = match send_timeout {
+                    Some(t) if t < remaining => t,
+                    _ => remaining
+                };
+                if let Err(err) = stream.set_write_timeout(Some(timeout)) {
+                    return self.fail(err.kind());
                 }
-            }else {
+            }
+            let mut render = BufReader::new(&file);
+            let data = match render.fill_buf() {
+                Ok(data) => data,
+                Err(err) => return self.fail(err.kind())
+            };
+            if data.is_empty() {
                 break;
             }
+            let read = data.len();
+            match self.encoding {
+                ContentEncoding::Gzip => {
+                    if let Ok(data) = compress::gzip(data) {
+                        metrics::compressed(read, data.len());
+                        let hex = format!("{:x}", data.len()).as_bytes().to_vec();
+                        let rn = b"\r\n";
+                        let chunk = [hex, rn.to_vec(), data, rn.to_vec()].concat();
+                        if let Err(err) = stream.write_all(&chunk) {
+                            return self.fail(err.kind());
+                        }
+                        self.record(|sent| sent.body += chunk.len() as u64);
+                    }else {
+                        return self.fail(ErrorKind::Other);
+                    }
+                },
+                ContentEncoding::Deflate => {
+                    if let Ok(data) = compress::deflate(data) {
+                        metrics::compressed(read, data.len());
+                        let hex = format!("{:x}", data.len()).as_bytes().to_vec();
+                        let rn = b"\r\n";
+                        let chunk = [hex, rn.to_vec(), data, rn.to_vec()].concat();
+                        if let Err(err) = stream.write_all(&chunk) {
+                            return self.fail(err.kind());
+                        }
+                        self.record(|sent| sent.body += chunk.len() as u64);
+                    }else {
+                        return self.fail(ErrorKind::Other);
+                    }
+                },
+                _ =>  {
+                    if let Err(err) = stream.write_all(data) {
+                        return self.fail(err.kind());
+                    }
+                    let n = data.len() as u64;
+                    self.record(|sent| sent.body += n);
+                }
+            };
         }
 
         // Chunk end
-        if self.encoding != ContentEncoding::None {
-            stream.write(b"0\r\n\r\n").unwrap();
+        if self.encoding != ContentEncoding::None
+            && stream.write_all(b"0\r\n\r\n").is_ok() {
+            self.record(|sent| sent.body += 5);
         }
 
         vec![]
 
     }
 
+    // A body of unknown length, written by `body` as it is produced
+    pub fn stream<F>(mut self, stream: &TcpStream, body: F) -> Vec<u8>
+        where F: FnOnce(&mut dyn Write) -> io::Result<()> {
+
+        self.header.insert(
+            String::from("Transfer-Encoding"),
+            String::from("chunked")
+        );
+
+        let mut res = String::new();
+        let _ = write!(res, "{} {}\r\n", self.version, self.status);
+        for (key, value) in self.header.iter() {
+            let _ = write!(res, "{}: {}\r\n", key, value);
+        }
+        res.push_str("\r\n");
+        let mut writer = stream;
+        if let Err(err) = writer.write_all(res.as_bytes()) {
+            return self.fail(err.kind());
+        }
+        self.record(|sent| {
+            sent.status = self.status;
+            sent.header = res.len() as u64;
+        });
+
+        let chunked = Chunked {
+            stream,
+            deadline: self.deadline,
+            timeout: stream.write_timeout().unwrap_or(None),
+            sent: self.sent
+        };
+        // Small writes are gathered into chunks of a useful size
+        let mut out = BufWriter::with_capacity(64 * 1024, chunked);
+        let result = body(&mut out).and_then(|_| out.flush());
+        // Whatever is left after a failure is dropped, not sent
+        let _ = out.into_parts();
+        if let Err(err) = result {
+            return self.fail(err.kind());
+        }
+
+        // Chunk end
+        if writer.write_all(b"0\r\n\r\n").is_ok() {
+            self.record(|sent| sent.body += 5);
+        }
+
+        vec![]
+
+    }
+
+    // The rest of the response is lost
+    fn fail(&self, kind: ErrorKind) -> Vec<u8> {
+
+        self.record(|sent| sent.error = Some(kind));
+        vec![]
+
+    }
+
+    fn record<F: FnOnce(&mut Sent)>(&self, f: F) {
+
+        if let Some(cell) = self.sent {
+            let mut sent = cell.get();
+            f(&mut sent);
+            cell.set(sent);
+        }
+
+    }
+
+}
+
+
+// Each write goes out as one chunk, within the deadline of the response
+struct Chunked<'a> {
+    stream: &'a TcpStream,
+    deadline: Option<Instant>,
+    timeout: Option<Duration>,
+    sent: Option<&'a Cell<Sent>>
+}
+
+impl Write for Chunked<'_> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(deadline) = self.deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(ErrorKind::TimedOut, "response deadline passed"));
+            }
+            let remaining = deadline - now;
+            let timeout = match self.timeout {
+                Some(t) if t < remaining => t,
+                _ => remaining
+            };
+            self.stream.set_write_timeout(Some(timeout))?;
+        }
+
+        let chunk = [format!("{:x}\r\n", buf.len()).as_bytes(), buf, b"\r\n"].concat();
+        let mut stream = self.stream;
+        stream.write_all(&chunk)?;
+        if let Some(cell) = self.sent {
+            let mut sent = cell.get();
+            sent.body += chunk.len() as u64;
+            cell.set(sent);
+        }
+        Ok(buf.len())
+
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
 }
 
 
@@ -0,0 +1,159 @@
+
+use std::io;
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::ptr;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
+
+// Write end of the self-pipe, the only thing a handler may touch
+#[cfg(unix)]
+static WRITER: AtomicI32 = AtomicI32::new(-1);
+
+#[derive(Debug, PartialEq)]
+pub enum Signal {
+    // SIGTERM, SIGINT: stop accepting and drain
+    Shutdown,
+    // SIGHUP: load the configuration file again
+    Reload,
+    // SIGUSR2: hand the listeners to a new process
+    Upgrade,
+    // SIGUSR1: open the log files again
+    Reopen
+}
+
+// Signals are turned into bytes on a pipe so the event loop can poll them
+#[cfg(unix)]
+pub struct Signals {
+    reader: RawFd
+}
+
+#[cfg(unix)]
+extern "C" fn notify(sig: libc::c_int) {
+    let byte = sig as u8;
+    unsafe {
+        libc::write(WRITER.load(Ordering::SeqCst), &byte as *const u8 as *const libc::c_void, 1);
+    }
+}
+
+// Non-blocking and not leaked to child processes
+#[cfg(unix)]
+pub fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for fd in fds.iter() {
+        unsafe {
+            if libc::fcntl(*fd, libc::F_SETFL, libc::O_NONBLOCK) < 0
+                || libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok((fds[0], fds[1]))
+}
+
+// Wait until `fd` is readable, `timeout` in milliseconds (-1 waits forever)
+#[cfg(unix)]
+pub fn wait_readable(fd: RawFd, timeout: i32) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0
+    };
+    unsafe { libc::poll(&mut pollfd, 1, timeout) > 0 }
+}
+
+#[cfg(unix)]
+impl Signals {
+
+    pub fn new() -> io::Result<Signals> {
+
+        let (reader, writer) = pipe()?;
+        WRITER.store(writer, Ordering::SeqCst);
+
+        for sig in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP, libc::SIGUSR1, libc::SIGUSR2].iter() {
+            unsafe {
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction = notify as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                if libc::sigaction(*sig, &action, ptr::null_mut()) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        Ok(Signals { reader })
+
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.reader
+    }
+
+    // Signals received since the last call, without blocking
+    pub fn read(&self) -> Vec<Signal> {
+
+        let mut signals = vec![];
+        let mut buffer = [0u8; 64];
+
+        loop {
+            let n = unsafe {
+                libc::read(self.reader, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+            };
+            if n <= 0 {
+                break;
+            }
+            for sig in buffer.iter().take(n as usize) {
+                match *sig as libc::c_int {
+                    libc::SIGTERM | libc::SIGINT => signals.push(Signal::Shutdown),
+                    libc::SIGHUP => signals.push(Signal::Reload),
+                    libc::SIGUSR1 => signals.push(Signal::Reopen),
+                    libc::SIGUSR2 => signals.push(Signal::Upgrade),
+                    _ => {}
+                }
+            }
+        }
+
+        signals
+
+    }
+
+    // Block until a signal arrives
+    #[cfg(not(target_os = "linux"))]
+    pub fn wait(&self) -> Vec<Signal> {
+        loop {
+            wait_readable(self.reader, -1);
+            let signals = self.read();
+            if !signals.is_empty() {
+                return signals;
+            }
+        }
+    }
+
+}
+
+
+// No signals to wait for, serve until killed
+#[cfg(not(unix))]
+pub struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+
+    pub fn new() -> io::Result<Signals> {
+        Ok(Signals)
+    }
+
+    pub fn wait(&self) -> Vec<Signal> {
+        loop {
+            std::thread::park();
+        }
+    }
+
+}
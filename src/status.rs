@@ -0,0 +1,221 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::Instant;
+use crate::config::ServerConfig;
+use crate::html::{self, STATUS};
+use crate::log;
+use crate::metrics;
+
+// Errors are kept per minute for the last hour
+const MINUTES: usize = 60;
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    // 4xx responses
+    Client,
+    // 5xx responses
+    Server,
+    // Lines of the error log at the error level
+    Logged
+}
+
+// One listening port and the hosts it answers for
+pub struct Listen {
+    port: i64,
+    hosts: Vec<String>
+}
+
+struct Recent {
+    // Minutes since start of the newest bucket
+    minute: u64,
+    counts: [[u64; 3]; MINUTES]
+}
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static CONFIG: RwLock<Option<String>> = RwLock::new(None);
+static SERVERS: RwLock<Vec<Listen>> = RwLock::new(Vec::new());
+static RECENT: Mutex<Recent> = Mutex::new(Recent { minute: 0, counts: [[0; 3]; MINUTES] });
+
+
+pub fn init(config_file: Option<&str>) {
+    STARTED.get_or_init(Instant::now);
+    *CONFIG.write().unwrap_or_else(|err| err.into_inner()) = config_file.map(String::from);
+}
+
+// What the listeners serve, taken again after a reload
+pub fn servers(configs: &[Arc<Vec<ServerConfig>>]) -> Vec<Listen> {
+    configs.iter()
+        .map(|group| Listen {
+            port: group[0].listen,
+            hosts: group.iter()
+                .flat_map(|config| config.hosts.clone().unwrap_or_else(|| vec![String::from("*")]))
+                .collect()
+        })
+        .collect()
+}
+
+pub fn serving(servers: Vec<Listen>) {
+    *SERVERS.write().unwrap_or_else(|err| err.into_inner()) = servers;
+}
+
+pub fn error(kind: Kind) {
+    let mut recent = RECENT.lock().unwrap_or_else(|err| err.into_inner());
+    let i = recent.advance(minute());
+    recent.counts[i][kind as usize] += 1;
+}
+
+fn minute() -> u64 {
+    STARTED.get_or_init(Instant::now).elapsed().as_secs() / 60
+}
+
+impl Recent {
+
+    // Clear the buckets of the minutes gone by, returns the current one
+    fn advance(&mut self, now: u64) -> usize {
+        for minute in (self.minute + 1..=now).take(MINUTES) {
+            self.counts[minute as usize % MINUTES] = [0; 3];
+        }
+        self.minute = self.minute.max(now);
+        now as usize % MINUTES
+    }
+
+    fn last(&mut self, now: u64, minutes: usize) -> [u64; 3] {
+        self.advance(now);
+        let mut sum = [0; 3];
+        for i in 0..minutes.min(MINUTES) as u64 {
+            if let Some(minute) = now.checked_sub(i) {
+                let counts = self.counts[minute as usize % MINUTES];
+                for (total, n) in sum.iter_mut().zip(counts.iter()) {
+                    *total += n;
+                }
+            }
+        }
+        sum
+    }
+
+}
+
+
+struct Snapshot {
+    uptime: u64,
+    config: Option<String>,
+    connections: i64,
+    // Last 5 minutes and last hour
+    errors: [(&'static str, [u64; 3]); 2]
+}
+
+fn snapshot() -> Snapshot {
+    let now = minute();
+    let mut recent = RECENT.lock().unwrap_or_else(|err| err.into_inner());
+    Snapshot {
+        uptime: STARTED.get_or_init(Instant::now).elapsed().as_secs(),
+        config: CONFIG.read().unwrap_or_else(|err| err.into_inner()).clone(),
+        connections: metrics::active(),
+        errors: [("5m", recent.last(now, 5)), ("1h", recent.last(now, MINUTES))]
+    }
+}
+
+pub fn json() -> String {
+
+    let status = snapshot();
+    let mut out = String::new();
+
+    let _ = write!(out, "{{\"version\":\"{}\",\"uptime\":{},\"config\":", env!("CARGO_PKG_VERSION"), status.uptime);
+    match &status.config {
+        Some(path) => log::escape(&mut out, path),
+        None => out.push_str("null")
+    }
+
+    out.push_str(",\"servers\":[");
+    for (i, listen) in SERVERS.read().unwrap_or_else(|err| err.into_inner()).iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{{\"listen\":{},\"hosts\":[", listen.port);
+        for (i, host) in listen.hosts.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            log::escape(&mut out, host);
+        }
+        out.push_str("]}");
+    }
+
+    let _ = write!(out, "],\"connections\":{},\"errors\":{{", status.connections);
+    for (i, (period, [client, server, logged])) in status.errors.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "\"{}\":{{\"4xx\":{},\"5xx\":{},\"logged\":{}}}", period, client, server, logged);
+    }
+    out.push_str("}}");
+
+    out
+
+}
+
+pub fn html() -> String {
+
+    let status = snapshot();
+
+    let mut servers = String::new();
+    for listen in SERVERS.read().unwrap_or_else(|err| err.into_inner()).iter() {
+        let _ = write!(servers, "<tr><td>{}</td><td>{}</td></tr>", listen.port, html::escape(&listen.hosts.join(", ")));
+    }
+
+    let mut errors = String::new();
+    for (period, [client, server, logged]) in status.errors.iter() {
+        let _ = write!(errors, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", period, client, server, logged);
+    }
+
+    STATUS
+        .replace("{version}", env!("CARGO_PKG_VERSION"))
+        .replace("{uptime}", &uptime(status.uptime))
+        .replace("{config}", &html::escape(status.config.as_deref().unwrap_or("-")))
+        .replace("{connections}", &status.connections.to_string())
+        .replace("{servers}", &servers)
+        .replace("{errors}", &errors)
+
+}
+
+// 1d 2h 3m 4s
+fn uptime(secs: u64) -> String {
+    let (d, h, m, s) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+    if d > 0 {
+        format!("{}d {}h {}m {}s", d, h, m, s)
+    }else if h > 0 {
+        format!("{}h {}m {}s", h, m, s)
+    }else if m > 0 {
+        format!("{}m {}s", m, s)
+    }else {
+        format!("{}s", s)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::status::{uptime, Recent, MINUTES};
+
+    #[test]
+    fn test_recent() {
+        let mut recent = Recent { minute: 0, counts: [[0; 3]; MINUTES] };
+        let i = recent.advance(0);
+        recent.counts[i][0] += 2;
+        let i = recent.advance(3);
+        recent.counts[i][1] += 1;
+        assert_eq!(recent.last(3, 1), [0, 1, 0]);
+        assert_eq!(recent.last(3, 5), [2, 1, 0]);
+        // Two hours later everything is gone
+        assert_eq!(recent.last(123, 60), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_uptime() {
+        assert_eq!(uptime(5), "5s");
+        assert_eq!(uptime(3725), "1h 2m 5s");
+        assert_eq!(uptime(90061), "1d 1h 1m 1s");
+    }
+
+}
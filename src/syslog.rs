@@ -0,0 +1,138 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use chrono::Local;
+
+// Local socket of syslogd and journald
+#[cfg(unix)]
+const SOCKET_PATH: &str = "/dev/log";
+// Facility of a system daemon
+const FACILITY: u8 = 3;
+const APP_NAME: &str = env!("CARGO_PKG_NAME");
+
+pub const ERR: u8 = 3;
+pub const WARNING: u8 = 4;
+pub const INFO: u8 = 6;
+pub const DEBUG: u8 = 7;
+
+enum Socket {
+    #[cfg(unix)]
+    Local(UnixDatagram),
+    Udp(UdpSocket)
+}
+
+// Sends RFC 5424 messages, one datagram per line
+pub struct Syslog {
+    socket: Socket,
+    hostname: String
+}
+
+impl Syslog {
+
+    // The local socket when `addr` is `None`
+    pub fn connect(addr: Option<SocketAddr>) -> io::Result<Syslog> {
+
+        let socket = match addr {
+            Some(addr) => Socket::Udp(udp(addr)?),
+            None => local()?
+        };
+
+        Ok(Syslog {
+            socket,
+            hostname: hostname()
+        })
+
+    }
+
+    pub fn send(&self, severity: u8, msg_id: &str, message: &str) -> io::Result<()> {
+
+        let packet = format!(
+            "<{}>1 {} {} {} {} {} - {}",
+            FACILITY * 8 + severity,
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.6f%:z"),
+            self.hostname,
+            APP_NAME,
+            process::id(),
+            msg_id,
+            message
+        );
+
+        match &self.socket {
+            #[cfg(unix)]
+            Socket::Local(socket) => socket.send(packet.as_bytes())?,
+            Socket::Udp(socket) => socket.send(packet.as_bytes())?
+        };
+        Ok(())
+
+    }
+
+}
+
+fn udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let bind: SocketAddr = if addr.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    }else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect(addr)?;
+    Ok(socket)
+}
+
+// Without a local daemon, try one listening on localhost
+#[cfg(unix)]
+fn local() -> io::Result<Socket> {
+    let socket = UnixDatagram::unbound()?;
+    match socket.connect(SOCKET_PATH) {
+        Ok(()) => Ok(Socket::Local(socket)),
+        Err(_) => Ok(Socket::Udp(udp(([127, 0, 0, 1], 514).into())?))
+    }
+}
+
+#[cfg(not(unix))]
+fn local() -> io::Result<Socket> {
+    Ok(Socket::Udp(udp(([127, 0, 0, 1], 514).into())?))
+}
+
+// "-" is the nil value of the header field
+fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        let ok = unsafe {
+            libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) == 0
+        };
+        let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+        if ok && end > 0 {
+            return String::from_utf8_lossy(&buffer[..end]).to_string();
+        }
+    }
+    String::from("-")
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::UdpSocket;
+    use std::time::Duration;
+    use crate::syslog::{Syslog, INFO};
+
+    #[test]
+    fn test_send_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let syslog = Syslog::connect(Some(server.local_addr().unwrap())).unwrap();
+        syslog.send(INFO, "access", "GET / 200").unwrap();
+
+        let mut buffer = [0; 1024];
+        let n = server.recv(&mut buffer).unwrap();
+        let packet = String::from_utf8_lossy(&buffer[..n]).to_string();
+        // daemon.info
+        assert!(packet.starts_with("<30>1 "));
+        assert!(packet.ends_with(&format!(" see {} access - GET / 200", std::process::id())));
+    }
+
+}
@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use chrono::{DateTime, Local};
+use chrono::format::{Item, StrftimeItems};
+use percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use crate::html;
+use crate::listing::bytes_to_size;
+
+// A small template language for directory listings:
+//   {{ entry.name }}                 escaped for HTML
+//   {{ entry.size | size }}          1.20 KB
+//   {{ entry.mtime | time }}         2019-05-01 12:30, or time:"%d %b %Y"
+//   {{ entry.name | url }}           percent-encoded path segment
+//   {{ readme | raw }}               as it is
+//   {% for entry in entries %} ... {% endfor %}
+//   {% if not root %} ... {% else %} ... {% endif %}
+
+pub enum Value {
+    Str(String),
+    Num(u64),
+    Bool(bool),
+    Time(Option<SystemTime>),
+    List(Vec<Value>),
+    Object(BTreeMap<&'static str, Value>)
+}
+
+impl Value {
+
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Str(s) => !s.is_empty(),
+            Value::Num(n) => *n != 0,
+            Value::Bool(b) => *b,
+            Value::Time(t) => t.is_some(),
+            Value::List(list) => !list.is_empty(),
+            Value::Object(_) => true
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Value::Str(s) => s.clone(),
+            Value::Num(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Time(t) => time(*t, DEFAULT_TIME),
+            Value::List(_) | Value::Object(_) => String::new()
+        }
+    }
+
+}
+
+const DEFAULT_TIME: &str = "%Y-%m-%d %H:%M";
+
+fn time(time: Option<SystemTime>, format: &str) -> String {
+    match time {
+        Some(time) => {
+            let datetime: DateTime<Local> = DateTime::from(time);
+            datetime.format(format).to_string()
+        },
+        None => String::new()
+    }
+}
+
+#[derive(Debug)]
+enum Filter {
+    Size,
+    Time(String),
+    Url,
+    Raw
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var(Vec<String>, Vec<Filter>),
+    For(String, Vec<String>, Vec<Node>),
+    // Negated, condition, then, else
+    If(bool, Vec<String>, Vec<Node>, Vec<Node>)
+}
+
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>
+}
+
+// Where the parser is while reading the nodes of a block
+enum Block {
+    Root,
+    For(String, Vec<String>),
+    If(bool, Vec<String>),
+    Else(bool, Vec<String>, Vec<Node>)
+}
+
+impl Template {
+
+    pub fn new(source: &str) -> Result<Template, String> {
+
+        let mut stack: Vec<(Block, Vec<Node>)> = vec![(Block::Root, vec![])];
+        let mut rest = source;
+
+        while !rest.is_empty() {
+
+            let start = match (rest.find("{{"), rest.find("{%")) {
+                (Some(a), Some(b)) => a.min(b),
+                (Some(a), None) | (None, Some(a)) => a,
+                (None, None) => rest.len()
+            };
+            if start > 0 {
+                current(&mut stack).push(Node::Text(rest[..start].to_string()));
+                rest = &rest[start..];
+                continue;
+            }
+
+            let close = if rest.starts_with("{{") { "}}" } else { "%}" };
+            let end = match rest.find(close) {
+                Some(end) => end,
+                None => return Err(format!("Unclosed '{}' in the template", &rest[..2]))
+            };
+            let tag = rest[2..end].trim();
+            let var = rest.starts_with("{{");
+            rest = &rest[end + 2..];
+
+            if var {
+                let mut parts = tag.split('|');
+                let path = path(parts.next().unwrap_or(""))?;
+                let filters = parts.map(filter).collect::<Result<Vec<Filter>, String>>()?;
+                current(&mut stack).push(Node::Var(path, filters));
+                continue;
+            }
+
+            let words: Vec<&str> = tag.split_whitespace().collect();
+            match words.as_slice() {
+                ["for", name, "in", list] => {
+                    stack.push((Block::For(name.to_string(), path(list)?), vec![]));
+                },
+                ["if", "not", cond] => stack.push((Block::If(true, path(cond)?), vec![])),
+                ["if", cond] => stack.push((Block::If(false, path(cond)?), vec![])),
+                ["else"] => match stack.pop() {
+                    Some((Block::If(not, cond), then)) => stack.push((Block::Else(not, cond, then), vec![])),
+                    _ => return Err(String::from("'else' outside of 'if' in the template"))
+                },
+                ["endfor"] => match stack.pop() {
+                    Some((Block::For(name, list), body)) => current(&mut stack).push(Node::For(name, list, body)),
+                    _ => return Err(String::from("'endfor' without 'for' in the template"))
+                },
+                ["endif"] => {
+                    let node = match stack.pop() {
+                        Some((Block::If(not, cond), then)) => Node::If(not, cond, then, vec![]),
+                        Some((Block::Else(not, cond, then), otherwise)) => Node::If(not, cond, then, otherwise),
+                        _ => return Err(String::from("'endif' without 'if' in the template"))
+                    };
+                    current(&mut stack).push(node);
+                },
+                _ => return Err(format!("Unknown tag '{{% {} %}}' in the template", tag))
+            }
+
+        }
+
+        match stack.pop() {
+            Some((Block::Root, nodes)) if stack.is_empty() => Ok(Template { nodes }),
+            _ => Err(String::from("Unclosed 'for' or 'if' in the template"))
+        }
+
+    }
+
+    pub fn render(&self, context: &Value) -> String {
+        let mut out = String::new();
+        render(&self.nodes, &mut vec![("", context)], &mut out);
+        out
+    }
+
+}
+
+fn current(stack: &mut [(Block, Vec<Node>)]) -> &mut Vec<Node> {
+    // The root block is never popped while parsing
+    &mut stack.last_mut().unwrap().1
+}
+
+// entry.name -> ["entry", "name"]
+fn path(value: &str) -> Result<Vec<String>, String> {
+    let value = value.trim();
+    let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !value.split('.').all(valid) {
+        return Err(format!("Invalid variable '{}' in the template", value));
+    }
+    Ok(value.split('.').map(String::from).collect())
+}
+
+fn filter(value: &str) -> Result<Filter, String> {
+    let value = value.trim();
+    match value {
+        "size" => Ok(Filter::Size),
+        "time" => Ok(Filter::Time(DEFAULT_TIME.to_string())),
+        "url" => Ok(Filter::Url),
+        "raw" => Ok(Filter::Raw),
+        _ => match value.strip_prefix("time:") {
+            Some(format) => {
+                let format = format.trim();
+                match format.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+                    // chrono panics on an unknown specifier while formatting
+                    Some(format) if StrftimeItems::new(format).any(|item| item == Item::Error) => {
+                        Err(format!("Invalid time format '{}' in the template", format))
+                    },
+                    Some(format) => Ok(Filter::Time(format.to_string())),
+                    None => Err(format!("Quote the format of '{}' in the template", value))
+                }
+            },
+            None => Err(format!("Unknown filter '{}' in the template", value))
+        }
+    }
+}
+
+// Loop variables shadow the context, innermost first
+fn lookup<'a>(scope: &[(&str, &'a Value)], path: &[String]) -> Option<&'a Value> {
+
+    let (first, rest) = path.split_first()?;
+    let mut value = scope.iter().rev()
+        .find(|(name, _)| name == first)
+        .map(|(_, value)| *value)
+        .or_else(|| match scope.first() {
+            Some((_, Value::Object(map))) => map.get(first.as_str()),
+            _ => None
+        })?;
+
+    for key in rest {
+        value = match value {
+            Value::Object(map) => map.get(key.as_str())?,
+            _ => return None
+        };
+    }
+    Some(value)
+
+}
+
+fn render<'a>(nodes: &'a [Node], scope: &mut Vec<(&'a str, &'a Value)>, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(path, filters) => {
+                let value = match lookup(scope, path) {
+                    Some(value) => value,
+                    None => continue
+                };
+                let mut text = value.text();
+                let mut escape = true;
+                for filter in filters {
+                    text = match (filter, value) {
+                        (Filter::Size, Value::Num(n)) => bytes_to_size(*n as f64),
+                        (Filter::Time(format), Value::Time(t)) => time(*t, format),
+                        (Filter::Url, _) => utf8_percent_encode(&text, PATH_SEGMENT_ENCODE_SET).to_string(),
+                        (Filter::Raw, _) => {
+                            escape = false;
+                            text
+                        },
+                        _ => text
+                    };
+                }
+                if escape {
+                    out.push_str(&html::escape(&text));
+                }else {
+                    out.push_str(&text);
+                }
+            },
+            Node::For(name, list, body) => {
+                if let Some(Value::List(items)) = lookup(scope, list) {
+                    for item in items {
+                        scope.push((name, item));
+                        render(body, scope, out);
+                        scope.pop();
+                    }
+                }
+            },
+            Node::If(not, cond, then, otherwise) => {
+                let truthy = lookup(scope, cond).is_some_and(Value::truthy);
+                if truthy != *not {
+                    render(then, scope, out);
+                }else {
+                    render(otherwise, scope, out);
+                }
+            }
+        }
+    }
+}
+
+// Build an object in one expression
+pub fn object(fields: Vec<(&'static str, Value)>) -> Value {
+    Value::Object(fields.into_iter().collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::{Duration, SystemTime};
+    use crate::template::{object, Template, Value};
+
+    fn context() -> Value {
+        object(vec![
+            ("title", Value::Str(String::from("/<docs>/"))),
+            ("root", Value::Bool(false)),
+            ("entries", Value::List(vec![
+                object(vec![
+                    ("name", Value::Str(String::from("a b.txt"))),
+                    ("size", Value::Num(2048)),
+                    ("dir", Value::Bool(false)),
+                    ("mtime", Value::Time(Some(SystemTime::UNIX_EPOCH + Duration::from_secs(86400 * 365))))
+                ]),
+                object(vec![
+                    ("name", Value::Str(String::from("sub"))),
+                    ("size", Value::Num(0)),
+                    ("dir", Value::Bool(true)),
+                    ("mtime", Value::Time(None))
+                ])
+            ]))
+        ])
+    }
+
+    #[test]
+    fn test_render() {
+        let template = Template::new(concat!(
+            "<h1>{{ title }}</h1>{{ title | raw }}",
+            "{% if not root %}<a href=\"../\">..</a>{% endif %}",
+            "{% for entry in entries %}",
+            "<a href=\"{{ entry.name | url }}\">{{ entry.name }}</a>",
+            "{% if entry.dir %}/{% else %} {{ entry.size | size }} {{ entry.mtime | time:\"%Y\" }}{% endif %};",
+            "{% endfor %}{{ missing }}"
+        )).unwrap();
+        assert_eq!(
+            template.render(&context()),
+            concat!(
+                "<h1>/&lt;docs&gt;/</h1>/<docs>/<a href=\"../\">..</a>",
+                "<a href=\"a%20b.txt\">a b.txt</a> 2.00 KB 1971;",
+                "<a href=\"sub\">sub</a>/;"
+            )
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Template::new("{% for entry in entries %}").is_err());
+        assert!(Template::new("{% endif %}").is_err());
+        assert!(Template::new("{{ title").is_err());
+        assert!(Template::new("{{ title | upper }}").is_err());
+        assert!(Template::new("{{ entry..name }}").is_err());
+        assert!(Template::new("{% while x %}").is_err());
+        assert!(Template::new("{{ t | time:%Y }}").is_err());
+        assert!(Template::new("{{ t | time:\"%Q\" }}").is_err());
+        assert!(Template::new("{{ t | time:\"%Y %\" }}").is_err());
+    }
+
+}
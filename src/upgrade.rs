@@ -0,0 +1,140 @@
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::process::{self, Command};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use crate::signal::{pipe, wait_readable};
+use crate::PID_PATH;
+
+// Listening sockets handed over by the previous process, "port:fd,port:fd"
+const LISTENERS_ENV: &str = "SEE_LISTENERS";
+// Pipe used to tell the previous process that we are serving
+const READY_ENV: &str = "SEE_READY";
+// How long the previous process waits for us, in milliseconds
+const READY_TIMEOUT: i32 = 10_000;
+
+
+// Take over the listeners of the process that started us
+pub fn inherited() -> HashMap<i64, TcpListener> {
+
+    let mut listeners = HashMap::new();
+
+    let value = match env::var(LISTENERS_ENV) {
+        Ok(value) => value,
+        Err(_) => return listeners
+    };
+    env::remove_var(LISTENERS_ENV);
+
+    for item in value.split(',') {
+        let mut pair = item.split(':');
+        if let (Some(port), Some(fd)) = (pair.next(), pair.next()) {
+            if let (Ok(port), Ok(fd)) = (port.parse::<i64>(), fd.parse::<RawFd>()) {
+                unsafe {
+                    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                    listeners.insert(port, TcpListener::from_raw_fd(fd));
+                }
+            }
+        }
+    }
+
+    listeners
+
+}
+
+// Every listener is bound, the previous process can stop accepting
+pub fn ready() {
+
+    let fd = match env::var(READY_ENV) {
+        Ok(value) => value.parse::<RawFd>(),
+        Err(_) => return
+    };
+    env::remove_var(READY_ENV);
+
+    if let Ok(fd) = fd {
+        unsafe {
+            libc::write(fd, b"1".as_ptr() as *const libc::c_void, 1);
+            libc::close(fd);
+        }
+    }
+
+}
+
+// Start the (possibly replaced) binary with the same arguments and our listeners,
+// returns once it is serving
+pub fn spawn(listeners: &[&TcpListener]) -> io::Result<u32> {
+
+    let args: Vec<String> = env::args().collect();
+
+    let mut fds = vec![];
+    let mut value = vec![];
+    for listener in listeners {
+        let port = listener.local_addr()?.port();
+        fds.push(listener.as_raw_fd());
+        value.push(format!("{}:{}", port, listener.as_raw_fd()));
+    }
+
+    let (reader, writer) = pipe()?;
+    fds.push(writer);
+
+    let mut command = Command::new(&args[0]);
+    command
+        .args(&args[1..])
+        .env(LISTENERS_ENV, value.join(","))
+        .env(READY_ENV, writer.to_string());
+
+    // Only the new process gets to keep these descriptors across exec
+    unsafe {
+        command.pre_exec(move || {
+            for fd in fds.iter() {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+
+    let child = command.spawn();
+    unsafe {
+        libc::close(writer);
+    }
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            unsafe {
+                libc::close(reader);
+            }
+            return Err(err);
+        }
+    };
+
+    // Reads 0 bytes if the new process exits before it is ready
+    let mut byte = 0u8;
+    let ready = wait_readable(reader, READY_TIMEOUT) && unsafe {
+        libc::read(reader, &mut byte as *mut u8 as *mut libc::c_void, 1) == 1
+    };
+    unsafe {
+        libc::close(reader);
+    }
+
+    if !ready {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(io::Error::other("the new process failed to start"));
+    }
+
+    // Hand over the PID file when running as a daemon
+    if let Ok(pid) = fs::read_to_string(PID_PATH) {
+        if pid.trim() == process::id().to_string() {
+            let _ = fs::write(PID_PATH, child.id().to_string());
+        }
+    }
+
+    Ok(child.id())
+
+}
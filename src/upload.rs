@@ -0,0 +1,234 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Files written by PUT and POST requests
+
+#[derive(Debug)]
+pub enum Error {
+    // Exists and may not be replaced, or a directory is in the way
+    Conflict,
+    Io(io::Error)
+}
+
+// Where a request path lands below `root`, None when it would leave it
+pub fn confine(root: &str, path: &str) -> Option<PathBuf> {
+    let mut target = PathBuf::from(root);
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(name) => target.push(name),
+            Component::CurDir => {},
+            _ => return None
+        }
+    }
+    // A link may still lead out, what exists of the path has to resolve below the root
+    let root = fs::canonicalize(root).ok()?;
+    let existing = target.ancestors().find(|path| path.symlink_metadata().is_ok())?;
+    match fs::canonicalize(existing) {
+        Ok(resolved) if resolved.starts_with(&root) => Some(target),
+        _ => None
+    }
+}
+
+// Temporary files of uploads in progress
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Written next to the target and moved in place, readers never see half a file.
+// Returns whether the file is new
+pub fn save(target: &Path, data: &[u8], overwrite: bool) -> Result<bool, Error> {
+
+    if target.is_dir() {
+        return Err(Error::Conflict);
+    }
+    let existed = target.exists();
+    if existed && !overwrite {
+        return Err(Error::Conflict);
+    }
+
+    let (dir, name) = match (target.parent(), target.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_string_lossy()),
+        _ => return Err(Error::Conflict)
+    };
+    if let Err(err) = fs::create_dir_all(dir) {
+        return Err(match err.kind() {
+            // A file where a directory should be
+            ErrorKind::AlreadyExists | ErrorKind::NotADirectory => Error::Conflict,
+            _ => Error::Io(err)
+        });
+    }
+
+    let temp = dir.join(format!(".{}.{}-{}.upload", name, process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let result = File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| {
+            if overwrite {
+                fs::rename(&temp, target)
+            }else {
+                // Fails if another upload got there first
+                fs::hard_link(&temp, target)
+            }
+        });
+    if !overwrite || result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    match result {
+        Ok(()) => Ok(!existed),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Err(Error::Conflict),
+        Err(err) => Err(Error::Io(err))
+    }
+
+}
+
+// A file of a multipart/form-data body
+#[derive(Debug, PartialEq)]
+pub struct Part<'a> {
+    pub filename: String,
+    pub data: &'a [u8]
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// The files of the body, other fields are skipped. None when it can't be read
+pub fn multipart<'a>(content_type: &str, body: &'a [u8]) -> Option<Vec<Part<'a>>> {
+
+    let mut params = content_type.split(';');
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    let boundary = params
+        .find_map(|param| param.trim().strip_prefix("boundary="))?
+        .trim_matches('"');
+    if boundary.is_empty() {
+        return None;
+    }
+
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let separator = [b"\r\n", &delimiter[..]].concat();
+    let mut parts = vec![];
+    let mut rest = &body[find(body, &delimiter)? + delimiter.len()..];
+
+    loop {
+        // The last delimiter ends with "--"
+        if rest.starts_with(b"--") {
+            return Some(parts);
+        }
+        rest = rest.strip_prefix(b"\r\n")?;
+        let end = find(rest, b"\r\n\r\n")?;
+        let head = String::from_utf8_lossy(&rest[..end]);
+        let content = &rest[end + 4..];
+        let close = find(content, &separator)?;
+        if let Some(filename) = filename(&head) {
+            parts.push(Part { filename, data: &content[..close] });
+        }
+        rest = &content[close + separator.len()..];
+    }
+
+}
+
+// Content-Disposition: form-data; name="file"; filename="a.txt"
+fn filename(head: &str) -> Option<String> {
+    let disposition = head.lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if key.trim().eq_ignore_ascii_case("content-disposition") { Some(value) } else { None }
+        })?;
+    disposition.split(';')
+        .find_map(|param| param.trim().strip_prefix("filename="))
+        .map(|name| name.trim_matches('"').to_string())
+        .filter(|name| !name.is_empty())
+}
+
+// The file name without any directory some clients send along
+pub fn basename(filename: &str) -> Option<&str> {
+    let name = filename.rsplit(['/', '\\']).next()?;
+    if name.is_empty() || name == "." || name == ".." || name.chars().any(char::is_control) {
+        return None;
+    }
+    Some(name)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::upload::{self, Error, Part};
+
+    #[test]
+    fn test_confine() {
+        let dir = env::temp_dir().join(format!("see-confine-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("www/a")).unwrap();
+        fs::create_dir(dir.join("outside")).unwrap();
+        let root = dir.join("www");
+        let root = root.to_str().unwrap();
+
+        assert_eq!(upload::confine(root, "/a/./b.txt"), Some(dir.join("www/a/b.txt")));
+        assert_eq!(upload::confine(root, "/"), Some(PathBuf::from(root)));
+        assert_eq!(upload::confine(root, "/a/../../etc/passwd"), None);
+        assert_eq!(upload::confine(root, "/new/dir/c.txt"), Some(dir.join("www/new/dir/c.txt")));
+
+        // Links out of the root are not followed
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("www/out")).unwrap();
+        std::os::unix::fs::symlink(dir.join("www/a"), dir.join("www/in")).unwrap();
+        assert_eq!(upload::confine(root, "/out/c.txt"), None);
+        assert_eq!(upload::confine(root, "/out"), None);
+        assert_eq!(upload::confine(root, "/in/c.txt"), Some(dir.join("www/in/c.txt")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = env::temp_dir().join(format!("see-upload-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let target = dir.join("a/b.txt");
+
+        assert!(upload::save(&target, b"one", false).unwrap());
+        assert!(matches!(upload::save(&target, b"two", false), Err(Error::Conflict)));
+        assert!(!upload::save(&target, b"two", true).unwrap());
+        assert_eq!(fs::read(&target).unwrap(), b"two");
+        // b.txt is not a directory
+        assert!(matches!(upload::save(&target.join("c"), b"", true), Err(Error::Conflict)));
+        assert!(matches!(upload::save(&dir.join("a"), b"", true), Err(Error::Conflict)));
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(dir.join("a")).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_multipart() {
+        let body = concat!(
+            "preamble\r\n--xyz\r\n",
+            "Content-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n--xyz\r\n",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n",
+            "Content-Type: text/plain\r\n\r\nline\r\n--x\r\n--xyz\r\n",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"\"\r\n\r\n\r\n--xyz--\r\n"
+        );
+        assert_eq!(
+            upload::multipart("multipart/form-data; boundary=xyz", body.as_bytes()),
+            Some(vec![Part { filename: String::from("a.txt"), data: b"line\r\n--x" }])
+        );
+        assert_eq!(upload::multipart("multipart/form-data; boundary=\"xyz\"", b"--xyz--"), Some(vec![]));
+        assert_eq!(upload::multipart("multipart/form-data; boundary=xyz", b"--xyz\r\nbroken"), None);
+        assert_eq!(upload::multipart("text/plain", body.as_bytes()), None);
+    }
+
+    #[test]
+    fn test_basename() {
+        assert_eq!(upload::basename("C:\\Users\\me\\a.txt"), Some("a.txt"));
+        assert_eq!(upload::basename("../a.txt"), Some("a.txt"));
+        assert_eq!(upload::basename(".."), None);
+        assert_eq!(upload::basename("dir/"), None);
+    }
+
+}
@@ -0,0 +1,788 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Utc};
+use percent_encoding::{define_encode_set, utf8_percent_encode, DEFAULT_ENCODE_SET};
+use crate::cidr;
+use crate::config::ServerConfig;
+use crate::manage;
+use crate::response;
+use crate::upload;
+use crate::xml::{self, escape, Element};
+
+// Properties, locks and copies of WebDAV, the requests are answered in main
+
+pub const DAV: &str = "DAV:";
+
+// Turned on along with `webdav`
+pub const METHODS: [&str; 10] = ["OPTIONS", "PROPFIND", "PROPPATCH", "MKCOL", "COPY", "MOVE", "LOCK", "UNLOCK", "PUT", "DELETE"];
+
+// Computed from the file, PROPPATCH can't change them
+const LIVE: [&str; 9] = [
+    "creationdate", "displayname", "getcontentlength", "getcontenttype", "getetag",
+    "getlastmodified", "lockdiscovery", "resourcetype", "supportedlock"
+];
+
+// Responses of one PROPFIND
+const MAX_RESOURCES: usize = 10_000;
+
+const HEAD: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+
+define_encode_set! {
+    pub HREF_ENCODE_SET = [DEFAULT_ENCODE_SET] | {'%'}
+}
+
+#[derive(Debug)]
+pub enum Error {
+    // Depth infinity over more than MAX_RESOURCES
+    TooMany,
+    Io(io::Error)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Depth {
+    Zero,
+    One,
+    Infinity
+}
+
+impl Depth {
+
+    // The Depth header, None when it isn't one of them
+    pub fn new(value: Option<&String>, default: Depth) -> Option<Depth> {
+        match value.map(|value| value.trim()) {
+            None => Some(default),
+            Some("0") => Some(Depth::Zero),
+            Some("1") => Some(Depth::One),
+            Some(value) if value.eq_ignore_ascii_case("infinity") => Some(Depth::Infinity),
+            _ => None
+        }
+    }
+
+}
+
+pub struct Resource {
+    pub path: PathBuf,
+    // Request path, collections end with '/'
+    pub href: String,
+    pub meta: fs::Metadata
+}
+
+// The resource and its members down to `depth`, without sidecars and what `access` denies
+pub fn walk(path: &Path, url: &str, depth: Depth, config: &ServerConfig, ip: Option<IpAddr>) -> Result<Vec<Resource>, Error> {
+
+    let meta = fs::metadata(path).map_err(Error::Io)?;
+    let href = if meta.is_dir() && !url.ends_with('/') { format!("{}/", url) } else { url.to_string() };
+    let mut pending = vec![];
+    if meta.is_dir() && depth != Depth::Zero {
+        pending.push((path.to_path_buf(), href.clone()));
+    }
+    let mut resources = vec![Resource { path: path.to_path_buf(), href, meta }];
+
+    while let Some((dir, url)) = pending.pop() {
+
+        let mut names = vec![];
+        for entry in fs::read_dir(&dir).map_err(Error::Io)? {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue
+            };
+            match entry.file_name().to_str() {
+                Some(name) if !is_sidecar(name) => names.push(name.to_string()),
+                _ => continue
+            }
+        }
+        names.sort();
+
+        for name in names {
+            let href = format!("{}{}", url, name);
+            if let Some(ip) = ip {
+                if !cidr::allowed(config.access(&href), &ip) {
+                    continue;
+                }
+            }
+            let path = dir.join(&name);
+            let meta = match fs::metadata(&path) {
+                Ok(meta) => meta,
+                Err(_) => continue
+            };
+            if meta.is_dir() {
+                let href = format!("{}/", href);
+                // Links to directories could loop
+                let link = fs::symlink_metadata(&path).map_or(true, |meta| meta.file_type().is_symlink());
+                if depth == Depth::Infinity && !link {
+                    pending.push((path.clone(), href.clone()));
+                }
+                resources.push(Resource { path, href, meta });
+            }else {
+                resources.push(Resource { path, href, meta });
+            }
+            if resources.len() > MAX_RESOURCES {
+                return Err(Error::TooMany);
+            }
+        }
+
+    }
+
+    Ok(resources)
+
+}
+
+// What a PROPFIND asks for
+#[derive(Debug, PartialEq)]
+pub enum Find {
+    All,
+    Names,
+    // Namespace and name
+    Props(Vec<(String, String)>)
+}
+
+// None for a body that isn't a propfind
+pub fn find(body: &[u8]) -> Option<Find> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Some(Find::All);
+    }
+    let root = xml::parse(std::str::from_utf8(body).ok()?).ok()?;
+    if !root.is(DAV, "propfind") {
+        return None;
+    }
+    if let Some(prop) = root.child(DAV, "prop") {
+        return Some(Find::Props(prop.children.iter().map(|child| (child.ns.clone(), child.name.clone())).collect()));
+    }
+    if root.child(DAV, "propname").is_some() {
+        return Some(Find::Names);
+    }
+    root.child(DAV, "allprop").map(|_| Find::All)
+}
+
+fn utc(time: SystemTime) -> DateTime<Utc> {
+    DateTime::from(time)
+}
+
+// Value of a live property, None when the resource has none
+fn live(name: &str, resource: &Resource) -> Option<String> {
+    let meta = &resource.meta;
+    match name {
+        "creationdate" => meta.created().or_else(|_| meta.modified()).ok()
+            .map(|time| utc(time).format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+        "displayname" => resource.href.trim_end_matches('/').rsplit('/').next().map(escape),
+        "getcontentlength" if meta.is_file() => Some(meta.len().to_string()),
+        "getcontenttype" if meta.is_file() => {
+            let ext = resource.path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+            Some(response::mime(ext).to_string())
+        },
+        "getetag" if meta.is_file() => {
+            let modified = meta.modified().ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_nanos());
+            Some(format!("\"{:x}-{:x}\"", meta.len(), modified))
+        },
+        "getlastmodified" => meta.modified().ok()
+            .map(|time| utc(time).format("%a, %d %b %Y %H:%M:%S GMT").to_string()),
+        "resourcetype" => Some(if meta.is_dir() { String::from("<D:collection/>") } else { String::new() }),
+        "supportedlock" => Some(String::from(concat!(
+            "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>",
+            "<D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>"
+        ))),
+        "lockdiscovery" => Some(locks().iter().filter(|lock| lock.covers(&resource.path)).map(activelock).collect()),
+        _ => None
+    }
+}
+
+fn dav(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        format!("<D:{}/>", name)
+    }else {
+        format!("<D:{}>{}</D:{}>", name, value, name)
+    }
+}
+
+// A property without its value, as for propname
+fn empty(ns: &str, name: &str) -> String {
+    match ns {
+        DAV => format!("<D:{}/>", name),
+        "" => format!("<{} xmlns=\"\"/>", name),
+        ns => format!("<ns0:{} xmlns:ns0=\"{}\"/>", name, escape(ns))
+    }
+}
+
+fn propstat(out: &mut String, props: &str, status: &str) {
+    if !props.is_empty() {
+        let _ = write!(out, "<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>", props, status);
+    }
+}
+
+fn href(path: &str) -> String {
+    escape(&utf8_percent_encode(path, HREF_ENCODE_SET).to_string())
+}
+
+// 207 body of a PROPFIND
+pub fn multistatus(resources: &[Resource], find: &Find) -> String {
+
+    let mut out = format!("{}<D:multistatus xmlns:D=\"DAV:\">\n", HEAD);
+
+    for resource in resources {
+        let dead = read_props(&resource.path, resource.meta.is_dir());
+        let mut found = String::new();
+        let mut missing = String::new();
+        match find {
+            Find::All => {
+                for name in LIVE {
+                    if let Some(value) = live(name, resource) {
+                        found.push_str(&dav(name, &value));
+                    }
+                }
+                for prop in &dead {
+                    found.push_str(&prop.xml);
+                }
+            },
+            Find::Names => {
+                for name in LIVE {
+                    if live(name, resource).is_some() {
+                        found.push_str(&empty(DAV, name));
+                    }
+                }
+                for prop in &dead {
+                    found.push_str(&empty(&prop.ns, &prop.name));
+                }
+            },
+            Find::Props(names) => {
+                for (ns, name) in names {
+                    let value = if ns == DAV { live(name, resource).map(|value| dav(name, &value)) } else { None };
+                    match value.or_else(|| dead.iter().find(|prop| prop.ns == *ns && prop.name == *name).map(|prop| prop.xml.clone())) {
+                        Some(value) => found.push_str(&value),
+                        None => missing.push_str(&empty(ns, name))
+                    }
+                }
+            }
+        }
+        let _ = write!(out, "<D:response><D:href>{}</D:href>", href(&resource.href));
+        propstat(&mut out, &found, "200 OK");
+        propstat(&mut out, &missing, "404 Not Found");
+        out.push_str("</D:response>\n");
+    }
+
+    out.push_str("</D:multistatus>\n");
+    out
+
+}
+
+// Dead properties are kept in a sidecar file, ".name.props" next to a file and ".props" in a directory
+const SIDECAR: &str = ".props";
+
+pub fn is_sidecar(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(SIDECAR)
+}
+
+fn sidecar(path: &Path, dir: bool) -> PathBuf {
+    if dir {
+        return path.join(SIDECAR);
+    }
+    let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().to_string());
+    path.with_file_name(format!(".{}{}", name, SIDECAR))
+}
+
+struct Prop {
+    ns: String,
+    name: String,
+    // The whole element, with the namespaces its value relies on
+    xml: String
+}
+
+// One property a line: namespace, name and element, separated by tabs
+fn field(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unfield(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+fn read_props(path: &Path, dir: bool) -> Vec<Prop> {
+    let text = match fs::read_to_string(sidecar(path, dir)) {
+        Ok(text) => text,
+        Err(_) => return vec![]
+    };
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t').map(unfield);
+            Some(Prop { ns: fields.next()?, name: fields.next()?, xml: fields.next()? })
+        })
+        .collect()
+}
+
+fn write_props(path: &Path, dir: bool, props: &[Prop]) -> io::Result<()> {
+    let sidecar = sidecar(path, dir);
+    if props.is_empty() {
+        return match fs::remove_file(&sidecar) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(())
+        };
+    }
+    let mut data = String::new();
+    for prop in props {
+        let _ = writeln!(data, "{}\t{}\t{}", field(&prop.ns), field(&prop.name), field(&prop.xml));
+    }
+    match upload::save(&sidecar, data.as_bytes(), true) {
+        Ok(_) => Ok(()),
+        Err(upload::Error::Io(err)) => Err(err),
+        Err(upload::Error::Conflict) => Err(io::Error::other("a directory is in the way"))
+    }
+}
+
+// An element that reads the same outside of the request it came in
+fn serialize(element: &Element) -> String {
+
+    let mut n = 0;
+    while element.scope.iter().any(|(prefix, _)| *prefix == format!("ns{}", n)) {
+        n += 1;
+    }
+    let (tag, mut attrs) = if element.ns.is_empty() {
+        (element.name.clone(), String::from(" xmlns=\"\""))
+    }else {
+        (format!("ns{}:{}", n, element.name), format!(" xmlns:ns{}=\"{}\"", n, escape(&element.ns)))
+    };
+    // The latest declaration of each prefix
+    for (i, (prefix, ns)) in element.scope.iter().enumerate() {
+        if element.scope[i + 1..].iter().any(|(other, _)| other == prefix) {
+            continue;
+        }
+        if !prefix.is_empty() {
+            let _ = write!(attrs, " xmlns:{}=\"{}\"", prefix, escape(ns));
+        }else if !element.ns.is_empty() {
+            let _ = write!(attrs, " xmlns=\"{}\"", escape(ns));
+        }
+    }
+
+    if element.inner.is_empty() {
+        format!("<{}{}/>", tag, attrs)
+    }else {
+        format!("<{}{}>{}</{}>", tag, attrs, element.inner, tag)
+    }
+
+}
+
+pub enum Update {
+    Set(Element),
+    Remove(Element)
+}
+
+impl Update {
+
+    fn element(&self) -> &Element {
+        match self {
+            Update::Set(element) | Update::Remove(element) => element
+        }
+    }
+
+}
+
+// None for a body that isn't a propertyupdate
+pub fn updates(body: &[u8]) -> Option<Vec<Update>> {
+    let root = xml::parse(std::str::from_utf8(body).ok()?).ok()?;
+    if !root.is(DAV, "propertyupdate") {
+        return None;
+    }
+    let mut updates = vec![];
+    for child in root.children {
+        let set = child.is(DAV, "set");
+        if !set && !child.is(DAV, "remove") {
+            continue;
+        }
+        for prop in child.children.into_iter().filter(|prop| prop.is(DAV, "prop")) {
+            for element in prop.children {
+                updates.push(if set { Update::Set(element) } else { Update::Remove(element) });
+            }
+        }
+    }
+    if updates.is_empty() { None } else { Some(updates) }
+}
+
+// All of them or none, the 207 body tells which
+pub fn proppatch(path: &Path, url: &str, updates: &[Update]) -> io::Result<String> {
+
+    let dir = path.is_dir();
+    let protected = |element: &Element| element.ns == DAV && LIVE.contains(&element.name.as_str());
+    let names = |elements: &[&Element]| -> String {
+        elements.iter().map(|element| empty(&element.ns, &element.name)).collect()
+    };
+
+    let mut out = format!("{}<D:multistatus xmlns:D=\"DAV:\">\n<D:response><D:href>{}</D:href>", HEAD, href(url));
+    let elements: Vec<&Element> = updates.iter().map(Update::element).collect();
+    if elements.iter().any(|element| protected(element)) {
+        let (forbidden, failed): (Vec<&Element>, Vec<&Element>) = elements.iter().partition(|element| protected(element));
+        propstat(&mut out, &names(&forbidden), "403 Forbidden");
+        propstat(&mut out, &names(&failed), "424 Failed Dependency");
+    }else {
+        let mut props = read_props(path, dir);
+        for update in updates {
+            let element = update.element();
+            props.retain(|prop| prop.ns != element.ns || prop.name != element.name);
+            if let Update::Set(element) = update {
+                props.push(Prop { ns: element.ns.clone(), name: element.name.clone(), xml: serialize(element) });
+            }
+        }
+        write_props(path, dir, &props)?;
+        propstat(&mut out, &names(&elements), "200 OK");
+    }
+    out.push_str("</D:response>\n</D:multistatus>\n");
+    Ok(out)
+
+}
+
+// Copies a file or a directory with its properties, `infinite` takes the members too.
+// Links to directories are left out
+pub fn copy(from: &Path, to: &Path, infinite: bool) -> io::Result<()> {
+
+    let meta = fs::metadata(from)?;
+    if !meta.is_dir() {
+        fs::copy(from, to)?;
+        return copy_props(&sidecar(from, false), &sidecar(to, false));
+    }
+
+    fs::create_dir(to)?;
+    copy_props(&sidecar(from, true), &sidecar(to, true))?;
+    if !infinite {
+        return Ok(());
+    }
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_str().is_some_and(is_sidecar) {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() && fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            continue;
+        }
+        copy(&path, &to.join(&name), true)?;
+    }
+    Ok(())
+
+}
+
+fn copy_props(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::copy(from, to) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(())
+    }
+}
+
+// After a move: the properties of a file follow it, a directory has them inside
+pub fn moved(from: &Path, to: &Path, dir: bool) {
+    let replaced = sidecar(to, false);
+    if dir || fs::rename(sidecar(from, false), &replaced).is_err() {
+        let _ = fs::remove_file(&replaced);
+    }
+}
+
+// Directories go with everything in them
+pub fn delete(path: &Path) -> Result<(), manage::Error> {
+    let dir = fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir());
+    manage::delete(path, true)?;
+    if !dir {
+        let _ = fs::remove_file(sidecar(path, false));
+    }
+    Ok(())
+}
+
+// Write locks, they are gone with a restart
+#[derive(Debug, Clone)]
+pub struct Lock {
+    pub token: String,
+    path: PathBuf,
+    href: String,
+    infinite: bool,
+    exclusive: bool,
+    // The owner element of the request, if any
+    owner: String,
+    expires: Instant
+}
+
+impl Lock {
+
+    fn covers(&self, path: &Path) -> bool {
+        self.path == path || (self.infinite && path.starts_with(&self.path))
+    }
+
+}
+
+static LOCKS: Mutex<Vec<Lock>> = Mutex::new(Vec::new());
+
+const TOKEN_SCHEME: &str = "opaquelocktoken:";
+
+pub const MAX_TIMEOUT: u64 = 3600;
+
+// Without the expired ones
+fn locks() -> MutexGuard<'static, Vec<Lock>> {
+    let mut locks = LOCKS.lock().unwrap_or_else(|err| err.into_inner());
+    let now = Instant::now();
+    locks.retain(|lock| lock.expires > now);
+    locks
+}
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn token() -> String {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+    let unique = (process::id() as u64) << 32 | (COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff_ffff);
+    format!(
+        "{}{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        TOKEN_SCHEME, time >> 32, (time >> 16) & 0xffff, time & 0xffff, unique >> 48, unique & 0xffff_ffff_ffff
+    )
+}
+
+// Seconds of a Timeout header, "Infinite" gets the longest there is
+pub fn timeout(value: Option<&String>) -> u64 {
+    value
+        .and_then(|value| {
+            value.split(',').find_map(|item| {
+                let item = item.trim();
+                if item.eq_ignore_ascii_case("infinite") {
+                    Some(MAX_TIMEOUT)
+                }else {
+                    item.strip_prefix("Second-")?.parse().ok()
+                }
+            })
+        })
+        .map_or(MAX_TIMEOUT, |seconds: u64| seconds.clamp(1, MAX_TIMEOUT))
+}
+
+// The lock tokens of an If header, its other conditions are not checked
+pub fn tokens(value: Option<&String>) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut rest = value.map_or("", |value| value.as_str());
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break
+        };
+        let token = &rest[start + 1..end];
+        if token.starts_with(TOKEN_SCHEME) {
+            tokens.push(token.to_string());
+        }
+        rest = &rest[end + 1..];
+    }
+    tokens
+}
+
+// Whether a lock whose token wasn't sent keeps `path` from changing.
+// `members` adds the locks of the parent collection, `tree` those of everything below
+pub fn locked(path: &Path, tokens: &[String], members: bool, tree: bool) -> bool {
+    locks().iter().any(|lock| {
+        !tokens.contains(&lock.token) && (
+            lock.covers(path)
+                || (members && path.parent().is_some_and(|parent| lock.covers(parent)))
+                || (tree && lock.path.starts_with(path))
+        )
+    })
+}
+
+pub struct LockInfo {
+    exclusive: bool,
+    owner: String
+}
+
+// None for a body that isn't a lockinfo of a write lock
+pub fn lockinfo(body: &[u8]) -> Option<LockInfo> {
+    let root = xml::parse(std::str::from_utf8(body).ok()?).ok()?;
+    if !root.is(DAV, "lockinfo") {
+        return None;
+    }
+    let scope = root.child(DAV, "lockscope")?;
+    let exclusive = scope.child(DAV, "exclusive").is_some();
+    if !exclusive && scope.child(DAV, "shared").is_none() {
+        return None;
+    }
+    root.child(DAV, "locktype")?.child(DAV, "write")?;
+    Some(LockInfo {
+        exclusive,
+        owner: root.child(DAV, "owner").map_or_else(String::new, serialize)
+    })
+}
+
+// None when an exclusive lock is in the way, or any lock of an exclusive one
+pub fn lock(path: &Path, url: &str, info: LockInfo, infinite: bool, timeout: u64) -> Option<Lock> {
+    let mut locks = locks();
+    let conflict = locks.iter().any(|lock| {
+        (lock.covers(path) || (infinite && lock.path.starts_with(path))) && (lock.exclusive || info.exclusive)
+    });
+    if conflict {
+        return None;
+    }
+    let lock = Lock {
+        token: token(),
+        path: path.to_path_buf(),
+        href: url.to_string(),
+        infinite,
+        exclusive: info.exclusive,
+        owner: info.owner,
+        expires: Instant::now() + Duration::from_secs(timeout)
+    };
+    locks.push(lock.clone());
+    Some(lock)
+}
+
+// A LOCK without a body renews a lock of the If header
+pub fn refresh(path: &Path, tokens: &[String], timeout: u64) -> Option<Lock> {
+    let mut locks = locks();
+    let lock = locks.iter_mut().find(|lock| lock.covers(path) && tokens.contains(&lock.token))?;
+    lock.expires = Instant::now() + Duration::from_secs(timeout);
+    Some(lock.clone())
+}
+
+pub fn unlock(path: &Path, token: &str) -> bool {
+    let mut locks = locks();
+    let before = locks.len();
+    locks.retain(|lock| lock.token != token || !lock.covers(path));
+    locks.len() < before
+}
+
+// The locks of a path that is gone
+pub fn release(path: &Path) {
+    locks().retain(|lock| !lock.path.starts_with(path));
+}
+
+fn activelock(lock: &Lock) -> String {
+    format!(
+        concat!(
+            "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:{}/></D:lockscope>",
+            "<D:depth>{}</D:depth>{}<D:timeout>Second-{}</D:timeout>",
+            "<D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>"
+        ),
+        if lock.exclusive { "exclusive" } else { "shared" },
+        if lock.infinite { "infinity" } else { "0" },
+        lock.owner,
+        lock.expires.saturating_duration_since(Instant::now()).as_secs_f64().ceil() as u64,
+        lock.token,
+        href(&lock.href)
+    )
+}
+
+// Body of a LOCK response
+pub fn discovery(lock: &Lock) -> String {
+    format!("{}<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>\n", HEAD, activelock(lock))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::slice;
+    use crate::webdav::{self, Depth, Find, LockInfo, Update};
+    use crate::xml;
+
+    #[test]
+    fn test_find() {
+        assert_eq!(webdav::find(b""), Some(Find::All));
+        assert_eq!(webdav::find(b"<propfind xmlns='DAV:'><propname/></propfind>"), Some(Find::Names));
+        assert_eq!(
+            webdav::find(b"<D:propfind xmlns:D='DAV:'><D:prop><D:getetag/><x xmlns='urn:x'/></D:prop></D:propfind>"),
+            Some(Find::Props(vec![(String::from("DAV:"), String::from("getetag")), (String::from("urn:x"), String::from("x"))]))
+        );
+        assert_eq!(webdav::find(b"<propfind/>"), None);
+        assert_eq!(Depth::new(Some(&String::from("Infinity")), Depth::One), Some(Depth::Infinity));
+        assert_eq!(Depth::new(None, Depth::One), Some(Depth::One));
+        assert_eq!(Depth::new(Some(&String::from("2")), Depth::One), None);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let root = xml::parse("<D:prop xmlns:D='DAV:' xmlns:a='urn:a'><a:note xmlns='urn:d'>x <b/> &amp; <a:c/></a:note><plain xmlns=''/></D:prop>").unwrap();
+        assert_eq!(
+            webdav::serialize(&root.children[0]),
+            "<ns0:note xmlns:ns0=\"urn:a\" xmlns:D=\"DAV:\" xmlns:a=\"urn:a\" xmlns=\"urn:d\">x <b/> &amp; <a:c/></ns0:note>"
+        );
+        assert_eq!(webdav::serialize(&root.children[1]), "<plain xmlns=\"\" xmlns:D=\"DAV:\" xmlns:a=\"urn:a\"/>");
+    }
+
+    #[test]
+    fn test_props() {
+        let dir = env::temp_dir().join(format!("see-webdav-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::write(dir.join("a/b.txt"), "b").unwrap();
+
+        let body = b"<propertyupdate xmlns='DAV:'><set><prop><n xmlns='urn:x'>one\ttwo\nthree</n></prop></set></propertyupdate>";
+        let updates = webdav::updates(body).unwrap();
+        webdav::proppatch(&dir.join("a/b.txt"), "/a/b.txt", &updates).unwrap();
+        let props = webdav::read_props(&dir.join("a/b.txt"), false);
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].xml, "<ns0:n xmlns:ns0=\"urn:x\" xmlns=\"urn:x\">one\ttwo\nthree</ns0:n>");
+
+        // They go along with copies and moves
+        webdav::copy(&dir.join("a"), &dir.join("c"), true).unwrap();
+        assert_eq!(webdav::read_props(&dir.join("c/b.txt"), false).len(), 1);
+        fs::rename(dir.join("c/b.txt"), dir.join("c/d.txt")).unwrap();
+        webdav::moved(&dir.join("c/b.txt"), &dir.join("c/d.txt"), false);
+        assert_eq!(webdav::read_props(&dir.join("c/d.txt"), false).len(), 1);
+        webdav::delete(&dir.join("c/d.txt")).unwrap();
+        assert_eq!(fs::read_dir(dir.join("c")).unwrap().count(), 0);
+
+        // Live properties stay as they are
+        let body = b"<propertyupdate xmlns='DAV:'><remove><prop><n xmlns='urn:x'/><getetag/></prop></remove></propertyupdate>";
+        let out = webdav::proppatch(&dir.join("a/b.txt"), "/a/b.txt", &webdav::updates(body).unwrap()).unwrap();
+        assert!(out.contains("<D:getetag/></D:prop><D:status>HTTP/1.1 403 Forbidden"));
+        assert!(out.contains("424 Failed Dependency"));
+        assert_eq!(webdav::read_props(&dir.join("a/b.txt"), false).len(), 1);
+        assert!(webdav::updates(b"<propertyupdate xmlns='DAV:'/>").is_none());
+        assert!(matches!(webdav::updates(body).unwrap()[0], Update::Remove(_)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_locks() {
+        let path = Path::new("/see-webdav-locks/a");
+        let exclusive = || LockInfo { exclusive: true, owner: String::new() };
+        let shared = || LockInfo { exclusive: false, owner: String::new() };
+
+        let lock = webdav::lock(path, "/a", exclusive(), true, 60).unwrap();
+        assert!(webdav::lock(&path.join("b"), "/a/b", shared(), false, 60).is_none());
+        assert!(webdav::locked(&path.join("b"), &[], false, false));
+        assert!(!webdav::locked(&path.join("b"), slice::from_ref(&lock.token), false, false));
+        assert!(webdav::locked(path.parent().unwrap(), &[], false, true));
+        assert!(!webdav::locked(path.parent().unwrap(), &[], false, false));
+
+        let header = format!("</a> (<{}> [\"etag\"])", lock.token);
+        assert_eq!(webdav::tokens(Some(&header)), vec![lock.token.clone()]);
+        assert!(webdav::refresh(&path.join("b"), slice::from_ref(&lock.token), 60).is_some());
+        assert!(!webdav::unlock(Path::new("/see-webdav-locks/c"), &lock.token));
+        assert!(webdav::unlock(path, &lock.token));
+
+        // Shared locks get along
+        assert!(webdav::lock(path, "/a", shared(), false, 60).is_some());
+        assert!(webdav::lock(path, "/a", shared(), false, 60).is_some());
+        assert!(webdav::lock(path, "/a", exclusive(), false, 60).is_none());
+        webdav::release(path);
+        assert!(!webdav::locked(path, &[], true, true));
+
+        assert_eq!(webdav::timeout(Some(&String::from("Infinite, Second-4100"))), webdav::MAX_TIMEOUT);
+        assert_eq!(webdav::timeout(Some(&String::from("Second-60"))), 60);
+        assert_eq!(webdav::timeout(None), webdav::MAX_TIMEOUT);
+    }
+
+}
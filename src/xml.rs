@@ -0,0 +1,287 @@
+// Just enough XML for WebDAV request bodies: elements with namespaces and text.
+// Document types are refused, so there are no entities to expand
+
+#[derive(Debug, Default)]
+pub struct Element {
+    pub ns: String,
+    pub name: String,
+    pub children: Vec<Element>,
+    pub text: String,
+    // Source between the tags, as it was sent
+    pub inner: String,
+    // Namespaces in scope, the prefix is "" for the default one
+    pub scope: Vec<(String, String)>
+}
+
+impl Element {
+
+    pub fn is(&self, ns: &str, name: &str) -> bool {
+        self.ns == ns && self.name == name
+    }
+
+    pub fn child(&self, ns: &str, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.is(ns, name))
+    }
+
+}
+
+// Nesting deeper than this is refused rather than followed
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize
+}
+
+pub fn parse(src: &str) -> Result<Element, String> {
+
+    let mut parser = Parser { src: src.trim_start_matches('\u{feff}'), pos: 0 };
+    parser.misc()?;
+    if !parser.rest().starts_with('<') {
+        return Err(String::from("Missing root element"));
+    }
+    let root = parser.element(&[], 0)?;
+    parser.misc()?;
+    if parser.pos < parser.src.len() {
+        return Err(String::from("Content after the root element"));
+    }
+    Ok(root)
+
+}
+
+impl<'a> Parser<'a> {
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // Moves past `end`
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, String> {
+        let rest = self.rest();
+        match rest.find(end) {
+            Some(at) => {
+                self.pos += at + end.len();
+                Ok(&rest[..at])
+            },
+            None => Err(format!("Missing '{}'", end))
+        }
+    }
+
+    // Declarations, comments and white space around the root
+    fn misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_space();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            }else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            }else if rest.starts_with("<!") {
+                return Err(String::from("Document types are not supported"));
+            }else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, String> {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err(String::from("Missing name"));
+        }
+        self.pos += end;
+        Ok(&rest[..end])
+    }
+
+    fn element(&mut self, scope: &[(String, String)], depth: usize) -> Result<Element, String> {
+
+        if depth > MAX_DEPTH {
+            return Err(String::from("Elements are nested too deep"));
+        }
+        self.pos += 1;
+        let tag = self.name()?;
+
+        let mut scope = scope.to_vec();
+        let mut empty = false;
+        loop {
+            self.skip_space();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                empty = true;
+                break;
+            }
+            if rest.starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let key = self.name()?;
+            self.skip_space();
+            if !self.rest().starts_with('=') {
+                return Err(format!("Missing value of '{}'", key));
+            }
+            self.pos += 1;
+            self.skip_space();
+            let quote = match self.rest().chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return Err(format!("Unquoted value of '{}'", key))
+            };
+            self.pos += 1;
+            let value = unescape(self.skip_past(&quote.to_string())?)?;
+            if key == "xmlns" {
+                scope.push((String::new(), value));
+            }else if let Some(prefix) = key.strip_prefix("xmlns:") {
+                scope.push((prefix.to_string(), value));
+            }
+        }
+
+        let (prefix, name) = match tag.split_once(':') {
+            Some((prefix, name)) => (prefix, name),
+            None => ("", tag)
+        };
+        let ns = match scope.iter().rev().find(|(key, _)| key == prefix) {
+            Some((_, ns)) => ns.to_string(),
+            None if prefix.is_empty() => String::new(),
+            None => return Err(format!("Unknown prefix '{}'", prefix))
+        };
+        let mut element = Element { ns, name: name.to_string(), ..Element::default() };
+
+        if !empty {
+            let start = self.pos;
+            loop {
+                let rest = self.rest();
+                if rest.starts_with("</") {
+                    element.inner = self.src[start..self.pos].to_string();
+                    self.pos += 2;
+                    if self.name()? != tag {
+                        return Err(format!("Mismatched end of '{}'", tag));
+                    }
+                    self.skip_space();
+                    if !self.rest().starts_with('>') {
+                        return Err(format!("Broken end of '{}'", tag));
+                    }
+                    self.pos += 1;
+                    break;
+                }else if rest.starts_with("<!--") {
+                    self.skip_past("-->")?;
+                }else if rest.starts_with("<![CDATA[") {
+                    self.pos += 9;
+                    let text = self.skip_past("]]>")?;
+                    element.text.push_str(text);
+                }else if rest.starts_with("<?") {
+                    self.skip_past("?>")?;
+                }else if rest.starts_with('<') {
+                    let child = self.element(&scope, depth + 1)?;
+                    element.children.push(child);
+                }else if rest.is_empty() {
+                    return Err(format!("Missing end of '{}'", tag));
+                }else {
+                    let end = rest.find('<').unwrap_or(rest.len());
+                    element.text.push_str(&unescape(&rest[..end])?);
+                    self.pos += end;
+                }
+            }
+        }
+
+        element.scope = scope;
+        Ok(element)
+
+    }
+
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('&') {
+        out.push_str(&rest[..at]);
+        let end = rest[at..].find(';').ok_or_else(|| String::from("Unterminated reference"))?;
+        let reference = &rest[at + 1..at + end];
+        let c = match reference {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = reference.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                }else if let Some(dec) = reference.strip_prefix('#') {
+                    dec.parse().ok()
+                }else {
+                    None
+                };
+                code.and_then(char::from_u32).ok_or_else(|| format!("Unknown reference '&{};'", reference))?
+            }
+        };
+        out.push(c);
+        rest = &rest[at + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// Text and attribute values
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c)
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::xml;
+
+    #[test]
+    fn test_parse() {
+        let src = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <!-- a comment -->
+            <D:propertyupdate xmlns:D="DAV:" xmlns:Z='urn:z'>
+              <D:set><D:prop><Z:note>a &amp; b<![CDATA[ <c> ]]></Z:note></D:prop></D:set>
+              <D:remove><D:prop><title xmlns="urn:t"/></D:prop></D:remove>
+            </D:propertyupdate>"#;
+        let root = xml::parse(src).unwrap();
+        assert!(root.is("DAV:", "propertyupdate"));
+        let note = &root.child("DAV:", "set").unwrap().child("DAV:", "prop").unwrap().children[0];
+        assert!(note.is("urn:z", "note"));
+        assert_eq!(note.text, "a & b <c> ");
+        assert_eq!(note.inner, "a &amp; b<![CDATA[ <c> ]]>");
+        let title = &root.child("DAV:", "remove").unwrap().child("DAV:", "prop").unwrap().children[0];
+        assert!(title.is("urn:t", "title"));
+        assert_eq!(title.scope.last(), Some(&(String::new(), String::from("urn:t"))));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(xml::parse("").is_err());
+        assert!(xml::parse("<a><b></a>").is_err());
+        assert!(xml::parse("<x:a/>").is_err());
+        assert!(xml::parse("<a/><b/>").is_err());
+        assert!(xml::parse("<!DOCTYPE a [<!ENTITY e 'x'>]><a>&e;</a>").is_err());
+        assert!(xml::parse(&"<a>".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(xml::escape("<a href=\"x\">&</a>"), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+    }
+
+}
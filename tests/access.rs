@@ -0,0 +1,45 @@
+
+mod common;
+
+use std::fs;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    access:
+      - allow: 10.0.0.0/8
+      - deny: all
+    location:
+      /public:
+        access:
+          - allow: all
+"#;
+
+fn get_from(port: u16, path: &str, forwarded: &str) -> String {
+    let req = format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\nX-Forwarded-For: {}\r\n\r\n", path, forwarded);
+    common::send(port, req.as_bytes())
+}
+
+#[test]
+fn denies_by_rule() {
+    let server = common::start_with_config("access", CONFIG);
+    fs::create_dir(server.root.join("public")).unwrap();
+    fs::write(server.root.join("public/a.txt"), "a").unwrap();
+    fs::write(server.root.join("b.txt"), "b").unwrap();
+
+    assert!(common::get(server.port, "/b.txt").starts_with("HTTP/1.1 403"));
+    assert!(common::get(server.port, "/public/a.txt").starts_with("HTTP/1.1 200"));
+    // Not a trusted proxy, the header is ignored
+    assert!(get_from(server.port, "/b.txt", "10.1.2.3").starts_with("HTTP/1.1 403"));
+}
+
+#[test]
+fn trusts_forwarded_for_from_proxies() {
+    let config = CONFIG.to_string() + "    trusted_proxies:\n      - 127.0.0.1\n";
+    let server = common::start_with_config("access-proxy", &config);
+    fs::write(server.root.join("b.txt"), "b").unwrap();
+
+    assert!(get_from(server.port, "/b.txt", "10.1.2.3").starts_with("HTTP/1.1 200"));
+    assert!(get_from(server.port, "/b.txt", "10.1.2.3, 192.168.0.1").starts_with("HTTP/1.1 403"));
+}
@@ -0,0 +1,132 @@
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+// Entries are written in the background
+fn read_lines(path: &Path, count: usize) -> Vec<String> {
+    for _ in 0..100 {
+        if let Ok(text) = fs::read_to_string(path) {
+            let lines: Vec<String> = text.lines().map(String::from).collect();
+            if lines.len() >= count {
+                return lines;
+            }
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("{} has fewer than {} lines", path.display(), count);
+}
+
+#[test]
+fn writes_template_lines() {
+    let server = common::start_with_config("access-log", r#"
+- server:
+    listen: {port}
+    root: {root}
+    log:
+      success: logs/success.log
+      error: logs/error.log
+      format: '$remote_addr "$request" $status $body_bytes_sent $http_user_agent'
+"#);
+    fs::write(server.root.join("hello.txt"), "hello world").unwrap();
+
+    common::send(server.port, b"GET /hello.txt?a=1 HTTP/1.1\r\nHost: 127.0.0.1\r\nUser-Agent: test\r\n\r\n");
+    common::get(server.port, "/missing");
+
+    let success = read_lines(&server.root.join("logs/success.log"), 1);
+    assert_eq!(success, ["127.0.0.1 \"GET /hello.txt?a=1 HTTP/1.1\" 200 11 test"]);
+    let error = read_lines(&server.root.join("logs/error.log"), 1);
+    assert_eq!(error, ["127.0.0.1 \"GET /missing HTTP/1.1\" 404 3 -"]);
+}
+
+#[test]
+fn writes_json_lines() {
+    let server = common::start_with_config("access-json", r#"
+- server:
+    listen: {port}
+    root: {root}
+    index: index.html
+    log:
+      success: logs/success.log
+      format: json
+"#);
+    fs::write(server.root.join("index.html"), "<h1>hi</h1>").unwrap();
+    common::get(server.port, "/");
+
+    let line = &read_lines(&server.root.join("logs/success.log"), 1)[0];
+    assert!(line.starts_with("{\"time\":\""));
+    assert!(line.contains("\"remote_addr\":\"127.0.0.1\",\"remote_user\":null,\"method\":\"GET\",\"uri\":\"/\""));
+    assert!(line.contains("\"status\":200,"));
+    assert!(line.contains("\"body_bytes_sent\":11,"));
+}
+
+#[cfg(unix)]
+#[test]
+fn flushes_on_shutdown() {
+    let mut server = common::start_with_config("access-flush", r#"
+- server:
+    listen: {port}
+    root: {root}
+    log:
+      error: logs/error.log
+      flush: 3600
+"#);
+    for _ in 0..5 {
+        common::get(server.port, "/missing");
+    }
+    common::signal(server.child.id(), libc::SIGTERM);
+    assert!(common::wait_exit(&mut server, Duration::from_secs(10)).is_some());
+
+    let text = fs::read_to_string(server.root.join("logs/error.log")).unwrap();
+    assert_eq!(text.lines().count(), 5);
+}
+
+#[cfg(unix)]
+#[test]
+fn reopens_on_sigusr1() {
+    let server = common::start_with_config("access-reopen", r#"
+- server:
+    listen: {port}
+    root: {root}
+    log:
+      error: logs/error.log
+"#);
+    let log = server.root.join("logs/error.log");
+    let moved = server.root.join("logs/error.log.1");
+
+    common::get(server.port, "/before");
+    read_lines(&log, 1);
+    fs::rename(&log, &moved).unwrap();
+    common::signal(server.child.id(), libc::SIGUSR1);
+    thread::sleep(Duration::from_millis(200));
+    common::get(server.port, "/after");
+
+    assert!(read_lines(&log, 1)[0].contains("/after"));
+    assert_eq!(read_lines(&moved, 1).len(), 1);
+}
+
+#[test]
+fn sends_to_syslog() {
+    let collector = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let config = r#"
+- server:
+    listen: {port}
+    root: {root}
+    log:
+      error: syslog://{syslog}
+      format: $request_method $uri $status
+"#.replace("{syslog}", &collector.local_addr().unwrap().to_string());
+    let server = common::start_with_config("access-syslog", &config);
+    common::get(server.port, "/missing");
+
+    let mut buffer = [0; 1024];
+    let n = collector.recv(&mut buffer).unwrap();
+    let packet = String::from_utf8_lossy(&buffer[..n]).to_string();
+    // daemon.warning
+    assert!(packet.starts_with("<28>1 "));
+    assert!(packet.ends_with(" access - GET /missing 404"));
+}
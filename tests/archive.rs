@@ -0,0 +1,93 @@
+mod common;
+
+use std::fs;
+use std::io::prelude::*;
+use flate2::read::GzDecoder;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    directory:
+      archive:
+        formats:
+          - tar.gz
+        max_size: 1
+    location:
+      /docs/private:
+        access:
+          - deny: all
+"#;
+
+// Header and the body with the chunks joined
+fn download(port: u16, path: &str) -> (String, Vec<u8>) {
+    let mut stream = common::connect(port);
+    let req = format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", path);
+    stream.write_all(req.as_bytes()).unwrap();
+    let mut res = vec![];
+    let _ = stream.read_to_end(&mut res);
+
+    let end = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let header = String::from_utf8_lossy(&res[..end]).to_string();
+    let mut rest = &res[end..];
+    let mut body = vec![];
+    loop {
+        let line = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = usize::from_str_radix(std::str::from_utf8(&rest[..line]).unwrap(), 16).unwrap();
+        if size == 0 {
+            break;
+        }
+        body.extend_from_slice(&rest[line + 2..line + 2 + size]);
+        rest = &rest[line + 4 + size..];
+    }
+    (header, body)
+}
+
+// Names in a tar file
+fn names(tar: &[u8]) -> Vec<String> {
+    let mut names = vec![];
+    let mut at = 0;
+    while at + 512 <= tar.len() && tar[at] != 0 {
+        let name = &tar[at..at + 100];
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(100)];
+        names.push(String::from_utf8_lossy(name).to_string());
+        let size = std::str::from_utf8(&tar[at + 124..at + 135]).unwrap();
+        let size = usize::from_str_radix(size, 8).unwrap();
+        at += 512 + size.div_ceil(512) * 512;
+    }
+    names
+}
+
+#[test]
+fn streams_tar_gz() {
+    let server = common::start_with_config("archive", CONFIG);
+    fs::create_dir_all(server.root.join("docs/sub")).unwrap();
+    fs::create_dir_all(server.root.join("docs/private")).unwrap();
+    fs::write(server.root.join("docs/a.txt"), "a").unwrap();
+    fs::write(server.root.join("docs/sub/b.txt"), "b").unwrap();
+    fs::write(server.root.join("docs/.hidden"), "h").unwrap();
+    fs::write(server.root.join("docs/private/key"), "k").unwrap();
+
+    let (header, body) = download(server.port, "/docs/?download=tar.gz");
+    assert!(header.starts_with("HTTP/1.1 200"));
+    assert!(header.contains("Content-Type: application/gzip"));
+    assert!(header.contains("Transfer-Encoding: chunked"));
+    assert!(header.contains("Content-Disposition: attachment; filename=\"docs.tar.gz\""));
+
+    let mut tar = vec![];
+    GzDecoder::new(&body[..]).read_to_end(&mut tar).unwrap();
+    assert_eq!(names(&tar), ["a.txt", "sub/", "sub/b.txt"]);
+}
+
+#[test]
+fn rejects_other_formats_and_large_trees() {
+    let server = common::start_with_config("archive-limit", CONFIG);
+    fs::create_dir(server.root.join("big")).unwrap();
+    fs::write(server.root.join("big/data"), vec![0; 2 * 1024 * 1024]).unwrap();
+
+    assert!(common::get(server.port, "/?download=zip").starts_with("HTTP/1.1 400"));
+    assert!(common::get(server.port, "/big/?download=tar.gz").starts_with("HTTP/1.1 403"));
+    // Without the option it is only a listing
+    let server = common::start("archive-off");
+    assert!(common::get(server.port, "/?download=zip").contains("<title>Index of /</title>"));
+}
@@ -0,0 +1,151 @@
+
+// Each test binary uses a different part of these helpers
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+pub struct Server {
+    pub child: Child,
+    pub port: u16,
+    pub root: PathBuf
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+// An empty directory for one test
+pub fn temp_root(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("see-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_ready(port: u16) {
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("server on port {} did not start", port);
+}
+
+// Run `see start <port>` in an empty directory
+pub fn start(name: &str) -> Server {
+
+    let root = temp_root(name);
+    let port = free_port();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_see"))
+        .args(["start", &port.to_string()])
+        .current_dir(&root)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    wait_ready(port);
+    Server { child, port, root }
+
+}
+
+// Run `see -c config.yml`, `{port}` and `{root}` in the config are filled in
+pub fn start_with_config(name: &str, config: &str) -> Server {
+
+    let root = temp_root(name);
+    let port = free_port();
+
+    let config = config
+        .replace("{port}", &port.to_string())
+        .replace("{root}", root.to_str().unwrap());
+    let path = root.join("config.yml");
+    fs::write(&path, config).unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_see"))
+        .args(["-c", path.to_str().unwrap()])
+        .current_dir(&root)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    wait_ready(port);
+    Server { child, port, root }
+
+}
+
+pub fn connect(port: u16) -> TcpStream {
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream
+}
+
+// Send raw bytes and read until the server closes the connection
+pub fn send(port: u16, data: &[u8]) -> String {
+    let mut stream = connect(port);
+    stream.write_all(data).unwrap();
+    let mut res = vec![];
+    let _ = stream.read_to_end(&mut res);
+    String::from_utf8_lossy(&res).to_string()
+}
+
+pub fn get(port: u16, path: &str) -> String {
+    let req = format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", path);
+    send(port, req.as_bytes())
+}
+
+// Processes whose command line contains `needle`, e.g. an upgraded server
+#[cfg(target_os = "linux")]
+pub fn pids_with_arg(needle: &str) -> Vec<i32> {
+    let mut pids = vec![];
+    for entry in fs::read_dir("/proc").unwrap().flatten() {
+        let pid = match entry.file_name().to_str().and_then(|n| n.parse::<i32>().ok()) {
+            Some(pid) => pid,
+            None => continue
+        };
+        if let Ok(cmdline) = fs::read(entry.path().join("cmdline")) {
+            let args: Vec<&[u8]> = cmdline.split(|b| *b == 0).collect();
+            if args.contains(&needle.as_bytes()) {
+                pids.push(pid);
+            }
+        }
+    }
+    pids
+}
+
+pub fn signal(pid: u32, sig: i32) {
+    unsafe {
+        libc::kill(pid as i32, sig);
+    }
+}
+
+// Wait for the server process to exit on its own
+pub fn wait_exit(server: &mut Server, timeout: Duration) -> Option<std::process::ExitStatus> {
+    let start = std::time::Instant::now();
+    while start.elapsed() < timeout {
+        if let Ok(Some(status)) = server.child.try_wait() {
+            return Some(status);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    None
+}
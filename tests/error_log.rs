@@ -0,0 +1,55 @@
+
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+const CONFIG: &str = r#"
+- global:
+    error_log: {root}/logs/error.log
+    log_level: {level}
+- server:
+    listen: {port}
+    root: {root}
+    method:
+      - GET
+"#;
+
+fn wait_for(server: &common::Server, needle: &str) -> String {
+    let path = server.root.join("logs/error.log");
+    for _ in 0..100 {
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        if text.contains(needle) {
+            return text;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("\"{}\" is not in {}", needle, path.display());
+}
+
+#[test]
+fn records_causes() {
+    let server = common::start_with_config("error-log", &CONFIG.replace("{level}", "info"));
+    common::get(server.port, "/missing.html");
+    common::send(server.port, b"BROKEN\r\n\r\n");
+
+    let path = server.root.join("missing.html");
+    let text = wait_for(&server, "Invalid request line");
+    assert!(text.contains(&format!(
+        "[info] \"{}\" is not found (No such file or directory (os error 2)), \
+         client: 127.0.0.1, request: \"GET /missing.html HTTP/1.1\"",
+        path.display()
+    )));
+    assert!(text.contains("[info] Invalid request line \"BROKEN\", client: 127.0.0.1"));
+}
+
+#[test]
+fn filters_by_level() {
+    let config = CONFIG.replace("{level}", "error") + "    error:\n      404: not-there.html\n";
+    let server = common::start_with_config("error-level", &config);
+    common::get(server.port, "/missing.html");
+
+    let text = wait_for(&server, "[error] Opening the error page");
+    assert!(!text.contains("[info]"));
+}
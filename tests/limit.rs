@@ -0,0 +1,112 @@
+
+mod common;
+
+use std::fs;
+use std::io::prelude::*;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn limits_request_rate() {
+    let server = common::start_with_config("limit-rate", r#"
+- server:
+    listen: {port}
+    root: {root}
+    limit:
+      rate: 0.5
+      burst: 1
+      nodelay: true
+      key: X-Api-Key
+"#);
+    fs::write(server.root.join("a.txt"), "a").unwrap();
+
+    let get = |key: &str| {
+        let req = format!("GET /a.txt HTTP/1.1\r\nHost: 127.0.0.1\r\nX-Api-Key: {}\r\n\r\n", key);
+        common::send(server.port, req.as_bytes())
+    };
+    assert!(get("one").starts_with("HTTP/1.1 200"));
+    assert!(get("one").starts_with("HTTP/1.1 200"));
+    let res = get("one");
+    assert!(res.starts_with("HTTP/1.1 429"));
+    assert!(res.contains("Retry-After: "));
+    // Each key has its own bucket
+    assert!(get("two").starts_with("HTTP/1.1 200"));
+}
+
+#[test]
+fn delays_the_burst() {
+    let server = common::start_with_config("limit-delay", r#"
+- global:
+    worker_threads: 1
+- server:
+    listen: {port}
+    root: {root}
+    limit:
+      rate: 2
+      burst: 1
+"#);
+    fs::write(server.root.join("a.txt"), "a").unwrap();
+
+    assert!(common::get(server.port, "/a.txt").starts_with("HTTP/1.1 200"));
+    // The second request waits for its turn, without the only worker
+    let mut delayed = common::connect(server.port);
+    delayed.write_all(b"GET /a.txt HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    let start = Instant::now();
+    assert!(common::get(server.port, "/a.txt").starts_with("HTTP/1.1 429"));
+    assert!(start.elapsed() < Duration::from_millis(300));
+    let mut res = String::new();
+    let _ = delayed.read_to_string(&mut res);
+    assert!(res.starts_with("HTTP/1.1 200"));
+}
+
+#[test]
+fn limits_connections() {
+    let server = common::start_with_config("limit-connections", r#"
+- server:
+    listen: {port}
+    root: {root}
+    limit:
+      connections: 1
+"#);
+    fs::write(server.root.join("a.txt"), "a").unwrap();
+
+    let idle = common::connect(server.port);
+    assert!(common::get(server.port, "/a.txt").starts_with("HTTP/1.1 429"));
+    drop(idle);
+
+    // The server notices the closed connection on its own time
+    for _ in 0..100 {
+        if common::get(server.port, "/a.txt").starts_with("HTTP/1.1 200") {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("the connection was not released");
+}
+
+#[test]
+fn limits_connections_per_client_behind_a_proxy() {
+    let server = common::start_with_config("limit-proxied", r#"
+- server:
+    listen: {port}
+    root: {root}
+    proxy_protocol: true
+    timeout:
+      header: 2
+    limit:
+      connections: 1
+"#);
+    fs::write(server.root.join("a.txt"), "a").unwrap();
+
+    let get = |client: &str| {
+        let req = format!("PROXY TCP4 {} 10.0.0.1 40000 80\r\nGET /a.txt HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", client);
+        common::send(server.port, req.as_bytes())
+    };
+    let mut idle = common::connect(server.port);
+    idle.write_all(b"PROXY TCP4 203.0.113.7 10.0.0.1 40000 80\r\nGET /a.txt HTTP/1.1\r\n").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    // The proxy itself isn't held to the limit, its clients are
+    assert!(get("203.0.113.8").starts_with("HTTP/1.1 200"));
+    assert!(get("203.0.113.7").starts_with("HTTP/1.1 429"));
+}
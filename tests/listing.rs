@@ -0,0 +1,162 @@
+
+mod common;
+
+use std::fs;
+
+#[test]
+fn escapes_hostile_names() {
+    let server = common::start("listing-escape");
+    fs::write(server.root.join("<img src=x onerror=alert(1)>"), "").unwrap();
+    fs::write(server.root.join("a b#c?d.txt"), "").unwrap();
+    fs::write(server.root.join("\"quoted\" {files}.txt"), "").unwrap();
+    fs::write(server.root.join("ü.txt"), "").unwrap();
+    fs::create_dir(server.root.join("x&y")).unwrap();
+
+    let res = common::get(server.port, "/");
+    assert!(!res.contains("<img"));
+    assert!(res.contains("<a href=\"%3Cimg%20src=x%20onerror=alert(1)%3E\">&lt;img src=x onerror=alert(1)&gt;</a>"));
+    assert!(res.contains("<a href=\"a%20b%23c%3Fd.txt\">a b#c?d.txt</a>"));
+    assert!(res.contains("<a href=\"%22quoted%22%20%7Bfiles%7D.txt\">&quot;quoted&quot; &#123;files&#125;.txt</a>"));
+    assert!(res.contains("<a href=\"%C3%BC.txt\">ü.txt</a>"));
+    assert!(res.contains("<a href=\"x&amp;y/\">x&amp;y/</a>"));
+
+    // The links lead back to the files
+    assert!(common::get(server.port, "/a%20b%23c%3Fd.txt").starts_with("HTTP/1.1 200"));
+}
+
+#[test]
+fn escapes_title() {
+    let server = common::start("listing-title");
+    fs::create_dir(server.root.join("<b>")).unwrap();
+
+    let res = common::get(server.port, "/%3Cb%3E/");
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.contains("<title>Index of /&lt;b&gt;/</title>"));
+}
+
+#[test]
+fn sorts_entries() {
+    let server = common::start("listing-sort");
+    fs::write(server.root.join("file10.txt"), "1234").unwrap();
+    fs::write(server.root.join("file2.txt"), "12").unwrap();
+    fs::write(server.root.join("File1.txt"), "123").unwrap();
+    fs::create_dir(server.root.join("zdir")).unwrap();
+
+    let order = |res: &str, names: &[&str]| {
+        let at: Vec<usize> = names.iter().map(|name| res.find(&format!(">{}<", name)).unwrap()).collect();
+        assert!(at.windows(2).all(|w| w[0] < w[1]), "{:?} out of order", names);
+    };
+
+    let res = common::get(server.port, "/");
+    order(&res, &["zdir/", "File1.txt", "file2.txt", "file10.txt"]);
+    assert!(res.contains("<a class=\"sort\" href=\"?sort=name&amp;order=desc\">Name \u{2191}</a>"));
+
+    let res = common::get(server.port, "/?sort=size&order=desc");
+    order(&res, &["zdir/", "file10.txt", "File1.txt", "file2.txt"]);
+    assert!(res.contains("<a class=\"sort column\" href=\"?sort=size&amp;order=asc\">Size \u{2193}</a>"));
+}
+
+#[test]
+fn lists_as_json_and_text() {
+    let server = common::start("listing-formats");
+    fs::create_dir(server.root.join("builds")).unwrap();
+    fs::write(server.root.join("builds/app 2.tar"), "1234").unwrap();
+    fs::write(server.root.join("builds/app 10.tar"), "12").unwrap();
+
+    let res = common::get(server.port, "/builds/?format=json");
+    assert!(res.contains("Content-Type: application/json"));
+    assert!(res.contains("[{\"name\":\"app 2.tar\",\"type\":\"file\",\"size\":4,\"mtime\":\""));
+    assert!(res.contains("\"link\":\"/builds/app%2010.tar\"}]"));
+
+    let req = b"GET /builds/ HTTP/1.1\r\nHost: 127.0.0.1\r\nAccept: text/plain\r\n\r\n";
+    let res = common::send(server.port, req);
+    assert!(res.contains("Content-Type: text/plain"));
+    assert!(res.ends_with("\r\n\r\napp 2.tar\napp 10.tar\n"));
+}
+
+#[test]
+fn renders_user_template() {
+    // Outside the served root, the template is read on start
+    let theme = common::temp_root("listing-theme");
+    fs::write(theme.join("listing.html"), concat!(
+        "<nav><a href=\"/\">home</a>{% for crumb in breadcrumbs %} / <a href=\"{{ crumb.href }}\">{{ crumb.name }}</a>{% endfor %}</nav>\n",
+        "{% if not root %}<a href=\"../\">up</a>\n{% endif %}",
+        "{% for entry in entries %}<a href=\"{{ entry.href }}\">{{ entry.name }}</a> {{ entry.size | size }}\n{% endfor %}"
+    )).unwrap();
+
+    let config = r#"
+- server:
+    listen: {port}
+    root: {root}
+    directory:
+      template: {theme}/listing.html
+"#.replace("{theme}", theme.to_str().unwrap());
+    let server = common::start_with_config("listing-template", &config);
+    fs::create_dir_all(server.root.join("docs/v 1")).unwrap();
+    fs::write(server.root.join("docs/v 1/<a>.txt"), "1234").unwrap();
+
+    let res = common::get(server.port, "/docs/v%201/");
+    assert!(res.ends_with(concat!(
+        "\r\n\r\n<nav><a href=\"/\">home</a> / <a href=\"/docs/\">docs</a> / <a href=\"/docs/v%201/\">v 1</a></nav>\n",
+        "<a href=\"../\">up</a>\n",
+        "<a href=\"%3Ca%3E.txt\">&lt;a&gt;.txt</a> 4.00 B\n"
+    )));
+
+    let res = common::get(server.port, "/");
+    assert!(!res.contains("up</a>"));
+    let _ = fs::remove_dir_all(theme);
+}
+
+#[test]
+fn filters_and_pages() {
+    let config = "- server:\n    listen: {port}\n    root: {root}\n    directory:\n      page_size: 2\n";
+    let server = common::start_with_config("listing-filter", config);
+    for name in ["nightly-1.tar.gz", "nightly-2.tar.gz", "nightly-3.zip", "release.tar.gz", "c++ notes.txt"] {
+        fs::write(server.root.join(name), "").unwrap();
+    }
+
+    let res = common::get(server.port, "/?q=nightly");
+    assert!(res.contains("<input type=\"search\" name=\"q\" value=\"nightly\""));
+    assert!(res.contains(">nightly-1.tar.gz<"));
+    assert!(res.contains(">nightly-2.tar.gz<"));
+    assert!(!res.contains(">nightly-3.zip<"));
+    assert!(!res.contains(">release.tar.gz<"));
+    // The filter goes along to the next page and the sorting links
+    assert!(res.contains("<span>Page 1 of 2</span><a href=\"?sort=name&amp;order=asc&amp;q=nightly&amp;page=2\">"));
+    assert!(res.contains("href=\"?sort=name&amp;order=desc&amp;q=nightly\""));
+
+    let res = common::get(server.port, "/?q=nightly&page=2");
+    assert!(res.contains(">nightly-3.zip<"));
+    assert!(!res.contains(">nightly-1.tar.gz<"));
+    assert!(res.contains("<span>Page 2 of 2</span></nav>"));
+
+    let res = common::get(server.port, "/?q=*.tar.gz&format=text");
+    assert!(res.ends_with("\r\n\r\nnightly-1.tar.gz\nnightly-2.tar.gz\nrelease.tar.gz\n"));
+
+    // '+' from a form is a space, %2B a plus
+    let res = common::get(server.port, "/?q=c%2B%2B+notes&format=text");
+    assert!(res.ends_with("\r\n\r\nc++ notes.txt\n"));
+    let res = common::get(server.port, "/?q=c%2B%2B&format=text");
+    assert!(res.ends_with("\r\n\r\nc++ notes.txt\n"));
+    let res = common::get(server.port, "/?q=c+%2B&format=text");
+    assert!(res.ends_with("\r\n\r\n"));
+
+    let res = common::get(server.port, "/?q=%22a");
+    assert!(res.contains("value=\"&quot;a\""));
+    assert!(!res.contains("<nav class=\"pages\">"));
+
+    // An encoded '&' stays in the filter, across pages and the redirect to the directory
+    for name in ["a&b-1.txt", "a&b-2.txt", "a&b-3.txt"] {
+        fs::write(server.root.join(name), "").unwrap();
+    }
+    let res = common::get(server.port, "/?q=a%26b");
+    assert!(res.contains(">a&amp;b-1.txt<"));
+    assert!(res.contains("<span>Page 1 of 2</span><a href=\"?sort=name&amp;order=asc&amp;q=a%26b&amp;page=2\">"));
+    let res = common::get(server.port, "/?sort=name&order=asc&q=a%26b&page=2");
+    assert!(res.contains(">a&amp;b-3.txt<"));
+    assert!(res.contains("<span>Page 2 of 2</span></nav>"));
+    fs::create_dir(server.root.join("sub")).unwrap();
+    let res = common::get(server.port, "/sub?q=a%26b+c");
+    assert!(res.starts_with("HTTP/1.1 301"));
+    assert!(res.contains("Location: /sub/?q=a%26b+c\r\n"));
+}
@@ -0,0 +1,30 @@
+
+mod common;
+
+use std::net::TcpStream;
+
+fn hold_idle(port: u16, count: usize) -> Vec<TcpStream> {
+    (0..count)
+        .map(|_| TcpStream::connect(("127.0.0.1", port)).unwrap())
+        .collect()
+}
+
+#[test]
+fn serves_with_idle_connections() {
+    let server = common::start("idle");
+    let idle = hold_idle(server.port, 500);
+    assert!(common::get(server.port, "/").starts_with("HTTP/1.1 200"));
+    drop(idle);
+}
+
+// Needs ~10k file descriptors per process: `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+fn serves_with_10k_idle_connections() {
+    let server = common::start("load");
+    let idle = hold_idle(server.port, 10_000);
+    for _ in 0..10 {
+        assert!(common::get(server.port, "/").starts_with("HTTP/1.1 200"));
+    }
+    drop(idle);
+}
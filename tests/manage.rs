@@ -0,0 +1,131 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    auth:
+      user: admin
+      password: secret
+    manage:
+      path: /files
+    location:
+      /files/private:
+        access:
+          - deny: all
+"#;
+
+// admin:secret
+const AUTH: &str = "Authorization: Basic YWRtaW46c2VjcmV0\r\n";
+
+fn request(port: u16, method: &str, path: &str, headers: &str) -> String {
+    let req = format!("{} {} HTTP/1.1\r\nHost: 127.0.0.1\r\n{}{}\r\n", method, path, AUTH, headers);
+    common::send(port, req.as_bytes())
+}
+
+#[test]
+fn deletes_files() {
+    let server = common::start_with_config("manage-delete", CONFIG);
+    fs::create_dir_all(server.root.join("files/dir")).unwrap();
+    fs::write(server.root.join("files/a.txt"), "a").unwrap();
+    fs::write(server.root.join("files/dir/b.txt"), "b").unwrap();
+    fs::write(server.root.join("other.txt"), "o").unwrap();
+
+    let res = request(server.port, "DELETE", "/files/a.txt", "");
+    assert!(res.starts_with("HTTP/1.1 204"));
+    assert!(!server.root.join("files/a.txt").exists());
+    assert!(request(server.port, "DELETE", "/files/a.txt", "").starts_with("HTTP/1.1 404"));
+
+    // Not without `recursive`
+    assert!(request(server.port, "DELETE", "/files/dir", "").starts_with("HTTP/1.1 409"));
+    assert!(server.root.join("files/dir/b.txt").exists());
+
+    assert!(request(server.port, "DELETE", "/other.txt", "").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "DELETE", "/files/../other.txt", "").starts_with("HTTP/1.1 403"));
+    assert!(server.root.join("other.txt").exists());
+
+    let res = common::send(server.port, b"DELETE /files/dir/b.txt HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+    assert!(res.starts_with("HTTP/1.1 401"));
+    assert!(server.root.join("files/dir/b.txt").exists());
+}
+
+#[test]
+fn stays_in_the_root() {
+    let server = common::start_with_config("manage-symlink", CONFIG);
+    let outside = common::temp_root("manage-outside");
+    fs::write(outside.join("a.txt"), "a").unwrap();
+    fs::create_dir(server.root.join("files")).unwrap();
+    fs::write(server.root.join("files/b.txt"), "b").unwrap();
+    std::os::unix::fs::symlink(&outside, server.root.join("files/out")).unwrap();
+
+    assert!(request(server.port, "DELETE", "/files/out/a.txt", "").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "MOVE", "/files/out/a.txt", "Destination: /files/a.txt\r\n").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "MOVE", "/files/b.txt", "Destination: /files/out/b.txt\r\n").starts_with("HTTP/1.1 403"));
+    assert!(outside.join("a.txt").exists());
+    assert!(!outside.join("b.txt").exists());
+    fs::remove_dir_all(&outside).unwrap();
+}
+
+#[test]
+fn deletes_directories_with_recursive() {
+    let config = CONFIG.replace("path: /files", "path: /files\n      recursive: true");
+    let server = common::start_with_config("manage-recursive", &config);
+    fs::create_dir_all(server.root.join("files/dir/sub")).unwrap();
+    fs::write(server.root.join("files/dir/sub/a.txt"), "a").unwrap();
+
+    assert!(request(server.port, "DELETE", "/files/dir/", "").starts_with("HTTP/1.1 204"));
+    assert!(!server.root.join("files/dir").exists());
+    // The managed path itself is fine, the root is not
+    assert!(request(server.port, "DELETE", "/files", "").starts_with("HTTP/1.1 204"));
+}
+
+#[test]
+fn moves_files() {
+    let server = common::start_with_config("manage-move", CONFIG);
+    fs::create_dir_all(server.root.join("files/dir")).unwrap();
+    fs::write(server.root.join("files/a.txt"), "a").unwrap();
+    fs::write(server.root.join("files/b.txt"), "b").unwrap();
+
+    let res = request(server.port, "MOVE", "/files/a.txt", "Destination: http://127.0.0.1/files/dir/a%20b.txt\r\n");
+    assert!(res.starts_with("HTTP/1.1 201"));
+    assert!(res.contains("Location: /files/dir/a%20b.txt"));
+    assert_eq!(fs::read(server.root.join("files/dir/a b.txt")).unwrap(), b"a");
+
+    // Replaced unless `Overwrite: F`
+    let res = request(server.port, "MOVE", "/files/b.txt", "Destination: /files/dir/a%20b.txt\r\nOverwrite: F\r\n");
+    assert!(res.starts_with("HTTP/1.1 412"));
+    let res = request(server.port, "MOVE", "/files/b.txt", "Destination: /files/dir/a%20b.txt\r\n");
+    assert!(res.starts_with("HTTP/1.1 204"));
+    assert_eq!(fs::read(server.root.join("files/dir/a b.txt")).unwrap(), b"b");
+
+    assert!(request(server.port, "MOVE", "/files/none.txt", "Destination: /files/c.txt\r\n").starts_with("HTTP/1.1 404"));
+    assert!(request(server.port, "MOVE", "/files/dir", "Destination: /files/dir/sub\r\n").starts_with("HTTP/1.1 409"));
+    assert!(request(server.port, "MOVE", "/files/dir", "Destination: /files/missing/dir\r\n").starts_with("HTTP/1.1 409"));
+    assert!(request(server.port, "MOVE", "/files/dir", "").starts_with("HTTP/1.1 400"));
+
+    // The destination follows the same rules
+    assert!(request(server.port, "MOVE", "/files/dir", "Destination: /dir\r\n").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "MOVE", "/files/dir", "Destination: /files/private\r\n").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "MOVE", "/files/dir", "Destination: /files/../dir\r\n").starts_with("HTTP/1.1 403"));
+    assert!(server.root.join("files/dir").is_dir());
+}
+
+#[test]
+fn needs_auth() {
+    let root = common::temp_root("manage-auth");
+    let path = root.join("config.yml");
+    let config = format!("- server:\n    listen: {}\n    root: {}\n    manage: true\n", common::free_port(), root.display());
+    fs::write(&path, config).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_see"))
+        .args(["-c", path.to_str().unwrap()])
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'manage' needs 'auth'"));
+    fs::remove_dir_all(&root).unwrap();
+}
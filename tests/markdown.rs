@@ -0,0 +1,59 @@
+mod common;
+
+use std::fs;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    directory: true
+    markdown: true
+    location:
+      /docs/README.md:
+        access:
+          - deny: all
+"#;
+
+#[test]
+fn renders_markdown_files() {
+    let server = common::start_with_config("markdown", CONFIG);
+    fs::write(server.root.join("notes.md"), "# Notes\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n<script>x</script>\n").unwrap();
+
+    let res = common::get(server.port, "/notes.md");
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.contains("Content-Type: text/html"));
+    assert!(res.contains("<title>/notes.md</title>"));
+    assert!(res.contains("<h1 id=\"notes\">Notes</h1>"));
+    assert!(res.contains("<td>1</td>"));
+    assert!(res.contains("&lt;script&gt;x&lt;/script&gt;"));
+
+    let res = common::get(server.port, "/notes.md?raw");
+    assert!(res.contains("Content-Type: text/plain; charset=utf-8"));
+    assert!(res.ends_with("<script>x</script>\n"));
+}
+
+#[test]
+fn shows_readme_below_listing() {
+    let server = common::start_with_config("markdown-readme", CONFIG);
+    fs::write(server.root.join("README.md"), "Read **me**").unwrap();
+    fs::create_dir(server.root.join("docs")).unwrap();
+    fs::write(server.root.join("docs/README.md"), "secret").unwrap();
+
+    let res = common::get(server.port, "/");
+    assert!(res.contains("<article class=\"readme\">\n<p>Read <strong>me</strong></p>\n</article>"));
+    assert!(!res.contains("{readme}"));
+
+    // Not shown to whom `access` denies the file
+    let res = common::get(server.port, "/docs/");
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(!res.contains("secret"));
+}
+
+#[test]
+fn serves_files_as_they_are_without_the_option() {
+    let server = common::start_with_config("markdown-off", "- server:\n    listen: {port}\n    root: {root}\n");
+    fs::write(server.root.join("notes.md"), "# Notes").unwrap();
+
+    let res = common::get(server.port, "/notes.md");
+    assert!(res.ends_with("\r\n\r\n# Notes"));
+}
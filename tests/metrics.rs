@@ -0,0 +1,47 @@
+
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn exposes_counters() {
+    let server = common::start_with_config("metrics", r#"
+- server:
+    listen: {port}
+    root: {root}
+    metrics: /metrics
+"#);
+    fs::write(server.root.join("hello.txt"), "hello world").unwrap();
+
+    common::get(server.port, "/hello.txt");
+    common::get(server.port, "/missing");
+
+    // Requests are counted once their response is out
+    let label = format!("server=\"*:{}\"", server.port);
+    for _ in 0..100 {
+        let res = common::get(server.port, "/metrics");
+        assert!(res.starts_with("HTTP/1.1 200"));
+        if res.contains(&format!("see_requests_total{{{},method=\"GET\",status=\"404\"}} 1", label)) {
+            assert!(res.contains(&format!("see_requests_total{{{},method=\"GET\",status=\"200\"}} 1", label)));
+            assert!(res.contains(&format!("see_request_duration_seconds_count{{{}}} 2", label)));
+            assert!(res.contains("see_connections_active 1"));
+            assert!(res.contains("# TYPE see_worker_queue_depth gauge"));
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("the requests were not counted");
+}
+
+#[test]
+fn serves_only_its_path() {
+    let server = common::start_with_config("metrics-path", r#"
+- server:
+    listen: {port}
+    root: {root}
+    metrics: /metrics
+"#);
+    assert!(common::get(server.port, "/metric").starts_with("HTTP/1.1 404"));
+}
@@ -0,0 +1,40 @@
+
+mod common;
+
+use std::fs;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    proxy_protocol: true
+    access:
+      - allow: 203.0.113.0/24
+      - deny: all
+"#;
+
+#[test]
+fn takes_client_from_v1_header() {
+    let server = common::start_with_config("proxy-v1", CONFIG);
+    fs::write(server.root.join("a.txt"), "a").unwrap();
+
+    let req = b"PROXY TCP4 203.0.113.7 10.0.0.1 40000 80\r\nGET /a.txt HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n";
+    assert!(common::send(server.port, req).starts_with("HTTP/1.1 200"));
+
+    let req = b"PROXY TCP4 198.51.100.1 10.0.0.1 40000 80\r\nGET /a.txt HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n";
+    assert!(common::send(server.port, req).starts_with("HTTP/1.1 403"));
+
+    // Without the header the connection is dropped
+    assert_eq!(common::get(server.port, "/a.txt"), "");
+}
+
+#[test]
+fn takes_client_from_v2_header() {
+    let server = common::start_with_config("proxy-v2", CONFIG);
+    fs::write(server.root.join("a.txt"), "a").unwrap();
+
+    let mut req = vec![0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a];
+    req.extend_from_slice(&[0x21, 0x11, 0, 12, 203, 0, 113, 7, 10, 0, 0, 1, 0x9c, 0x40, 0, 80]);
+    req.extend_from_slice(b"GET /a.txt HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+    assert!(common::send(server.port, &req).starts_with("HTTP/1.1 200"));
+}
@@ -0,0 +1,97 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::fs;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    header:
+      X-Version: "1"
+"#;
+
+fn rewrite(server: &common::Server, config: &str, extra: u16) {
+    let config = config
+        .replace("{port}", &server.port.to_string())
+        .replace("{extra}", &extra.to_string())
+        .replace("{root}", server.root.to_str().unwrap());
+    fs::write(server.root.join("config.yml"), config).unwrap();
+    common::signal(server.child.id(), libc::SIGHUP);
+    thread::sleep(Duration::from_millis(300));
+}
+
+#[test]
+fn sighup_applies_new_config() {
+    let server = common::start_with_config("reload", CONFIG);
+    assert!(common::get(server.port, "/").contains("X-Version: 1"));
+
+    let extra = common::free_port();
+    rewrite(&server, r#"
+- server:
+    listen: {port}
+    root: {root}
+    header:
+      X-Version: "2"
+- server:
+    listen: {extra}
+    root: {root}
+"#, extra);
+    assert!(common::get(server.port, "/").contains("X-Version: 2"));
+    assert!(common::get(extra, "/").starts_with("HTTP/1.1 404"));
+
+    // Dropping a server closes its port
+    rewrite(&server, r#"
+- server:
+    listen: {port}
+    root: {root}
+"#, extra);
+    assert!(TcpStream::connect(("127.0.0.1", extra)).is_err());
+    assert!(!common::get(server.port, "/").contains("X-Version"));
+}
+
+#[test]
+fn invalid_config_is_ignored() {
+    let server = common::start_with_config("reload-invalid", CONFIG);
+
+    rewrite(&server, r#"
+- server:
+    listen: {port}
+    header:
+      X-Version: "2"
+"#, 0);
+    assert!(common::get(server.port, "/").contains("X-Version: 1"));
+
+    // Nothing of a rejected configuration is opened
+    rewrite(&server, r#"
+- server:
+    listen: {port}
+    root: {root}
+    log:
+      success: {root}/rejected/access.log
+- server:
+    listen: {port}
+    host: other
+"#, 0);
+    assert!(common::get(server.port, "/").contains("X-Version: 1"));
+    assert!(!server.root.join("rejected").exists());
+
+    // A port that can't be bound rejects the whole reload
+    let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    rewrite(&server, r#"
+- server:
+    listen: {port}
+    root: {root}
+    header:
+      X-Version: "2"
+- server:
+    listen: {extra}
+    root: {root}
+"#, port);
+    assert!(common::get(server.port, "/").contains("X-Version: 1"));
+}
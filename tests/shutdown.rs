@@ -0,0 +1,99 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const CONFIG: &str = r#"
+- global:
+    shutdown_timeout: 5
+- server:
+    listen: {port}
+    root: {root}
+"#;
+
+#[test]
+fn sigterm_drains_requests_in_flight() {
+    let mut server = common::start_with_config("sigterm", CONFIG);
+
+    let mut stream = common::connect(server.port);
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: 5\r\n\r\nab").unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    common::signal(server.child.id(), libc::SIGTERM);
+    thread::sleep(Duration::from_millis(200));
+    assert!(TcpStream::connect(("127.0.0.1", server.port)).is_err());
+
+    // The request started before the signal still gets its response
+    stream.write_all(b"cde").unwrap();
+    let mut res = String::new();
+    stream.read_to_string(&mut res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 404"));
+
+    let status = common::wait_exit(&mut server, Duration::from_secs(5)).unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn sigterm_gives_up_after_shutdown_timeout() {
+    let config = CONFIG.replace("shutdown_timeout: 5", "shutdown_timeout: 0.5");
+    let mut server = common::start_with_config("sigterm-timeout", &config);
+
+    let mut stream = common::connect(server.port);
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: 5\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    common::signal(server.child.id(), libc::SIGTERM);
+    assert!(common::wait_exit(&mut server, Duration::from_secs(5)).is_some());
+}
+
+#[test]
+fn sigusr2_hands_over_listeners() {
+    let mut server = common::start_with_config("upgrade", CONFIG);
+    let port = server.port;
+    let config = server.root.join("config.yml");
+    let config = config.to_str().unwrap().to_string();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (ok, failed) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let client = {
+        let (stop, ok, failed) = (stop.clone(), ok.clone(), failed.clone());
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                if common::get(port, "/").starts_with("HTTP/1.1 404") {
+                    ok.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    failed.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        })
+    };
+
+    thread::sleep(Duration::from_millis(200));
+    let old = server.child.id() as i32;
+    common::signal(server.child.id(), libc::SIGUSR2);
+    let status = common::wait_exit(&mut server, Duration::from_secs(10)).unwrap();
+    assert!(status.success());
+
+    let served = ok.load(Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(300));
+    stop.store(true, Ordering::SeqCst);
+    client.join().unwrap();
+
+    let pids: Vec<i32> = common::pids_with_arg(&config)
+        .into_iter()
+        .filter(|pid| *pid != old)
+        .collect();
+    for pid in pids.iter() {
+        common::signal(*pid as u32, libc::SIGKILL);
+    }
+
+    assert_eq!(pids.len(), 1);
+    assert_eq!(failed.load(Ordering::SeqCst), 0);
+    assert!(ok.load(Ordering::SeqCst) > served);
+}
@@ -0,0 +1,47 @@
+
+mod common;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    health: /healthz
+    status:
+      path: /status
+      allow:
+        - {allow}
+"#;
+
+#[test]
+fn answers_health_checks() {
+    // The root is gone, the check must not look at it
+    let server = common::start_with_config("health", &CONFIG.replace("{allow}", "127.0.0.1"));
+    std::fs::remove_dir_all(&server.root).unwrap();
+    let res = common::get(server.port, "/healthz");
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.ends_with("\r\n\r\nok"));
+}
+
+#[test]
+fn shows_status() {
+    let server = common::start_with_config("status", &CONFIG.replace("{allow}", "127.0.0.0/8"));
+    common::get(server.port, "/missing");
+
+    let res = common::get(server.port, "/status");
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.contains("Content-Type: text/html"));
+    assert!(res.contains(&format!("<tr><td>{}</td><td>*</td></tr>", server.port)));
+    assert!(res.contains(&server.root.join("config.yml").display().to_string()));
+
+    let res = common::send(server.port, b"GET /status?format=json HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+    assert!(res.contains("Content-Type: application/json"));
+    assert!(res.contains(&format!("\"servers\":[{{\"listen\":{},\"hosts\":[\"*\"]}}]", server.port)));
+    assert!(res.contains("\"connections\":1"));
+    assert!(res.contains("\"5m\":{\"4xx\":1,\"5xx\":0"));
+}
+
+#[test]
+fn hides_status_from_others() {
+    let server = common::start_with_config("status-deny", &CONFIG.replace("{allow}", "10.0.0.0/8"));
+    assert!(common::get(server.port, "/status").starts_with("HTTP/1.1 403"));
+}
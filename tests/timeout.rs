@@ -0,0 +1,121 @@
+
+mod common;
+
+use std::fs;
+use std::io::prelude::*;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    timeout:
+      header: 0.5
+      body: 0.5
+      send: 0.5
+"#;
+
+fn read_all(stream: &mut std::net::TcpStream) -> String {
+    let mut res = vec![];
+    let _ = stream.read_to_end(&mut res);
+    String::from_utf8_lossy(&res).to_string()
+}
+
+#[test]
+fn idle_client_gets_408() {
+    let server = common::start_with_config("idle-408", CONFIG);
+    let start = Instant::now();
+    let mut stream = common::connect(server.port);
+    assert!(read_all(&mut stream).starts_with("HTTP/1.1 408"));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn slow_header_gets_408() {
+    let server = common::start_with_config("header-408", CONFIG);
+    let mut stream = common::connect(server.port);
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let _ = stream.write_all(b"Host: 127.0.0.1\r\n");
+    assert!(read_all(&mut stream).starts_with("HTTP/1.1 408"));
+}
+
+#[test]
+fn split_header_is_served() {
+    let server = common::start_with_config("header-split", CONFIG);
+    let mut stream = common::connect(server.port);
+    stream.write_all(b"GET / HTTP/1.1\r\nHo").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    stream.write_all(b"st: 127.0.0.1\r\n\r\n").unwrap();
+    assert!(read_all(&mut stream).starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn slow_header_holds_no_worker() {
+    let server = common::start_with_config("header-worker", &format!("
+- global:
+    worker_threads: 1{}", CONFIG));
+    let mut slow = common::connect(server.port);
+    slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    // Served while the only worker would still be waiting for the header above
+    assert!(common::get(server.port, "/").starts_with("HTTP/1.1 404"));
+    assert!(read_all(&mut slow).starts_with("HTTP/1.1 408"));
+}
+
+#[test]
+fn slow_body_gets_408() {
+    let server = common::start_with_config("body-408", CONFIG);
+    let mut stream = common::connect(server.port);
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: 10\r\n\r\nabc").unwrap();
+    assert!(read_all(&mut stream).starts_with("HTTP/1.1 408"));
+}
+
+#[test]
+fn stalled_reader_is_dropped() {
+    let server = common::start_with_config("send", CONFIG);
+    let size = 64 * 1024 * 1024;
+    fs::write(server.root.join("big.bin"), vec![0u8; size]).unwrap();
+
+    let mut stream = common::connect(server.port);
+    stream.write_all(b"GET /big.bin HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").unwrap();
+    // Stop reading long enough for the send timeout to fire
+    thread::sleep(Duration::from_secs(2));
+
+    let mut received = 0;
+    let mut buffer = [0; 65536];
+    while let Ok(n) = stream.read(&mut buffer) {
+        if n == 0 {
+            break;
+        }
+        received += n;
+    }
+    assert!(received > 0);
+    assert!(received < size);
+}
+
+#[test]
+fn request_deadline_cuts_trickling_client() {
+    let config = r#"
+- server:
+    listen: {port}
+    root: {root}
+    timeout:
+      request: 0.5
+"#;
+    let server = common::start_with_config("deadline", config);
+    let mut stream = common::connect(server.port);
+    let start = Instant::now();
+    // Each byte arrives well within the default header timeout
+    for byte in b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n".iter() {
+        if stream.write_all(&[*byte]).is_err() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+        if start.elapsed() > Duration::from_secs(1) {
+            break;
+        }
+    }
+    assert!(read_all(&mut stream).starts_with("HTTP/1.1 408"));
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use std::fs;
+use std::io::prelude::*;
+use std::process::Command;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    directory: true
+    auth:
+      user: admin
+      password: secret
+    upload:
+      path: /incoming
+      max_size: 1
+      extensions:
+        - txt
+        - tar.gz
+"#;
+
+// admin:secret
+const AUTH: &str = "Authorization: Basic YWRtaW46c2VjcmV0\r\n";
+
+fn request(port: u16, method: &str, path: &str, headers: &str, body: &[u8]) -> String {
+    let req = format!(
+        "{} {} HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: {}\r\n{}{}\r\n",
+        method, path, body.len(), AUTH, headers
+    );
+    common::send(port, &[req.as_bytes(), body].concat())
+}
+
+fn multipart(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = String::new();
+    for (name, data) in files {
+        body.push_str(&format!(
+            "--b0undary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n{}\r\n",
+            name, data
+        ));
+    }
+    body.push_str("--b0undary--\r\n");
+    body.into_bytes()
+}
+
+const FORM: &str = "Content-Type: multipart/form-data; boundary=b0undary\r\n";
+
+#[test]
+fn puts_files() {
+    let server = common::start_with_config("upload-put", CONFIG);
+
+    let res = request(server.port, "PUT", "/incoming/a/b.txt", "", b"one");
+    assert!(res.starts_with("HTTP/1.1 201"));
+    assert!(res.contains("Location: /incoming/a/b.txt"));
+    assert_eq!(fs::read(server.root.join("incoming/a/b.txt")).unwrap(), b"one");
+
+    // Not replaced without `overwrite`
+    assert!(request(server.port, "PUT", "/incoming/a/b.txt", "", b"two").starts_with("HTTP/1.1 409"));
+    assert_eq!(fs::read(server.root.join("incoming/a/b.txt")).unwrap(), b"one");
+
+    assert!(request(server.port, "PUT", "/incoming/c.tar.gz", "", b"x").starts_with("HTTP/1.1 201"));
+    assert!(request(server.port, "PUT", "/incoming/run.sh", "", b"x").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "PUT", "/other.txt", "", b"x").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "PUT", "/incoming/../other.txt", "", b"x").starts_with("HTTP/1.1 403"));
+    // Refused before the body is sent
+    let big = format!("PUT /incoming/big.txt HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: 2097152\r\n{}\r\n", AUTH);
+    assert!(common::send(server.port, big.as_bytes()).starts_with("HTTP/1.1 413"));
+    assert!(!server.root.join("other.txt").exists());
+}
+
+#[test]
+fn stays_in_the_root() {
+    let server = common::start_with_config("upload-symlink", CONFIG);
+    let outside = common::temp_root("upload-outside");
+    fs::create_dir(server.root.join("incoming")).unwrap();
+    std::os::unix::fs::symlink(&outside, server.root.join("incoming/out")).unwrap();
+
+    assert!(request(server.port, "PUT", "/incoming/out/a.txt", "", b"x").starts_with("HTTP/1.1 403"));
+    let res = request(server.port, "POST", "/incoming/out/", FORM, &multipart(&[("a.txt", "a")]));
+    assert!(res.starts_with("HTTP/1.1 403"));
+    assert!(!outside.join("a.txt").exists());
+    fs::remove_dir_all(&outside).unwrap();
+}
+
+#[test]
+fn replaces_with_overwrite() {
+    let config = CONFIG.replace("max_size: 1", "max_size: 1\n      overwrite: true");
+    let server = common::start_with_config("upload-overwrite", &config);
+
+    assert!(request(server.port, "PUT", "/incoming/a.txt", "", b"one").starts_with("HTTP/1.1 201"));
+    let res = request(server.port, "PUT", "/incoming/a.txt", "", b"two");
+    assert!(res.starts_with("HTTP/1.1 204"));
+    assert!(!res.contains("Content-Length"));
+    assert_eq!(fs::read(server.root.join("incoming/a.txt")).unwrap(), b"two");
+}
+
+#[test]
+fn waits_with_100_continue() {
+    let server = common::start_with_config("upload-continue", CONFIG);
+
+    let head = |path: &str, auth: &str| format!(
+        "PUT {} HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: 3\r\nExpect: 100-continue\r\n{}\r\n",
+        path, auth
+    );
+
+    let mut stream = common::connect(server.port);
+    stream.write_all(head("/incoming/a.txt", AUTH).as_bytes()).unwrap();
+    let mut interim = [0; 25];
+    stream.read_exact(&mut interim).unwrap();
+    assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(b"abc").unwrap();
+    let mut res = String::new();
+    let _ = stream.read_to_string(&mut res);
+    assert!(res.starts_with("HTTP/1.1 201"));
+
+    // Refused without waiting for the body
+    for (path, auth, status) in [
+        ("/incoming/b.txt", "", "HTTP/1.1 401"),
+        ("/other/b.txt", AUTH, "HTTP/1.1 403"),
+        ("/incoming/b.exe", AUTH, "HTTP/1.1 403")
+    ] {
+        let mut stream = common::connect(server.port);
+        stream.write_all(head(path, auth).as_bytes()).unwrap();
+        let mut res = String::new();
+        let _ = stream.read_to_string(&mut res);
+        assert!(res.starts_with(status), "{} {}", path, res);
+    }
+}
+
+#[test]
+fn posts_forms() {
+    let server = common::start_with_config("upload-post", CONFIG);
+    fs::create_dir(server.root.join("incoming")).unwrap();
+
+    let res = request(server.port, "POST", "/incoming/", FORM, &multipart(&[("a.txt", "a"), ("C:\\docs\\b.txt", "b")]));
+    assert!(res.starts_with("HTTP/1.1 201"));
+    assert!(res.ends_with("a.txt\nb.txt\n"));
+    assert_eq!(fs::read(server.root.join("incoming/b.txt")).unwrap(), b"b");
+
+    // The form goes back to the listing
+    let headers = format!("{}Accept: text/html\r\n", FORM);
+    let res = request(server.port, "POST", "/incoming/", &headers, &multipart(&[("c.txt", "c")]));
+    assert!(res.starts_with("HTTP/1.1 303"));
+    assert!(res.contains("Location: /incoming/"));
+
+    // Nothing is written when one of the files is refused
+    let res = request(server.port, "POST", "/incoming/", FORM, &multipart(&[("d.txt", "d"), ("e.exe", "e")]));
+    assert!(res.starts_with("HTTP/1.1 403"));
+    assert!(!server.root.join("incoming/d.txt").exists());
+    assert!(request(server.port, "POST", "/incoming/", FORM, &multipart(&[("a.txt", "again")])).starts_with("HTTP/1.1 409"));
+    assert!(request(server.port, "POST", "/incoming/", FORM, b"--b0undary--\r\n").starts_with("HTTP/1.1 400"));
+    assert!(request(server.port, "POST", "/incoming/none/", FORM, &multipart(&[("a.txt", "a")])).starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn lists_with_a_form() {
+    let server = common::start_with_config("upload-form", CONFIG);
+    fs::create_dir(server.root.join("incoming")).unwrap();
+
+    assert!(request(server.port, "GET", "/incoming/", "", b"").contains("<form class=\"upload\" method=\"post\""));
+    assert!(!request(server.port, "GET", "/", "", b"").contains("<form class=\"upload\""));
+}
+
+#[test]
+fn needs_auth() {
+    let root = common::temp_root("upload-auth");
+    let path = root.join("config.yml");
+    let config = format!("- server:\n    listen: {}\n    root: {}\n    upload: true\n", common::free_port(), root.display());
+    fs::write(&path, config).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_see"))
+        .args(["-c", path.to_str().unwrap()])
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'upload' needs 'auth'"));
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,237 @@
+mod common;
+
+use std::fs;
+
+const CONFIG: &str = r#"
+- server:
+    listen: {port}
+    root: {root}
+    auth:
+      user: admin
+      password: secret
+    webdav:
+      path: /dav
+    location:
+      /dav/private:
+        access:
+          - deny: all
+"#;
+
+// admin:secret
+const AUTH: &str = "Authorization: Basic YWRtaW46c2VjcmV0\r\n";
+
+fn request(port: u16, method: &str, path: &str, headers: &str, body: &str) -> String {
+    let req = format!(
+        "{} {} HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: {}\r\n{}{}\r\n{}",
+        method, path, body.len(), AUTH, headers, body
+    );
+    common::send(port, req.as_bytes())
+}
+
+// The opaquelocktoken of a LOCK response
+fn token(res: &str) -> String {
+    let start = res.find("Lock-Token: <").unwrap() + 13;
+    res[start..start + res[start..].find('>').unwrap()].to_string()
+}
+
+#[test]
+fn advertises_dav() {
+    let server = common::start_with_config("webdav-options", CONFIG);
+    fs::create_dir(server.root.join("dav")).unwrap();
+
+    let res = request(server.port, "OPTIONS", "/dav/", "", "");
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.contains("DAV: 1, 2"));
+    assert!(res.contains("PROPFIND"));
+    // Only below its paths
+    assert!(request(server.port, "PROPFIND", "/", "", "").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "MKCOL", "/other", "", "").starts_with("HTTP/1.1 403"));
+}
+
+#[test]
+fn finds_properties() {
+    let server = common::start_with_config("webdav-propfind", CONFIG);
+    fs::create_dir_all(server.root.join("dav/sub dir/deeper")).unwrap();
+    fs::create_dir(server.root.join("dav/private")).unwrap();
+    fs::write(server.root.join("dav/a.txt"), "hello").unwrap();
+    fs::write(server.root.join("dav/sub dir/b&c.txt"), "b").unwrap();
+
+    let res = request(server.port, "PROPFIND", "/dav", "Depth: 1\r\n", "");
+    assert!(res.starts_with("HTTP/1.1 207"));
+    assert!(res.contains("Content-Type: application/xml; charset=utf-8"));
+    assert!(res.contains("<D:href>/dav/</D:href>"));
+    assert!(res.contains("<D:href>/dav/a.txt</D:href>"));
+    assert!(res.contains("<D:getcontentlength>5</D:getcontentlength>"));
+    assert!(res.contains("<D:getcontenttype>text/plain</D:getcontenttype>"));
+    assert!(res.contains("<D:href>/dav/sub%20dir/</D:href>"));
+    assert!(res.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+    assert!(!res.contains("deeper"));
+    assert!(!res.contains("private"));
+
+    let res = request(server.port, "PROPFIND", "/dav/", "", "");
+    assert!(res.contains("<D:href>/dav/sub%20dir/b&amp;c.txt</D:href>"));
+    assert!(res.contains("<D:href>/dav/sub%20dir/deeper/</D:href>"));
+
+    let body = "<?xml version=\"1.0\"?><propfind xmlns=\"DAV:\"><prop><getetag/><x xmlns=\"urn:x\"/></prop></propfind>";
+    let res = request(server.port, "PROPFIND", "/dav/a.txt", "Depth: 0\r\n", body);
+    assert!(res.contains("<D:getetag>\""));
+    assert!(res.contains("<D:prop><ns0:x xmlns:ns0=\"urn:x\"/></D:prop><D:status>HTTP/1.1 404 Not Found</D:status>"));
+
+    assert!(request(server.port, "PROPFIND", "/dav/none", "", "").starts_with("HTTP/1.1 404"));
+    assert!(request(server.port, "PROPFIND", "/dav/", "Depth: 2\r\n", "").starts_with("HTTP/1.1 400"));
+    assert!(request(server.port, "PROPFIND", "/dav/", "", "<propfind").starts_with("HTTP/1.1 400"));
+}
+
+#[test]
+fn keeps_dead_properties() {
+    let config = CONFIG.replace("    webdav:", "    directory: true\n    webdav:");
+    let server = common::start_with_config("webdav-proppatch", &config);
+    fs::create_dir(server.root.join("dav")).unwrap();
+    fs::write(server.root.join("dav/a.txt"), "a").unwrap();
+
+    let body = concat!(
+        "<D:propertyupdate xmlns:D=\"DAV:\" xmlns:Z=\"urn:z\">",
+        "<D:set><D:prop><Z:Win32FileAttributes>00000020</Z:Win32FileAttributes></D:prop></D:set>",
+        "</D:propertyupdate>"
+    );
+    let res = request(server.port, "PROPPATCH", "/dav/a.txt", "", body);
+    assert!(res.starts_with("HTTP/1.1 207"));
+    assert!(res.contains("HTTP/1.1 200 OK"));
+
+    let res = request(server.port, "PROPFIND", "/dav/", "Depth: 1\r\n", "");
+    assert!(res.contains(">00000020</ns0:Win32FileAttributes>"));
+    // The sidecar isn't a resource of its own
+    assert!(!res.contains(".props"));
+    assert!(request(server.port, "PUT", "/dav/.a.txt.props", "", "x").starts_with("HTTP/1.1 403"));
+    assert!(request(server.port, "GET", "/dav/.a.txt.props", "", "").starts_with("HTTP/1.1 404"));
+    let res = request(server.port, "GET", "/dav/", "", "");
+    assert!(res.starts_with("HTTP/1.1 200"));
+    assert!(res.contains("a.txt") && !res.contains(".props"));
+
+    // They follow the file
+    let res = request(server.port, "MOVE", "/dav/a.txt", "Destination: /dav/b.txt\r\n", "");
+    assert!(res.starts_with("HTTP/1.1 201"));
+    let res = request(server.port, "PROPFIND", "/dav/b.txt", "Depth: 0\r\n", "");
+    assert!(res.contains(">00000020</ns0:Win32FileAttributes>"));
+
+    let body = "<propertyupdate xmlns=\"DAV:\"><set><prop><getcontentlength>9</getcontentlength></prop></set></propertyupdate>";
+    let res = request(server.port, "PROPPATCH", "/dav/b.txt", "", body);
+    assert!(res.contains("403 Forbidden"));
+}
+
+#[test]
+fn makes_copies_and_moves() {
+    let server = common::start_with_config("webdav-copy", CONFIG);
+    fs::create_dir(server.root.join("dav")).unwrap();
+
+    assert!(request(server.port, "MKCOL", "/dav/a", "", "").starts_with("HTTP/1.1 201"));
+    assert!(request(server.port, "MKCOL", "/dav/a", "", "").starts_with("HTTP/1.1 405"));
+    assert!(request(server.port, "MKCOL", "/dav/x/y", "", "").starts_with("HTTP/1.1 409"));
+    assert!(request(server.port, "MKCOL", "/dav/b", "", "<x/>").starts_with("HTTP/1.1 415"));
+
+    assert!(request(server.port, "PUT", "/dav/a/f.txt", "", "one").starts_with("HTTP/1.1 201"));
+    assert!(request(server.port, "PUT", "/dav/a/f.txt", "", "two").starts_with("HTTP/1.1 204"));
+    assert!(request(server.port, "PUT", "/dav/x/f.txt", "", "two").starts_with("HTTP/1.1 409"));
+
+    let res = request(server.port, "COPY", "/dav/a", "Destination: http://127.0.0.1/dav/c\r\n", "");
+    assert!(res.starts_with("HTTP/1.1 201"));
+    assert_eq!(fs::read(server.root.join("dav/c/f.txt")).unwrap(), b"two");
+    assert!(request(server.port, "COPY", "/dav/a", "Destination: /dav/c\r\nOverwrite: F\r\n", "").starts_with("HTTP/1.1 412"));
+    assert!(request(server.port, "COPY", "/dav/a", "Destination: /dav/a/d\r\n", "").starts_with("HTTP/1.1 409"));
+    assert!(request(server.port, "COPY", "/dav/a", "Destination: /elsewhere\r\n", "").starts_with("HTTP/1.1 403"));
+
+    // Depth 0 leaves the members behind
+    assert!(request(server.port, "COPY", "/dav/a", "Destination: /dav/c\r\nDepth: 0\r\n", "").starts_with("HTTP/1.1 204"));
+    assert_eq!(fs::read_dir(server.root.join("dav/c")).unwrap().count(), 0);
+
+    assert!(request(server.port, "MOVE", "/dav/a", "Destination: /dav/c\r\n", "").starts_with("HTTP/1.1 204"));
+    assert!(!server.root.join("dav/a").exists());
+    assert!(server.root.join("dav/c/f.txt").exists());
+
+    assert!(request(server.port, "DELETE", "/dav/c", "", "").starts_with("HTTP/1.1 204"));
+    assert!(!server.root.join("dav/c").exists());
+    assert!(request(server.port, "DELETE", "/dav/c", "", "").starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn locks_resources() {
+    let server = common::start_with_config("webdav-lock", CONFIG);
+    fs::create_dir(server.root.join("dav")).unwrap();
+
+    let body = concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><D:lockinfo xmlns:D=\"DAV:\">",
+        "<D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype>",
+        "<D:owner><D:href>mailto:me@example.com</D:href></D:owner></D:lockinfo>"
+    );
+    // A free name becomes an empty file
+    let res = request(server.port, "LOCK", "/dav/a.txt", "Timeout: Second-60\r\n", body);
+    assert!(res.starts_with("HTTP/1.1 201"));
+    assert!(res.contains("<D:timeout>Second-60</D:timeout>"));
+    assert!(res.contains("mailto:me@example.com"));
+    let token = token(&res);
+    assert!(server.root.join("dav/a.txt").exists());
+
+    assert!(request(server.port, "LOCK", "/dav/a.txt", "", body).starts_with("HTTP/1.1 423"));
+    assert!(request(server.port, "PUT", "/dav/a.txt", "", "x").starts_with("HTTP/1.1 423"));
+    assert!(request(server.port, "DELETE", "/dav/a.txt", "", "").starts_with("HTTP/1.1 423"));
+    assert!(request(server.port, "MOVE", "/dav/a.txt", "Destination: /dav/b.txt\r\n", "").starts_with("HTTP/1.1 423"));
+
+    let header = format!("If: (<{}>)\r\n", token);
+    assert!(request(server.port, "PUT", "/dav/a.txt", &header, "x").starts_with("HTTP/1.1 204"));
+    assert!(request(server.port, "LOCK", "/dav/a.txt", &header, "").starts_with("HTTP/1.1 200"));
+    let res = request(server.port, "PROPFIND", "/dav/a.txt", "Depth: 0\r\n", "");
+    assert!(res.contains(&format!("<D:locktoken><D:href>{}</D:href></D:locktoken>", token)));
+
+    assert!(request(server.port, "UNLOCK", "/dav/a.txt", "Lock-Token: <opaquelocktoken:none>\r\n", "").starts_with("HTTP/1.1 409"));
+    let header = format!("Lock-Token: <{}>\r\n", token);
+    assert!(request(server.port, "UNLOCK", "/dav/a.txt", &header, "").starts_with("HTTP/1.1 204"));
+    assert!(request(server.port, "DELETE", "/dav/a.txt", "", "").starts_with("HTTP/1.1 204"));
+}
+
+// The requests the Windows WebClient sends to save a new file on a mapped drive,
+// with its default namespaces and tagged If header
+#[test]
+fn saves_like_a_mounted_drive() {
+    let server = common::start_with_config("webdav-client", CONFIG);
+    fs::create_dir(server.root.join("dav")).unwrap();
+
+    let res = request(server.port, "OPTIONS", "/dav/", "", "");
+    assert!(res.contains("DAV: 1, 2"));
+    let body = concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\" ?><propfind xmlns=\"DAV:\"><prop>",
+        "<getcontentlength xmlns=\"DAV:\"/><getlastmodified xmlns=\"DAV:\"/><resourcetype xmlns=\"DAV:\"/>",
+        "</prop></propfind>"
+    );
+    assert!(request(server.port, "PROPFIND", "/dav/doc.txt", "Depth: 0\r\n", body).starts_with("HTTP/1.1 404"));
+    assert!(request(server.port, "PUT", "/dav/doc.txt", "", "").starts_with("HTTP/1.1 201"));
+
+    let body = concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\" ?><D:lockinfo xmlns:D=\"DAV:\">",
+        "<D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype>",
+        "<D:owner><D:href>DESKTOP\\admin</D:href></D:owner></D:lockinfo>"
+    );
+    let res = request(server.port, "LOCK", "/dav/doc.txt", "Depth: 0\r\nTimeout: Second-3600\r\n", body);
+    assert!(res.starts_with("HTTP/1.1 200"));
+    let token = token(&res);
+
+    let header = format!("If: <http://127.0.0.1/dav/doc.txt> (<{}>)\r\n", token);
+    assert!(request(server.port, "PUT", "/dav/doc.txt", &header, "hello").starts_with("HTTP/1.1 204"));
+    let body = concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\" ?>",
+        "<D:propertyupdate xmlns:D=\"DAV:\" xmlns:Z=\"urn:schemas-microsoft-com:\"><D:set><D:prop>",
+        "<Z:Win32LastModifiedTime>Mon, 19 Oct 2026 08:00:00 GMT</Z:Win32LastModifiedTime>",
+        "<Z:Win32FileAttributes>00000020</Z:Win32FileAttributes>",
+        "</D:prop></D:set></D:propertyupdate>"
+    );
+    let res = request(server.port, "PROPPATCH", "/dav/doc.txt", &header, body);
+    assert!(res.starts_with("HTTP/1.1 207"));
+    assert!(res.contains("200 OK"));
+    let header = format!("Lock-Token: <{}>\r\n", token);
+    assert!(request(server.port, "UNLOCK", "/dav/doc.txt", &header, "").starts_with("HTTP/1.1 204"));
+
+    let body = "<?xml version=\"1.0\" encoding=\"utf-8\" ?><propfind xmlns=\"DAV:\"><allprop/></propfind>";
+    let res = request(server.port, "PROPFIND", "/dav/doc.txt", "Depth: 0\r\n", body);
+    assert!(res.contains("<D:getcontentlength>5</D:getcontentlength>"));
+    assert!(res.contains("00000020"));
+    assert_eq!(fs::read(server.root.join("dav/doc.txt")).unwrap(), b"hello");
+}